@@ -1,4 +1,5 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use fyrox_sound::algebra::Vector3;
 use fyrox_sound::buffer::{DataSource, SoundBufferResource};
 use fyrox_sound::context::SoundContext;
@@ -33,7 +34,14 @@ impl AudioBackend {
         // get full path
         let full_path = format!("base/snd/{}", name);
         // load sound
-        let sound = SoundBufferResource::new_generic(block_on(DataSource::from_file(&full_path)).unwrap()).expect("failed to load sound");
+        let data = match block_on(DataSource::from_file(&full_path)) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("failed to load sound {}: {:?}", full_path, e);
+                return;
+            }
+        };
+        let sound = SoundBufferResource::new_generic(data).expect("failed to load sound");
         // insert into hashmap
         sounds.insert(name.to_string(), sound);
     }
@@ -48,16 +56,20 @@ impl AudioBackend {
         playing_sounds.contains_key(uuid)
     }
 
-    pub fn play_sound_with_uuid(&self, uuid: &str, name: &str, context: &SoundContext) {
+    /// starts `offset` seconds into the sound
+    pub fn play_sound_with_uuid(&self, uuid: &str, name: &str, offset: f64, looping: bool, context: &SoundContext) {
         let sounds = self.sounds.lock().unwrap();
         let sound = sounds.get(name).unwrap();
         let mut playing_sounds = self.playing_sounds.lock().unwrap();
         let source = SoundSourceBuilder::new()
             .with_buffer(sound.clone())
-            .with_looping(true)
+            .with_looping(looping)
             .with_status(Playing)
             .build().expect("failed to build sound source");
         let handle = context.state().add_source(source);
+        if offset > 0.0 {
+            context.state().source_mut(handle).set_playback_time(Duration::from_secs_f64(offset));
+        }
         playing_sounds.insert(uuid.to_string(), handle);
     }
 
@@ -91,6 +103,12 @@ impl AudioBackend {
         context.state().source_mut(*handle).set_position(Vector3::new(position.x, position.y, position.z));
     }
 
+    pub fn set_sound_gain(&self, uuid: &str, gain: f32, context: &SoundContext) {
+        let playing_sounds = self.playing_sounds.lock().unwrap();
+        let handle = playing_sounds.get(uuid).unwrap();
+        context.state().source_mut(*handle).set_gain(gain);
+    }
+
     pub fn update(&self, position: Vec3, forward: Vec3, up: Vec3, context: &SoundContext) {
         context.state().listener_mut().set_position(Vector3::new(position.x, position.y, position.z));
         context.state().listener_mut().set_orientation_rh(Vector3::new(forward.x, forward.y, forward.z), Vector3::new(up.x, up.y, up.z));
@@ -113,9 +131,14 @@ impl AudioBackend {
         drop(oneshots);
 
         let mut oneshots = ONESHOTS.lock().unwrap();
-        // play oneshots
+        // play oneshots, loading any that haven't been heard yet (footsteps come from materials.yaml, so could be anything)
         for oneshot in oneshots.iter() {
-            self.play_oneshot_with_uuid(&oneshot.0, &oneshot.0, context, oneshot.1);
+            if !self.is_sound_loaded(&oneshot.0) {
+                self.load_sound(&oneshot.0);
+            }
+            if self.is_sound_loaded(&oneshot.0) {
+                self.play_oneshot_with_uuid(&oneshot.0, &oneshot.0, context, oneshot.1);
+            }
         }
         oneshots.clear();
     }
@@ -72,6 +72,13 @@ pub fn distance(a: Vec3, b: Vec3) -> f32 {
     (x * x + y * y + z * z).abs().sqrt()
 }
 
+// ignoring height
+pub fn flat_distance(a: Vec3, b: Vec3) -> f32 {
+    let x = a.x - b.x;
+    let z = a.z - b.z;
+    (x * x + z * z).abs().sqrt()
+}
+
 pub fn distance2d(a: Vec2, b: Vec2) -> f32 {
     let x = a.x - b.x;
     let y = a.y - b.y;
@@ -231,4 +238,38 @@ pub fn to_q64(quat: Quaternion) -> (f64,f64,f64,f64) {
 
 pub fn from_q64(quat: (f64,f64,f64,f64)) -> Quaternion {
     Quaternion::new(quat.0 as f32, quat.1 as f32, quat.2 as f32, quat.3 as f32)
-}
\ No newline at end of file
+}
+/// the vertices and triangle indices of base/models/<mesh>.glb, without needing a renderer (the server doesn't have one)
+/// every primitive of the mesh is merged into one list
+pub fn load_gltf_mesh(game_data_path: &str, mesh_name: &str) -> Option<(Vec<Vec3>, Vec<u32>)> {
+    let path = format!("{}/models/{}.glb", game_data_path, mesh_name);
+    let (document, buffers, _) = match gltf::import(&path) {
+        Ok(imported) => imported,
+        Err(e) => {
+            warn!("failed to load {}: {:?}", path, e);
+            return None;
+        }
+    };
+    let mesh = document.meshes().find(|m| m.name() == Some(mesh_name))?;
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for primitive in mesh.primitives() {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions = match reader.read_positions() {
+            Some(positions) => positions.map(|p| Vec3::new(p[0], p[1], p[2])).collect::<Vec<Vec3>>(),
+            None => continue,
+        };
+        let offset = vertices.len() as u32;
+        let primitive_indices = match reader.read_indices() {
+            Some(primitive_indices) => primitive_indices.into_u32().collect::<Vec<u32>>(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        for triangle in primitive_indices.chunks_exact(3) {
+            if triangle.iter().all(|index| (*index as usize) < positions.len()) {
+                indices.extend(triangle.iter().map(|index| index + offset));
+            }
+        }
+        vertices.extend(positions);
+    }
+    Some((vertices, indices))
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+// editors tend to save in a few steps (truncate, write, rename), so wait for things to settle before reloading
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReloadEvent {
+    Map(String),
+    Script(String), // map name
+    Movement(String), // map name
+    EntityDef(String),
+    Texture(String),
+    Mesh(String),
+}
+
+/// watches the game data folder for changes, only created when running with --hot-reload
+pub struct HotReloader {
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<Event>>,
+    base: PathBuf,
+    pending: Vec<(ReloadEvent, Instant)>,
+}
+
+impl HotReloader {
+    pub fn new(game_data_path: &str) -> Option<Self> {
+        let base = match std::fs::canonicalize(game_data_path) {
+            Ok(base) => base,
+            Err(e) => {
+                error!("hot reload: can't find {}: {:?}", game_data_path, e);
+                return None;
+            }
+        };
+        let (sender, receiver) = channel();
+        let mut watcher = match notify::recommended_watcher(sender) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("hot reload: failed to create file watcher: {:?}", e);
+                return None;
+            }
+        };
+        if let Err(e) = watcher.watch(&base, RecursiveMode::Recursive) {
+            error!("hot reload: failed to watch {}: {:?}", base.display(), e);
+            return None;
+        }
+        info!("hot reload: watching {}", base.display());
+        Some(Self {
+            _watcher: watcher,
+            receiver,
+            base,
+            pending: Vec::new(),
+        })
+    }
+
+    /// things that changed and have been left alone for a bit, each reported once
+    pub fn poll(&mut self) -> Vec<ReloadEvent> {
+        while let Ok(event) = self.receiver.try_recv() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("hot reload: watcher error: {:?}", e);
+                    continue;
+                }
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if let Some(reload) = classify(&self.base, &path) {
+                    self.pending.retain(|(pending, _)| *pending != reload);
+                    self.pending.push((reload, Instant::now()));
+                }
+            }
+        }
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending).into_iter().partition(|(_, seen)| seen.elapsed() >= SETTLE_TIME);
+        self.pending = waiting;
+        ready.into_iter().map(|(reload, _)| reload).collect()
+    }
+}
+
+// works out what a changed file is from where it lives, e.g. maps/<name>/worlddef or textures/<name>/<name>_diffuse.png
+fn classify(base: &Path, path: &Path) -> Option<ReloadEvent> {
+    let relative = path.strip_prefix(base).ok()?;
+    let parts = relative.iter().map(|part| part.to_string_lossy().to_string()).collect::<Vec<String>>();
+    let stem = || relative.file_stem().map(|stem| stem.to_string_lossy().to_string());
+    let extension = relative.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default();
+    match parts.iter().map(|part| part.as_str()).collect::<Vec<&str>>().as_slice() {
+        ["maps", map, "worlddef"] => Some(ReloadEvent::Map(map.to_string())),
+        ["maps", map, "script.rhai"] => Some(ReloadEvent::Script(map.to_string())),
+        ["maps", map, "movement.yaml"] => Some(ReloadEvent::Movement(map.to_string())),
+        ["entities", _] if extension == "edef" => stem().map(ReloadEvent::EntityDef),
+        ["textures", texture, _] => Some(ReloadEvent::Texture(texture.to_string())),
+        // shadow meshes are loaded alongside their mesh, so reload that
+        ["models", _] if extension == "glb" => stem().map(|name| ReloadEvent::Mesh(name.trim_end_matches(".glb-shadow").to_string())),
+        _ => None,
+    }
+}
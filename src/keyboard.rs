@@ -8,6 +8,7 @@ pub enum HTKey {
     A,
     S,
     D,
+    E,
     Space,
     LeftShift,
     LeftControl,
@@ -99,6 +100,7 @@ impl Default for Keyboard {
         key_state.insert(HTKey::A, KeyState::TakenCareOf);
         key_state.insert(HTKey::S, KeyState::TakenCareOf);
         key_state.insert(HTKey::D, KeyState::TakenCareOf);
+        key_state.insert(HTKey::E, KeyState::TakenCareOf);
         key_state.insert(HTKey::Space, KeyState::TakenCareOf);
         key_state.insert(HTKey::LeftShift, KeyState::TakenCareOf);
         key_state.insert(HTKey::LeftControl, KeyState::TakenCareOf);
@@ -183,6 +185,7 @@ pub fn glfw_key_to_key(key: glfw::Key) -> HTKey {
         glfw::Key::A => HTKey::A,
         glfw::Key::S => HTKey::S,
         glfw::Key::D => HTKey::D,
+        glfw::Key::E => HTKey::E,
         glfw::Key::Space => HTKey::Space,
         glfw::Key::LeftShift => HTKey::LeftShift,
         glfw::Key::LeftControl => HTKey::LeftControl,
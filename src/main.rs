@@ -38,6 +38,7 @@ pub trait Thingy {
 pub mod sunlust_intro;
 pub mod renderer;
 pub mod helpers;
+pub mod hotreload;
 pub mod animation;
 pub mod shaders;
 pub mod camera;
@@ -73,6 +74,10 @@ async fn main() {
     let mut level_to_load = Option::None;
     let mut run_as_lan_server = false;
     let mut connect_to_lan_server = Option::None;
+    let mut hot_reload = false;
+    let mut record_demo = Option::None;
+    let mut play_demo = Option::None;
+    let mut network_conditions = server::lan::NetworkConditions::default();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -86,9 +91,26 @@ async fn main() {
             "--connect-to-lan-server" => {
                 connect_to_lan_server = Option::Some(args.next().expect("expected ip after --connect-to-lan-server"));
             }
+            "--hot-reload" => {
+                hot_reload = true;
+            }
+            "--record-demo" => {
+                record_demo = Option::Some(args.next().expect("expected file name after --record-demo"));
+            }
+            "--play-demo" => {
+                play_demo = Option::Some(args.next().expect("expected file name after --play-demo"));
+            }
+            // --net-latency <ms>, --net-loss <0 to 1>, etc, see NetworkConditions::SETTINGS
+            setting if setting.starts_with("--net-") => {
+                let value = args.next().unwrap_or_else(|| panic!("expected value after {}", setting));
+                if network_conditions.set(&setting["--net-".len()..], &value).is_none() {
+                    panic!("bad network condition {} {}", setting, value);
+                }
+            }
             _ => {}
         }
     }
+    *server::lan::DEFAULT_NETWORK_CONDITIONS.lock().unwrap() = network_conditions;
 
     let start_time = Instant::now();
 
@@ -98,7 +120,10 @@ async fn main() {
         let physics = physics::PhysicsSystem::init();
         info!("initialised physics");
 
-        let server = server::Server::new_host_lan_server(&level_to_load.unwrap_or("lava".to_string()), physics, 25566, 25567, "0.0.0.0").await;
+        let mut server = server::Server::new_host_lan_server(&level_to_load.unwrap_or("lava".to_string()), physics, 25566, 25567, "0.0.0.0").await;
+        if let Some(path) = &record_demo {
+            server.record_demo(path).await;
+        }
         let server_clone_a = server.clone();
         let server_clone_b = server.clone();
         let mut server_clone_c = server.clone();
@@ -109,6 +134,16 @@ async fn main() {
         tokio::spawn(async move {
             server_clone_b.player_and_physics_tick_thread().await;
         });
+        if hot_reload {
+            let server_clone_d = server.clone();
+            tokio::spawn(async move {
+                server_clone_d.hot_reload_thread().await;
+            });
+        }
+        let server_clone_e = server.clone();
+        tokio::spawn(async move {
+            server_clone_e.console_thread().await;
+        });
         server_clone_c.run().await;
     } else {
         info!("good day! initialising huskyTech2");
@@ -140,7 +175,13 @@ async fn main() {
 
         info!("initialised worldmachine");
 
-        if let Some(ip) = connect_to_lan_server {
+        let mut demo_camera = None;
+        if let Some(path) = play_demo {
+            let demo = server::demo::DemoPlayer::load(&path).expect("failed to load demo");
+            worldmachine.connect_to_server(ConnectionClientside::Demo(Arc::new(mutex_timeouts::tokio::MutexWithTimeoutAuto::new(demo))));
+            demo_camera = Some(server::demo::DemoCamera::default());
+            renderer.lock_mouse(true);
+        } else if let Some(ip) = connect_to_lan_server {
             let (server_connection, tcpstream, tcpreceiver) = ClientLanConnection::connect(ip.as_str(), 25566, 25567).await.expect("failed to connect to server");
             worldmachine.connect_to_server(ConnectionClientside::Lan(server_connection.clone()));
             let the_clone = server_connection.clone();
@@ -153,6 +194,9 @@ async fn main() {
             });
         } else {
             let mut server = server::Server::new(&level_to_load.unwrap_or("lava".to_string()), physics.clone());
+            if let Some(path) = &record_demo {
+                server.record_demo(path).await;
+            }
             let server_clone_a = server.clone();
             let server_clone_b = server.clone();
             let mut server_clone_c = server.clone();
@@ -165,6 +209,12 @@ async fn main() {
             tokio::spawn(async move {
                 server_clone_c.run().await;
             });
+            if hot_reload {
+                let server_clone_d = server.clone();
+                tokio::spawn(async move {
+                    server_clone_d.hot_reload_thread().await;
+                });
+            }
             let server_connection = server.join_local_server().await;
             worldmachine.connect_to_server(ConnectionClientside::Local(server_connection));
         }
@@ -198,6 +248,9 @@ async fn main() {
 
         chat::write_chat("engine".to_string(), "welcome to the huskyTech2 demo! press the comma key to unlock your mouse and send messages, or the period key to lock your mouse again (:".to_string());
 
+        // the server side of hot reloading (maps, scripts, entity definitions) runs with the server, this one handles assets
+        let mut hot_reloader = if hot_reload { hotreload::HotReloader::new("base") } else { None };
+
         let mut last_frame_time = Instant::now();
         let mut compensation_delta = 0.0;
         loop {
@@ -210,9 +263,23 @@ async fn main() {
 
             renderer.backend.input_state.lock().unwrap().input.time = Some(start_time.elapsed().as_secs_f64());
             renderer.backend.egui_context.lock().unwrap().begin_frame(renderer.backend.input_state.lock().unwrap().input.take());
+            if let Some(hot_reloader) = hot_reloader.as_mut() {
+                for event in hot_reloader.poll() {
+                    match event {
+                        hotreload::ReloadEvent::Texture(name) => renderer.reload_texture(&name),
+                        hotreload::ReloadEvent::Mesh(name) => renderer.reload_mesh(&name),
+                        _ => {}
+                    }
+                }
+                renderer.finish_reloads();
+            }
             worldmachine.next_frame(&mut renderer);
             let mut updates = worldmachine.client_tick(&mut renderer, physics.clone(), delta).await; // physics ticks are also simulated here clientside
             worldmachine.tick_connection(&mut updates).await;
+            if let Some(demo_camera) = demo_camera.as_mut() {
+                demo_camera.handle_input(&mut renderer, delta);
+                worldmachine.tick_demo(delta).await;
+            }
 
             // simulate a physics tick
             if let Some(delta) = physics.tick(delta + compensation_delta) {
@@ -245,6 +312,9 @@ async fn main() {
             if renderer.manage_window() || keyboard::check_key_released(HTKey::Escape) {
                 process::exit(0);
             }
+            if keyboard::check_key_released(HTKey::F3) {
+                crate::ui::SHOW_NAVMESH.fetch_xor(true, Ordering::Relaxed);
+            }
         }
     }
 }
\ No newline at end of file
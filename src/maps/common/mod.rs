@@ -0,0 +1,28 @@
+use crate::maps::triggers::{register_handler, TriggerAction};
+use crate::worldmachine::components::COMPONENT_TYPE_JUKEBOX;
+use crate::worldmachine::jukebox::JukeboxCommand;
+
+// handlers any map can use
+pub fn register_trigger_handlers() {
+    // toggles the jukebox on the trigger's entity, or on its parent if it doesn't have one
+    register_handler("jukebox", |worldmachine, context| {
+        if context.action == TriggerAction::Exit {
+            return;
+        }
+        let entity = match worldmachine.get_entity(context.trigger) {
+            Some(entity) => entity,
+            None => return,
+        };
+        let jukebox = if entity.get_component(COMPONENT_TYPE_JUKEBOX.clone()).is_some() {
+            Some(entity.uid)
+        } else {
+            entity.parent
+        };
+        match jukebox {
+            Some(jukebox) => {
+                worldmachine.jukebox_command(jukebox, JukeboxCommand::Toggle);
+            }
+            None => warn!("jukebox trigger {} isn't on or under a jukebox", context.trigger),
+        }
+    });
+}
@@ -1,21 +1,17 @@
-use std::sync::{Arc, Mutex};
-use halfbrown::HashMap;
-use crate::maps::triggers::Trigger;
-
+pub mod scripting;
 pub mod triggers;
+mod common;
 mod test;
 
-pub type TriggerMap = Arc<HashMap<String, Arc<Mutex<Trigger>>>>;
+lazy_static! {
+    pub static ref TRIGGER_HANDLERS_REGISTERED: bool = {
+        register_trigger_handlers();
+        true
+    };
+}
 
-pub fn get_trigger(map: impl ToString, key: impl ToString) -> Option<Arc<Mutex<Trigger>>> {
-    let map = map.to_string();
-    let key = key.to_string();
-    match map.as_str() {
-        "test" => {
-            test::get_trigger(key)
-        },
-        _ => {
-            None
-        }
-    }
-}
\ No newline at end of file
+/// registers the trigger handlers of every map, Trigger components refer to them by name
+pub fn register_trigger_handlers() {
+    common::register_trigger_handlers();
+    test::register_trigger_handlers();
+}
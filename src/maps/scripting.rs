@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use gfx_maths::{Quaternion, Vec2, Vec3};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+use crate::worldmachine::components::COMPONENT_TYPE_TRANSFORM;
+use crate::worldmachine::ecs::{Entity, ParameterValue};
+use crate::worldmachine::entities::load_entity_def;
+use crate::worldmachine::entity_store::EntityStore;
+use crate::worldmachine::jukebox::JukeboxCommand;
+use crate::worldmachine::EntityId;
+
+// a runaway on_tick shouldn't be able to hang the server
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// things a script asked for, applied to the world once the hook returns
+/// scripts never touch the WorldMachine directly
+#[derive(Clone, Debug)]
+pub enum ScriptCommand {
+    SetParameter(EntityId, String, String, Dynamic), // entity, component, parameter, value
+    Spawn(String, Entity), // prefab it came from, entity
+    Despawn(EntityId),
+    Chat(String),
+    Teleport(EntityId, Vec3),
+    Jukebox(EntityId, JukeboxCommand),
+    Movement(String, f32), // one of the MovementSettings, by name
+}
+
+#[derive(Default)]
+struct ScriptState {
+    entities: Vec<(EntityId, String)>, // what find_entity searches, refreshed before every hook
+    commands: Vec<ScriptCommand>,
+}
+
+/// a map's script, loaded from base/maps/<name>/script.rhai
+/// hooks (all optional):
+///   on_tick(delta)
+///   on_player_join(player, name)
+///   on_trigger_enter(trigger, who), on_trigger_exit(trigger, who), on_trigger_use(trigger, who)
+///   on_snowball_hit(who), who is -1 if it hit the world rather than an entity
+/// `who` is the entity that touched the trigger. a Trigger component's handler can also name a script function,
+/// which is called as handler(trigger, who, action)
+pub struct MapScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+impl MapScript {
+    pub fn load(game_data_path: &str, map_name: &str) -> Option<MapScript> {
+        let path = PathBuf::from(format!("{}/maps/{}/script.rhai", game_data_path, map_name));
+        if !path.exists() {
+            return None;
+        }
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let engine = Self::create_engine(game_data_path, state.clone());
+        let ast = match engine.compile_file(path.clone()) {
+            Ok(ast) => ast,
+            Err(e) => {
+                error!("failed to compile map script {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let mut scope = Scope::new();
+        if let Err(e) = engine.run_ast_with_scope(&mut scope, &ast) {
+            error!("map script {} failed while loading: {}", path.display(), e);
+            return None;
+        }
+        info!("loaded map script {}", path.display());
+        // the top level of the script runs before there is anything to apply its commands to
+        state.lock().unwrap().commands.clear();
+        Some(MapScript {
+            engine,
+            ast,
+            scope,
+            state,
+        })
+    }
+
+    fn create_engine(game_data_path: &str, state: Arc<Mutex<ScriptState>>) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("script: {}", text));
+        engine.on_debug(|text, _, _| debug!("script: {}", text));
+
+        let s = state.clone();
+        engine.register_fn("find_entity", move |name: &str| -> i64 {
+            let state = s.lock().unwrap();
+            state.entities.iter().find(|(_, entity_name)| entity_name == name).map(|(id, _)| *id as i64).unwrap_or(-1)
+        });
+        let s = state.clone();
+        engine.register_fn("set_parameter", move |entity: i64, component: &str, parameter: &str, value: Dynamic| {
+            s.lock().unwrap().commands.push(ScriptCommand::SetParameter(entity as EntityId, component.to_string(), parameter.to_string(), value));
+        });
+        let s = state.clone();
+        let game_data_path = game_data_path.to_string();
+        engine.register_fn("spawn", move |prefab: &str, x: f64, y: f64, z: f64| -> i64 {
+            let entity_def = match load_entity_def(&game_data_path, prefab) {
+                Some(entity_def) => entity_def,
+                None => return -1,
+            };
+            let mut entity = Entity::from_entity_def(&entity_def);
+            entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "position", ParameterValue::Vec3(Vec3::new(x as f32, y as f32, z as f32)));
+            let entity_id = entity.uid;
+            s.lock().unwrap().commands.push(ScriptCommand::Spawn(prefab.to_string(), entity));
+            entity_id as i64
+        });
+        let s = state.clone();
+        engine.register_fn("despawn", move |entity: i64| {
+            s.lock().unwrap().commands.push(ScriptCommand::Despawn(entity as EntityId));
+        });
+        let s = state.clone();
+        engine.register_fn("chat", move |message: &str| {
+            s.lock().unwrap().commands.push(ScriptCommand::Chat(message.to_string()));
+        });
+        let s = state.clone();
+        engine.register_fn("teleport", move |player: i64, x: f64, y: f64, z: f64| {
+            s.lock().unwrap().commands.push(ScriptCommand::Teleport(player as EntityId, Vec3::new(x as f32, y as f32, z as f32)));
+        });
+        for (name, command) in [
+            ("jukebox_play", JukeboxCommand::Play),
+            ("jukebox_stop", JukeboxCommand::Stop),
+            ("jukebox_toggle", JukeboxCommand::Toggle),
+            ("jukebox_next", JukeboxCommand::Next),
+        ] {
+            let s = state.clone();
+            engine.register_fn(name, move |jukebox: i64| {
+                s.lock().unwrap().commands.push(ScriptCommand::Jukebox(jukebox as EntityId, command.clone()));
+            });
+        }
+        let s = state.clone();
+        engine.register_fn("jukebox_seek", move |jukebox: i64, seconds: f64| {
+            s.lock().unwrap().commands.push(ScriptCommand::Jukebox(jukebox as EntityId, JukeboxCommand::Seek(seconds)));
+        });
+        let s = state.clone();
+        engine.register_fn("jukebox_volume", move |jukebox: i64, volume: f64| {
+            s.lock().unwrap().commands.push(ScriptCommand::Jukebox(jukebox as EntityId, JukeboxCommand::Volume(volume)));
+        });
+        let s = state.clone();
+        engine.register_fn("jukebox_track", move |jukebox: i64, track: &str| {
+            s.lock().unwrap().commands.push(ScriptCommand::Jukebox(jukebox as EntityId, JukeboxCommand::Track(track.to_string())));
+        });
+        let s = state;
+        engine.register_fn("set_movement", move |setting: &str, value: f64| {
+            s.lock().unwrap().commands.push(ScriptCommand::Movement(setting.to_string(), value as f32));
+        });
+
+        engine
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|function| function.name == name)
+    }
+
+    /// calls a script function if it exists, returning what it asked to be done
+    pub fn call(&mut self, entities: &EntityStore, name: &str, args: impl FuncArgs) -> Vec<ScriptCommand> {
+        if !self.has_function(name) {
+            return Vec::new();
+        }
+        {
+            let mut state = self.state.lock().unwrap();
+            state.entities = entities.iter().map(|(_, entity)| (entity.uid, entity.name.clone())).collect();
+        }
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(true);
+        if let Err(e) = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args) {
+            error!("map script: {} failed: {}", name, e);
+        }
+        std::mem::take(&mut self.state.lock().unwrap().commands)
+    }
+}
+
+/// turns a script value into a parameter value of the same type as the parameter it's replacing
+pub fn to_parameter_value(value: &Dynamic, like: &ParameterValue) -> Option<ParameterValue> {
+    let float = |value: &Dynamic| value.as_float().ok().or_else(|| value.as_int().ok().map(|v| v as f64));
+    let floats = |value: &Dynamic, len: usize| -> Option<Vec<f32>> {
+        let array = value.clone().try_cast::<Array>()?;
+        if array.len() != len {
+            return None;
+        }
+        array.iter().map(|v| float(v).map(|v| v as f32)).collect()
+    };
+    match like {
+        ParameterValue::Vec3(_) => floats(value, 3).map(|v| ParameterValue::Vec3(Vec3::new(v[0], v[1], v[2]))),
+        ParameterValue::Quaternion(_) => floats(value, 4).map(|v| ParameterValue::Quaternion(Quaternion::new(v[0], v[1], v[2], v[3]))),
+        ParameterValue::Vec2(_) => floats(value, 2).map(|v| ParameterValue::Vec2(Vec2::new(v[0], v[1]))),
+        ParameterValue::Float(_) => float(value).map(ParameterValue::Float),
+        ParameterValue::Int(_) => value.as_int().ok().map(|v| ParameterValue::Int(v as i32)),
+        ParameterValue::UnsignedInt(_) => value.as_int().ok().filter(|v| *v >= 0).map(|v| ParameterValue::UnsignedInt(v as u64)),
+        ParameterValue::Bool(_) => value.as_bool().ok().map(ParameterValue::Bool),
+        ParameterValue::String(_) => value.clone().into_string().ok().map(ParameterValue::String),
+    }
+}
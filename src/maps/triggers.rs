@@ -1,56 +1,167 @@
-use gfx_maths::{Quaternion, Vec3};
-use crate::renderer::ht_renderer;
-use crate::worldmachine::EntityId;
+use std::sync::Mutex;
+use halfbrown::HashMap;
+use crate::physics::ActorOwner;
+use crate::worldmachine::{EntityId, WorldMachine};
+use crate::worldmachine::ecs::{Component, ParameterValue};
 
-#[derive(Clone)]
+pub type TriggerFn = fn(&mut WorldMachine, &TriggerContext);
+
+lazy_static! {
+    static ref TRIGGER_HANDLERS: Mutex<HashMap<String, TriggerFn>> = Mutex::new(HashMap::new());
+}
+
+/// the runtime state of an entity's Trigger component
+#[derive(Clone, Debug)]
 pub struct Trigger {
-    pub position: Vec3,
-    pub rotation: Quaternion,
-    pub scale: Vec3,
+    pub entity: EntityId,
     pub report: TriggerReport,
     pub trigger_type: TriggerType,
-    pub trigger_fn: Option<fn(&mut ht_renderer, &mut Trigger, WhoTriggered)>,
-    state: bool,
+    pub handler: String,
+    pub occupants: Vec<WhoTriggered>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TriggerType {
-    OnEnter,
-    OnUse,
+    OnEnter, // handler runs when something enters or exits
+    OnUse, // handler runs when something inside presses use
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TriggerReport {
     OnlyPlayers,
-    OnlyClient,
+    OnlyClient, // only the local player, handled clientside
     OnlyNPCS,
     All,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WhoTriggered {
     Client,
     Player(EntityId),
+    Npc(EntityId),
     Other(EntityId),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerAction {
+    Enter,
+    Exit,
+    Use,
+}
+
+#[derive(Debug, Clone)]
+pub struct TriggerContext {
+    pub trigger: EntityId,
+    pub who: WhoTriggered,
+    pub action: TriggerAction,
+}
+
+impl TriggerType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "enter" => Some(TriggerType::OnEnter),
+            "use" => Some(TriggerType::OnUse),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerType::OnEnter => "enter",
+            TriggerType::OnUse => "use",
+        }
+    }
+}
+
+impl TriggerReport {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "players" => Some(TriggerReport::OnlyPlayers),
+            "client" => Some(TriggerReport::OnlyClient),
+            "npcs" => Some(TriggerReport::OnlyNPCS),
+            "all" => Some(TriggerReport::All),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerReport::OnlyPlayers => "players",
+            TriggerReport::OnlyClient => "client",
+            TriggerReport::OnlyNPCS => "npcs",
+            TriggerReport::All => "all",
+        }
+    }
+
+    /// should this side handle `who` touching the trigger?
+    /// client triggers only ever run clientside, everything else is up to the server
+    pub fn accepts(&self, who: &WhoTriggered, is_server: bool) -> bool {
+        match self {
+            TriggerReport::OnlyClient => !is_server && *who == WhoTriggered::Client,
+            TriggerReport::OnlyPlayers => is_server && matches!(who, WhoTriggered::Player(_)),
+            TriggerReport::OnlyNPCS => is_server && matches!(who, WhoTriggered::Npc(_)),
+            TriggerReport::All => is_server && *who != WhoTriggered::Client,
+        }
+    }
+}
+
+impl WhoTriggered {
+    pub fn from_owner(owner: ActorOwner) -> Option<Self> {
+        match owner {
+            ActorOwner::Player(entity_id) => Some(WhoTriggered::Player(entity_id)),
+            ActorOwner::LocalPlayer => Some(WhoTriggered::Client),
+            ActorOwner::Npc(entity_id) => Some(WhoTriggered::Npc(entity_id)),
+            ActorOwner::Other(entity_id) => Some(WhoTriggered::Other(entity_id)),
+            ActorOwner::Trigger(_) => None, // triggers touching triggers isn't interesting
+            ActorOwner::Snowball(_) => None, // they're gone too quickly to be worth telling anyone about
+        }
+    }
+
+    pub fn entity_id(&self) -> Option<EntityId> {
+        match self {
+            WhoTriggered::Client => None,
+            WhoTriggered::Player(entity_id) | WhoTriggered::Npc(entity_id) | WhoTriggered::Other(entity_id) => Some(*entity_id),
+        }
+    }
+}
+
 impl Trigger {
-    pub fn trigger(trigger_type: TriggerType, trigger_fn: fn(&mut ht_renderer, &mut Trigger, WhoTriggered)) -> Trigger {
+    pub fn from_component(entity: EntityId, component: &Component) -> Trigger {
+        let string_parameter = |name: &str| match &component.get_parameter(name).value {
+            ParameterValue::String(value) => Some(value.clone()),
+            _ => None,
+        };
+        let trigger_type = string_parameter("trigger_type").map(|name| {
+            TriggerType::from_name(&name).unwrap_or_else(|| {
+                warn!("trigger {} has unknown trigger_type {}, defaulting to enter", entity, name);
+                TriggerType::OnEnter
+            })
+        }).unwrap_or(TriggerType::OnEnter);
+        let report = string_parameter("report").map(|name| {
+            TriggerReport::from_name(&name).unwrap_or_else(|| {
+                warn!("trigger {} has unknown report {}, defaulting to all", entity, name);
+                TriggerReport::All
+            })
+        }).unwrap_or(TriggerReport::All);
+        let handler = string_parameter("handler").unwrap_or_default();
+        if handler.is_empty() {
+            debug!("trigger {} has no handler", entity);
+        }
         Trigger {
-            position: Vec3::new(0.0, 0.0, 0.0),
-            rotation: Quaternion::new(0.0, 0.0, 0.0, 1.0),
-            scale: Vec3::new(1.0, 1.0, 1.0),
-            report: TriggerReport::OnlyClient,
+            entity,
+            report,
             trigger_type,
-            trigger_fn: Some(trigger_fn),
-            state: false,
+            handler,
+            occupants: Vec::new(),
         }
     }
+}
 
-    pub fn set_position(&mut self, position: Vec3) {
-        self.position = position;
-    }
-    pub fn set_size(&mut self, scale: Vec3) {
-        self.scale = scale;
-    }
-}
\ No newline at end of file
+/// registers a function that Trigger components can name in their handler parameter
+pub fn register_handler(name: &str, handler: TriggerFn) {
+    TRIGGER_HANDLERS.lock().unwrap().insert(name.to_string(), handler);
+}
+
+pub fn get_handler(name: &str) -> Option<TriggerFn> {
+    TRIGGER_HANDLERS.lock().unwrap().get(name).cloned()
+}
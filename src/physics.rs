@@ -1,124 +1,546 @@
-use std::cell::UnsafeCell;
 use std::ffi::c_void;
+use std::ops::Deref;
 use halfbrown::HashMap;
-use std::ptr::{null_mut};
+use std::ptr::{null, null_mut};
 use std::sync::{Arc};
 use mutex_timeouts::std::MutexWithTimeout as Mutex;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use gfx_maths::{Vec3};
+use gfx_maths::{Quaternion, Vec3};
 use physx_sys::*;
-
-lazy_static! {
-    static ref BOX_COLLIDERS: Arc<Mutex<Vec<PhysicsBoxColliderStatic>>> = Arc::new(Mutex::new(Vec::new()));
-    static ref TRIGGER_SHAPES: Arc<Mutex<Vec<PhysicsTriggerShape>>> = Arc::new(Mutex::new(Vec::new()));
-    static ref PHYSICS_SYSTEM: Arc<Mutex<Option<PhysicsSystem>>> = Arc::new(Mutex::new(None));
-
-    pub static ref PHYSICS_LOCK: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
-}
+use serde::Deserialize;
+use crate::helpers;
+use crate::worldmachine::EntityId;
 
 pub const GRAVITY: f32 = -9.81;
 pub const PLAYER_GRAVITY: f32 = -24.36;
 pub const PLAYER_TERMINAL_VELOCITY: f32 = 90.0;
 pub const PLAYER_JUMP_VELOCITY: f32 = 12.3;
+pub const PLAYER_SWIM_VELOCITY: f32 = 5.0; // how fast jumping in water swims upwards
+
+// how quickly water slows a controller's vertical speed, per second
+const WATER_DRAG: f32 = 2.0;
+// the sphere that checks for room to stand up is this much thinner than the capsule, so walls it's already touching don't count
+const HEADROOM_SKIN: f32 = 0.9;
+
+// set in word0 of a shape's simulation filter data to have its contacts reported, see report_contacts
+const REPORT_CONTACTS: u32 = 1;
+// most contact points read from a single contact pair
+const CONTACT_POINT_BUFFER_SIZE: usize = 8;
+
+// most hits a single query can report, anything past this is lost
+const QUERY_BUFFER_SIZE: usize = 64;
+
+// how far below a controller's feet to look for what it's standing on
+const GROUND_PROBE_DISTANCE: f32 = 0.3;
 
+// the version of physx that physx-sys is built against, cooking refuses to start if this doesn't match
+const PHYSX_VERSION: u32 = version(4, 1, 2);
+
+/// a pointer to something owned by physx
+/// physx objects aren't thread safe, but nothing here touches one without holding the lock of the scene (or foundation)
+/// it belongs to, so the pointers themselves can be passed between threads
+struct PxPtr<T>(*mut T);
+
+unsafe impl<T> Send for PxPtr<T> {}
+
+unsafe impl<T> Sync for PxPtr<T> {}
+
+impl<T> Clone for PxPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PxPtr<T> {}
+
+// a mesh that's been cooked for physx, shared between scenes
+#[derive(Clone, Copy)]
+enum CookedMesh {
+    Triangles(PxPtr<PxTriangleMesh>),
+    Convex(PxPtr<PxConvexMesh>),
+}
+
+/// the shape of a collider or rigid body
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColliderShape {
+    Box(Vec3), // full size
+    Sphere(f32), // radius
+    Capsule(f32, f32), // radius, distance between the centres of the ends. stands upright
+    Convex(String, Vec3), // hull of base/models/<mesh>.glb, scale
+    Mesh(String, Vec3), // exact triangles of base/models/<mesh>.glb, scale. only for things that don't move
+}
+
+/// what a RigidBody component asks for
+#[derive(Clone, Debug, PartialEq)]
+pub struct RigidBodyDesc {
+    pub shape: ColliderShape,
+    pub mass: f32,
+    pub friction: f32,
+    pub restitution: f32,
+    pub kinematic: bool, // only moves where it's told to, but still pushes everything else
+}
+
+// physx geometry, kept here so that it outlives the calls that take a pointer to it
+enum ShapeGeometry {
+    Box(PxBoxGeometry),
+    Sphere(PxSphereGeometry),
+    Capsule(PxCapsuleGeometry),
+    Convex(PxConvexMeshGeometry),
+    Mesh(PxTriangleMeshGeometry),
+}
+
+impl ShapeGeometry {
+    fn as_ptr(&self) -> *const PxGeometry {
+        match self {
+            ShapeGeometry::Box(geometry) => geometry as *const PxBoxGeometry as *const PxGeometry,
+            ShapeGeometry::Sphere(geometry) => geometry as *const PxSphereGeometry as *const PxGeometry,
+            ShapeGeometry::Capsule(geometry) => geometry as *const PxCapsuleGeometry as *const PxGeometry,
+            ShapeGeometry::Convex(geometry) => geometry as *const PxConvexMeshGeometry as *const PxGeometry,
+            ShapeGeometry::Mesh(geometry) => geometry as *const PxTriangleMeshGeometry as *const PxGeometry,
+        }
+    }
+}
+
+// the physx foundation and physics objects, shared by every scene. physx only allows one of these per process
+struct PhysicsFoundation {
+    foundation: PxPtr<PxFoundation>,
+    physics: PxPtr<PxPhysics>,
+    cooking: PxPtr<PxCooking>,
+    player_material: PxPtr<PxMaterial>,
+    // name -> surface from materials.yaml, always has "default"
+    surfaces: Mutex<HashMap<String, (SurfaceMaterial, PxPtr<PxMaterial>)>>,
+    // (friction, restitution) bits -> material, for rigid bodies
+    custom_materials: Mutex<HashMap<(u32, u32), PxPtr<PxMaterial>>>,
+    // "triangles:<mesh>" or "convex:<mesh>" -> cooked mesh, None if it couldn't be loaded or cooked
+    meshes: Mutex<HashMap<String, Option<CookedMesh>>>,
+    lock: Mutex<()>, // held while creating scenes, actors, shapes and meshes
+}
+
+// a physx scene. everything created in it holds on to it, so it's only released once nothing in it is left
+struct PhysicsScene {
+    foundation: Arc<PhysicsFoundation>,
+    scene: PxPtr<PxScene>,
+    controller_manager: PxPtr<PxControllerManager>,
+    dispatcher: PxPtr<PxDefaultCpuDispatcher>,
+    callbacks: PxPtr<PxSimulationEventCallback>, // owned by us rather than the scene, so it's freed after it
+    lock: Mutex<()>, // held while touching anything in this scene, so other scenes can be stepped at the same time
+    callback_data: Box<SceneCallbackData>,
+    carry_velocities: Mutex<HashMap<usize, Vec3>>, // actor pointer -> how fast it carries controllers standing on it
+}
+
+// what the simulation callbacks of one scene read and fill in. physx is given a pointer to it, so it's boxed to keep it in one place
+struct SceneCallbackData {
+    actor_owners: Mutex<HashMap<usize, ActorOwner>>, // actor pointer -> what it belongs to, so physx callbacks and queries can be turned back into entities
+    trigger_events: Mutex<Vec<TriggerEvent>>, // filled during fetchResults
+    contact_events: Mutex<Vec<ContactEvent>>,
+}
+
+/// a handle to one physx scene, cheap to clone
+/// the client and the server each have their own scene, see copy_with_new_scene
 #[derive(Clone)]
 pub struct PhysicsSystem {
-    pub foundation: Arc<Mutex<*mut PxFoundation>>,
-    pub physics: Arc<Mutex<*mut PxPhysics>>,
-    pub dispatcher: Arc<Mutex<*mut PxDefaultCpuDispatcher>>,
-    pub scene: *mut PxScene,
-    pub controller_manager: *mut PxControllerManager,
-    pub physics_materials: HashMap<Materials, PhysicsMaterial>,
+    scene: Arc<PhysicsScene>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ActorOwner {
+    Player(EntityId),
+    LocalPlayer, // the client's own character controller, which has no entity
+    Npc(EntityId),
+    Trigger(EntityId),
+    Other(EntityId), // colliders and rigid bodies
+    Snowball(u64), // see Snowball::id, snowballs aren't entities
+}
+
+impl ActorOwner {
+    pub fn entity_id(&self) -> Option<EntityId> {
+        match self {
+            ActorOwner::Player(entity_id) | ActorOwner::Npc(entity_id) | ActorOwner::Trigger(entity_id) | ActorOwner::Other(entity_id) => Some(*entity_id),
+            ActorOwner::LocalPlayer | ActorOwner::Snowball(_) => None,
+        }
+    }
 }
 
-unsafe impl Send for PhysicsSystem {}
+/// something a raycast or sweep ran into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueryHit {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub distance: f32, // 0 if it started inside the thing it hit
+    pub owner: Option<ActorOwner>, // None for things that don't belong to anything, like snowballs
+}
+
+impl QueryHit {
+    pub fn entity_id(&self) -> Option<EntityId> {
+        self.owner?.entity_id()
+    }
+}
 
-unsafe impl Sync for PhysicsSystem {}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContactPhase {
+    Begin,
+    Persist, // still touching, sent every tick until it stops
+    End,
+}
+
+/// two actors touching, only reported for actors that asked for it with report_contacts
+#[derive(Clone, Copy, Debug)]
+pub struct ContactEvent {
+    pub owners: [Option<ActorOwner>; 2],
+    pub phase: ContactPhase,
+    pub position: Vec3, // where they first touched, zero when they stop touching
+    pub normal: Vec3,
+    pub impulse: f32, // how hard they hit, summed over every contact point
+}
 
+impl ContactEvent {
+    /// the other actor, if `owner` is one of the two
+    pub fn other(&self, owner: ActorOwner) -> Option<Option<ActorOwner>> {
+        if self.owners[0] == Some(owner) {
+            Some(self.owners[1])
+        } else if self.owners[1] == Some(owner) {
+            Some(self.owners[0])
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerEvent {
+    pub trigger: EntityId,
+    pub other: ActorOwner,
+    pub entered: bool, // false if the other actor left the trigger
+}
+
+impl SceneCallbackData {
+    fn set_actor_owner(&self, actor: *mut PxActor, owner: ActorOwner) {
+        self.actor_owners.lock().unwrap().insert(actor as usize, owner);
+    }
+
+    fn clear_actor_owner(&self, actor: *mut PxActor) {
+        self.actor_owners.lock().unwrap().remove(&(actor as usize));
+    }
+
+    fn actor_owner(&self, actor: *const PxActor) -> Option<ActorOwner> {
+        self.actor_owners.lock().unwrap().get(&(actor as usize)).cloned()
+    }
+}
+
+// user_data is the SceneCallbackData of the scene being stepped
 unsafe extern "C" fn on_trigger(
-    _: *mut c_void,
+    user_data: *mut c_void,
     b: *const PxTriggerPair,
     n_pairs: u32,
 ) {
+    let data = &*(user_data as *const SceneCallbackData);
     let pairs = std::slice::from_raw_parts(b, n_pairs as usize);
-    debug!("trigger pairs: {}", pairs.len());
+    let mut events = Vec::new();
+    for pair in pairs {
+        // shapes that were removed from the scene get reported as lost, their owners may already be gone
+        if pair.triggerActor.is_null() || pair.otherActor.is_null() {
+            continue;
+        }
+        let entered = if pair.status as u32 == PxPairFlag::NotifyTouchFound as u32 {
+            true
+        } else if pair.status as u32 == PxPairFlag::NotifyTouchLost as u32 {
+            false
+        } else {
+            continue;
+        };
+        let trigger = match data.actor_owner(pair.triggerActor as *const PxActor) {
+            Some(ActorOwner::Trigger(entity_id)) => entity_id,
+            _ => continue,
+        };
+        let other = match data.actor_owner(pair.otherActor as *const PxActor) {
+            Some(owner) => owner,
+            None => continue,
+        };
+        events.push(TriggerEvent { trigger, other, entered });
+    }
+    if !events.is_empty() {
+        data.trigger_events.lock().unwrap().append(&mut events);
+    }
 }
 
-impl PhysicsSystem {
-    pub fn init() -> Self {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let foundation = unsafe { physx_create_foundation() };
-        let physics = unsafe { physx_create_physics(foundation) };
-        let mut scene_desc = unsafe { PxSceneDesc_new(PxPhysics_getTolerancesScale(physics)) };
-        scene_desc.gravity = PxVec3 {
-            x: 0.0,
-            y: GRAVITY,
-            z: 0.0,
+unsafe extern "C" fn on_contact(
+    user_data: *mut c_void,
+    header: *const PxContactPairHeader,
+    pairs: *const PxContactPair,
+    n_pairs: u32,
+) {
+    let data = &*(user_data as *const SceneCallbackData);
+    let header = &*header;
+    // one of them has been removed from the scene, its owner has probably gone with it
+    let removed = PxContactPairHeaderFlag::RemovedActor0 as u16 | PxContactPairHeaderFlag::RemovedActor1 as u16;
+    if header.flags.bits() & removed != 0 || header.actors[0].is_null() || header.actors[1].is_null() {
+        return;
+    }
+    let owners = [
+        data.actor_owner(header.actors[0] as *const PxActor),
+        data.actor_owner(header.actors[1] as *const PxActor),
+    ];
+    let pairs = std::slice::from_raw_parts(pairs, n_pairs as usize);
+    let mut events = Vec::new();
+    for pair in pairs {
+        let flags = pair.events.bits();
+        let phase = if flags & PxPairFlag::NotifyTouchFound as u16 != 0 {
+            ContactPhase::Begin
+        } else if flags & PxPairFlag::NotifyTouchPersists as u16 != 0 {
+            ContactPhase::Persist
+        } else if flags & PxPairFlag::NotifyTouchLost as u16 != 0 {
+            ContactPhase::End
+        } else {
+            continue;
         };
-        let info = SimulationEventCallbackInfo {
-            trigger_callback: Some(on_trigger),
-            ..Default::default()
+        let mut points = (0..CONTACT_POINT_BUFFER_SIZE).map(|_| std::mem::zeroed::<PxContactPairPoint>()).collect::<Vec<PxContactPairPoint>>();
+        let count = PxContactPair_extractContacts(pair, points.as_mut_ptr(), CONTACT_POINT_BUFFER_SIZE as u32) as usize;
+        let points = &points[..count.min(CONTACT_POINT_BUFFER_SIZE)];
+        let (position, normal) = match points.first() {
+            Some(point) => (from_px_vec3(point.position), from_px_vec3(point.normal)),
+            None => (Vec3::zero(), Vec3::zero()),
         };
-        let callbacks = unsafe { create_simulation_event_callbacks(&info) };
-        scene_desc.simulationEventCallback = callbacks;
+        let impulse = points.iter().map(|point| from_px_vec3(point.impulse).magnitude()).sum();
+        events.push(ContactEvent { owners, phase, position, normal, impulse });
+    }
+    if !events.is_empty() {
+        data.contact_events.lock().unwrap().append(&mut events);
+    }
+}
 
-        unsafe {
-            scene_desc.filterShader = get_default_simulation_filter_shader();//filter_shader as *mut _;
-        }
+// asks for contact reports on pairs where either shape has REPORT_CONTACTS set, after the default shader has decided everything else
+unsafe extern "C" fn filter_shader(info: *mut FilterShaderCallbackInfo) -> u16 {
+    let info = &mut *info;
+    let report = (info.filterData0.word0 | info.filterData1.word0) & REPORT_CONTACTS != 0;
+    // triggers don't solve contacts, and they have their own reports
+    let solved = (*info.pairFlags).bits() & PxPairFlag::SolveContact as u16 != 0;
+    if report && solved {
+        let notify = PxPairFlag::NotifyTouchFound as u16
+            | PxPairFlag::NotifyTouchPersists as u16
+            | PxPairFlag::NotifyTouchLost as u16
+            | PxPairFlag::NotifyContactPoints as u16;
+        *info.pairFlags = PxPairFlags::from_bits_truncate((*info.pairFlags).bits() | notify);
+    }
+    0 // PxFilterFlag::eDEFAULT
+}
 
-        let nproc = num_cpus::get();
+fn px_transform(position: Vec3) -> PxTransform {
+    px_transform_rotated(position, Quaternion::identity())
+}
 
-        let dispatcher = unsafe { phys_PxDefaultCpuDispatcherCreate((nproc / 2).min(1) as u32, null_mut(), PxDefaultCpuDispatcherWaitForWorkMode::WaitForWork, 0) };
+fn px_transform_rotated(position: Vec3, rotation: Quaternion) -> PxTransform {
+    PxTransform {
+        p: PxVec3 {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+        },
+        q: PxQuat {
+            x: rotation.x,
+            y: rotation.y,
+            z: rotation.z,
+            w: rotation.w,
+        },
+    }
+}
 
-        scene_desc.cpuDispatcher = dispatcher as *mut _;
+fn px_vec3(v: Vec3) -> PxVec3 {
+    PxVec3 {
+        x: v.x,
+        y: v.y,
+        z: v.z,
+    }
+}
 
-        let scene = unsafe { PxPhysics_createScene_mut(physics, &scene_desc) };
+fn from_px_vec3(v: PxVec3) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
 
-        let controller_manager = unsafe { phys_PxCreateControllerManager(scene, true) };
+// every hit is reported as touching rather than blocking, so that queries can skip the ones they're ignoring and pick the closest themselves
+fn query_filter() -> PxQueryFilterData {
+    let mut filter = unsafe { PxQueryFilterData_new() };
+    let flags = PxQueryFlag::Static as u16 | PxQueryFlag::Dynamic as u16 | PxQueryFlag::NoBlock as u16;
+    filter.flags = PxQueryFlags::from_bits(flags).unwrap();
+    filter
+}
 
-        unsafe {
-            PxControllerManager_setOverlapRecoveryModule_mut(controller_manager, true);
-        }
+// the closest of a query's hits that doesn't belong to `ignore`
+fn closest_hit(scene: &PhysicsScene, hits: impl Iterator<Item = (*mut PxRigidActor, PxVec3, PxVec3, f32)>, ignore: Option<ActorOwner>) -> Option<QueryHit> {
+    hits.map(|(actor, position, normal, distance)| QueryHit {
+        position: from_px_vec3(position),
+        normal: from_px_vec3(normal),
+        distance,
+        owner: scene.callback_data.actor_owner(actor as *const PxActor),
+    })
+        .filter(|hit| ignore.is_none() || hit.owner != ignore)
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
 
-        drop(lock);
-        let physics_materials = Self::init_materials(physics);
+impl PhysicsFoundation {
+    fn new() -> Self {
+        let foundation = unsafe { physx_create_foundation() };
+        let physics = unsafe { physx_create_physics(foundation) };
+
+        let cooking = unsafe {
+            let params = PxCookingParams_new(PxPhysics_getTolerancesScale(physics));
+            phys_PxCreateCooking(PHYSX_VERSION, foundation, &params)
+        };
 
-        let sys = Self {
-            foundation: Arc::new(Mutex::new(foundation)),
-            physics: Arc::new(Mutex::new(physics)),
-            dispatcher: Arc::new(Mutex::new(dispatcher)),
-            scene, controller_manager, physics_materials };
+        let player_material = unsafe { PxPhysics_createMaterial_mut(physics, 0.0, 0.0, 0.0) };
+        let default = SurfaceMaterial::default();
+        let default_material = unsafe { PxPhysics_createMaterial_mut(physics, default.static_friction, default.dynamic_friction, default.restitution) };
+        let mut surfaces = HashMap::new();
+        surfaces.insert(default.name.clone(), (default, PxPtr(default_material)));
 
-        PHYSICS_SYSTEM.lock().unwrap().replace(sys.clone());
-        sys
+        Self {
+            foundation: PxPtr(foundation),
+            physics: PxPtr(physics),
+            cooking: PxPtr(cooking),
+            player_material: PxPtr(player_material),
+            surfaces: Mutex::new(surfaces),
+            custom_materials: Mutex::new(HashMap::new()),
+            meshes: Mutex::new(HashMap::new()),
+            lock: Mutex::new(()),
+        }
     }
 
-    // drop colliders and shapes before switching scenes
-    pub fn cleanup() {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let mut box_colliders = BOX_COLLIDERS.lock().unwrap();
-        let mut trigger_shapes = TRIGGER_SHAPES.lock().unwrap();
-        box_colliders.clear();
-        trigger_shapes.clear();
+    fn material(&self, material: &Materials) -> *mut PxMaterial {
+        match material {
+            Materials::Player => self.player_material.0,
+            Materials::Surface(name) => {
+                let surfaces = self.surfaces.lock().unwrap();
+                match surfaces.get(name) {
+                    Some((_, material)) => material.0,
+                    None => {
+                        warn!("no surface material called {}, using default", name);
+                        surfaces.get("default").unwrap().1.0
+                    }
+                }
+            }
+        }
+    }
+
+    // adds the surfaces in materials.yaml, or updates them if they're already there
+    fn load_surfaces(&self, game_data_path: &str) {
+        let path = format!("{}/materials.yaml", game_data_path);
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("load_surfaces: failed to open {}: {:?}, everything will use the default surface", path, e);
+                return;
+            }
+        };
+        let loaded: std::collections::BTreeMap<String, SurfaceMaterial> = match serde_yaml::from_reader(file) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                warn!("load_surfaces: failed to parse {}: {:?}", path, e);
+                return;
+            }
+        };
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let lock = self.lock.lock().unwrap();
+        for (name, mut surface) in loaded {
+            surface.name = name.clone();
+            let material = match surfaces.get(&name) {
+                Some((_, material)) => unsafe {
+                    PxMaterial_setStaticFriction_mut(material.0, surface.static_friction);
+                    PxMaterial_setDynamicFriction_mut(material.0, surface.dynamic_friction);
+                    PxMaterial_setRestitution_mut(material.0, surface.restitution);
+                    *material
+                },
+                None => PxPtr(unsafe { PxPhysics_createMaterial_mut(self.physics.0, surface.static_friction, surface.dynamic_friction, surface.restitution) }),
+            };
+            surfaces.insert(name, (surface, material));
+        }
         drop(lock);
+        debug!("loaded {} surface materials", surfaces.len());
     }
 
-    #[allow(clippy::not_unsafe_ptr_arg_deref)]
-    pub fn init_materials(physics: *mut PxPhysics) -> HashMap<Materials, PhysicsMaterial> {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let mut physics_materials = HashMap::new();
+    // the surface that a physx material was made for, None for the player material and rigid bodies' own materials
+    fn surface_of(&self, material: *mut PxMaterial) -> Option<SurfaceMaterial> {
+        self.surfaces.lock().unwrap().values()
+            .find(|(_, px_material)| px_material.0 == material)
+            .map(|(surface, _)| surface.clone())
+    }
 
-        let player_material = unsafe { PxPhysics_createMaterial_mut(physics, 0.0, 0.0, 0.0) };
+    // a material with the same static and dynamic friction, made the first time it's asked for
+    fn custom_material(&self, friction: f32, restitution: f32) -> *mut PxMaterial {
+        let mut custom_materials = self.custom_materials.lock().unwrap();
+        let key = (friction.to_bits(), restitution.to_bits());
+        if let Some(material) = custom_materials.get(&key) {
+            return material.0;
+        }
+        let lock = self.lock.lock().unwrap();
+        let material = unsafe { PxPhysics_createMaterial_mut(self.physics.0, friction, friction, restitution) };
+        drop(lock);
+        custom_materials.insert(key, PxPtr(material));
+        material
+    }
 
-        physics_materials.insert(Materials::Player, PhysicsMaterial { material: player_material });
+    // loads and cooks base/models/<mesh>.glb the first time it's asked for
+    fn cooked_mesh(&self, game_data_path: &str, mesh_name: &str, convex: bool) -> Option<CookedMesh> {
+        let key = format!("{}:{}", if convex { "convex" } else { "triangles" }, mesh_name);
+        if let Some(cooked) = self.meshes.lock().unwrap().get(&key) {
+            return *cooked;
+        }
+        let cooked = helpers::load_gltf_mesh(game_data_path, mesh_name).and_then(|(vertices, indices)| {
+            let points = vertices.iter().map(|v| px_vec3(*v)).collect::<Vec<PxVec3>>();
+            let lock = self.lock.lock().unwrap();
+            let cooked = unsafe {
+                let insertion = PxPhysics_getPhysicsInsertionCallback_mut(self.physics.0);
+                if convex {
+                    let mut desc = PxConvexMeshDesc_new();
+                    desc.points.count = points.len() as u32;
+                    desc.points.stride = std::mem::size_of::<PxVec3>() as u32;
+                    desc.points.data = points.as_ptr() as *const c_void;
+                    desc.flags = PxConvexFlags::from_bits(PxConvexFlag::ComputeConvex as u16).unwrap();
+                    let mesh = PxCooking_createConvexMesh(self.cooking.0, &desc, insertion, null_mut());
+                    (!mesh.is_null()).then_some(CookedMesh::Convex(PxPtr(mesh)))
+                } else {
+                    let mut desc = PxTriangleMeshDesc_new();
+                    desc.points.count = points.len() as u32;
+                    desc.points.stride = std::mem::size_of::<PxVec3>() as u32;
+                    desc.points.data = points.as_ptr() as *const c_void;
+                    desc.triangles.count = (indices.len() / 3) as u32;
+                    desc.triangles.stride = 3 * std::mem::size_of::<u32>() as u32;
+                    desc.triangles.data = indices.as_ptr() as *const c_void;
+                    let mesh = PxCooking_createTriangleMesh(self.cooking.0, &desc, insertion, null_mut());
+                    (!mesh.is_null()).then_some(CookedMesh::Triangles(PxPtr(mesh)))
+                }
+            };
+            drop(lock);
+            cooked
+        });
+        match cooked {
+            Some(_) => debug!("cooked {}", key),
+            None => warn!("failed to cook {}, it won't collide", key),
+        }
+        self.meshes.lock().unwrap().insert(key, cooked);
+        cooked
+    }
+}
 
+impl Drop for PhysicsFoundation {
+    fn drop(&mut self) {
+        let lock = self.lock.lock().unwrap();
+        unsafe {
+            PxCooking_release_mut(self.cooking.0);
+            PxPhysics_release_mut(self.physics.0);
+            PxFoundation_release_mut(self.foundation.0);
+        }
         drop(lock);
-        physics_materials
     }
+}
 
-    pub fn copy_with_new_scene(&self) -> Self {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let mut scene_desc = unsafe { PxSceneDesc_new(PxPhysics_getTolerancesScale(*self.physics.lock().unwrap())) };
+impl PhysicsScene {
+    fn new(foundation: Arc<PhysicsFoundation>, threads: u32) -> Self {
+        let callback_data = Box::new(SceneCallbackData {
+            actor_owners: Mutex::new(HashMap::new()),
+            trigger_events: Mutex::new(Vec::new()),
+            contact_events: Mutex::new(Vec::new()),
+        });
+        let user_data = &*callback_data as *const SceneCallbackData as *mut c_void;
+        let lock = foundation.lock.lock().unwrap();
+        let mut scene_desc = unsafe { PxSceneDesc_new(PxPhysics_getTolerancesScale(foundation.physics.0)) };
         scene_desc.gravity = PxVec3 {
             x: 0.0,
             y: GRAVITY,
@@ -126,20 +548,25 @@ impl PhysicsSystem {
         };
         let info = SimulationEventCallbackInfo {
             trigger_callback: Some(on_trigger),
+            trigger_user_data: user_data,
+            collision_callback: Some(on_contact),
+            collision_user_data: user_data,
             ..Default::default()
         };
         let callbacks = unsafe { create_simulation_event_callbacks(&info) };
         scene_desc.simulationEventCallback = callbacks;
+        // character controllers are kinematic and triggers are static, physx drops those pairs unless told otherwise
+        scene_desc.staticKineFilteringMode = PxPairFilteringMode::Keep;
 
         unsafe {
-            scene_desc.filterShader = get_default_simulation_filter_shader();//filter_shader as *mut _;
+            enable_custom_filter_shader(&mut scene_desc, filter_shader, 1);
         }
 
-        let dispatcher = unsafe { phys_PxDefaultCpuDispatcherCreate(2, null_mut(), PxDefaultCpuDispatcherWaitForWorkMode::WaitForWork, 0) };
+        let dispatcher = unsafe { phys_PxDefaultCpuDispatcherCreate(threads, null_mut(), PxDefaultCpuDispatcherWaitForWorkMode::WaitForWork, 0) };
 
         scene_desc.cpuDispatcher = dispatcher as *mut _;
 
-        let scene = unsafe { PxPhysics_createScene_mut(*self.physics.lock().unwrap(), &scene_desc) };
+        let scene = unsafe { PxPhysics_createScene_mut(foundation.physics.0, &scene_desc) };
 
         let controller_manager = unsafe { phys_PxCreateControllerManager(scene, true) };
 
@@ -148,8 +575,60 @@ impl PhysicsSystem {
         }
 
         drop(lock);
+        Self {
+            foundation,
+            scene: PxPtr(scene),
+            controller_manager: PxPtr(controller_manager),
+            dispatcher: PxPtr(dispatcher),
+            callbacks: PxPtr(callbacks),
+            lock: Mutex::new(()),
+            callback_data,
+            carry_velocities: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Drop for PhysicsScene {
+    fn drop(&mut self) {
+        let lock = self.lock.lock().unwrap();
+        unsafe {
+            PxControllerManager_release_mut(self.controller_manager.0);
+            PxScene_release_mut(self.scene.0);
+            PxDefaultCpuDispatcher_release_mut(self.dispatcher.0);
+            destroy_simulation_event_callbacks(self.callbacks.0);
+        }
+        drop(lock);
+    }
+}
+
+impl PhysicsSystem {
+    pub fn init() -> Self {
+        let foundation = Arc::new(PhysicsFoundation::new());
+        let nproc = num_cpus::get();
+        Self {
+            scene: Arc::new(PhysicsScene::new(foundation, (nproc / 2).max(1) as u32)),
+        }
+    }
 
-        Self { foundation: self.foundation.clone(), physics: self.physics.clone(), dispatcher: self.dispatcher.clone(), scene, controller_manager, physics_materials: self.physics_materials.clone() }
+    /// reads <game_data_path>/materials.yaml, the surfaces in it are shared by every scene
+    pub fn load_materials(&self, game_data_path: &str) {
+        self.scene.foundation.load_surfaces(game_data_path);
+    }
+
+    /// a new, empty scene sharing this one's foundation
+    pub fn copy_with_new_scene(&self) -> Self {
+        Self {
+            scene: Arc::new(PhysicsScene::new(self.scene.foundation.clone(), 2)),
+        }
+    }
+
+    /// trigger enters and exits that happened in this scene since the last call
+    pub fn drain_trigger_events(&self) -> Vec<TriggerEvent> {
+        std::mem::take(&mut *self.scene.callback_data.trigger_events.lock().unwrap())
+    }
+
+    pub fn drain_contact_events(&self) -> Vec<ContactEvent> {
+        std::mem::take(&mut *self.scene.callback_data.contact_events.lock().unwrap())
     }
 
     pub fn tick(&self, delta_time: f32) -> Option<f32> {
@@ -157,119 +636,182 @@ impl PhysicsSystem {
             return Some(delta_time);
         }
 
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        unsafe { PxScene_simulate_mut(self.scene, delta_time, null_mut(), null_mut(), 0, true) };
+        let lock = self.scene.lock.lock().unwrap();
+        unsafe { PxScene_simulate_mut(self.scene.scene.0, delta_time, null_mut(), null_mut(), 0, true) };
         let mut error = 0u32;
-        unsafe { PxScene_fetchResults_mut(self.scene, true, &mut error) };
+        unsafe { PxScene_fetchResults_mut(self.scene.scene.0, true, &mut error) };
         assert_eq!(error, 0, "physx error: {}", error);
         drop(lock);
         None
     }
 
     pub fn create_character_controller(&self, radius: f32, height: f32, step_offset: f32, material: Materials) -> Option<PhysicsCharacterController> {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let mut controller_desc = unsafe { PxCapsuleControllerDesc_new_alloc() };
+        let lock = self.scene.lock.lock().unwrap();
+        let controller_desc = unsafe { PxCapsuleControllerDesc_new_alloc() };
         unsafe { PxCapsuleControllerDesc_setToDefault_mut(controller_desc) };
-        let material = self.physics_materials.get(&material).unwrap();
         unsafe {
             (*controller_desc).height = height;
             (*controller_desc).radius = radius;
             (*controller_desc).stepOffset = step_offset;
-            (*controller_desc).material = material.material;
-
-            if PxCapsuleControllerDesc_isValid(controller_desc) {
-                let controller = PxControllerManager_createController_mut(self.controller_manager, controller_desc as *mut _);
-
-                drop(lock);
-                Some(PhysicsCharacterController {
-                    controller: Arc::new(Mutex::new(controller)),
-                    flags: Arc::new(Mutex::new(CollisionFlags::default())),
-                    y_velocity: Arc::new(UnsafeCell::new(0.0)),
-                })
-            } else {
-                drop(lock);
-                None
+            (*controller_desc).material = self.scene.foundation.material(&material);
+
+            if !PxCapsuleControllerDesc_isValid(controller_desc) {
+                PxCapsuleControllerDesc_delete(controller_desc);
+                return None;
             }
+            let controller = PxControllerManager_createController_mut(self.scene.controller_manager.0, controller_desc as *mut _);
+            // the manager copies what it needs out of the desc
+            PxCapsuleControllerDesc_delete(controller_desc);
+            drop(lock);
+            if controller.is_null() {
+                warn!("physx failed to create a character controller");
+                return None;
+            }
+            Some(PhysicsCharacterController {
+                inner: Arc::new(ControllerInner {
+                    controller: PxPtr(controller),
+                    scene: self.scene.clone(),
+                    state: Mutex::new(ControllerState::new(radius, height)),
+                }),
+            })
         }
     }
 
-    pub fn create_box_collider_static(&self, position: Vec3, size: Vec3, material: Materials) -> Option<PhysicsBoxColliderStatic> {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        // physx defines the center of the box as the center of the bottom face
-        // ht2 defines the center of the box as the top right of the bottom face
-        let position = position;
-        let size = size;
-
-        let transform = PxTransform {
-            p: PxVec3 {
-                x: position.x,
-                y: position.y,
-                z: position.z,
+    // adds a newly created actor to this scene, it's removed again when the last handle to it is dropped
+    fn add_actor(&self, actor: *mut PxRigidActor) -> Arc<SceneActor> {
+        let lock = self.scene.lock.lock().unwrap();
+        unsafe {
+            PxScene_addActor_mut(self.scene.scene.0, actor as *mut PxActor, null_mut());
+        }
+        drop(lock);
+        Arc::new(SceneActor {
+            actor: PxPtr(actor),
+            scene: self.scene.clone(),
+        })
+    }
+
+    // the physx geometry for a shape, and how the shape sits on its actor
+    fn shape_geometry(&self, game_data_path: &str, shape: &ColliderShape) -> Option<(ShapeGeometry, PxTransform)> {
+        let local_pose = px_transform(Vec3::zero());
+        let foundation = &self.scene.foundation;
+        match shape {
+            ColliderShape::Box(size) => {
+                let geometry = unsafe { PxBoxGeometry_new(size.x.abs() / 2.0, size.y.abs() / 2.0, size.z.abs() / 2.0) };
+                Some((ShapeGeometry::Box(geometry), local_pose))
+            }
+            ColliderShape::Sphere(radius) if *radius > 0.0 => {
+                Some((ShapeGeometry::Sphere(unsafe { PxSphereGeometry_new(*radius) }), local_pose))
+            }
+            ColliderShape::Capsule(radius, height) if *radius > 0.0 && *height >= 0.0 => {
+                let geometry = unsafe { PxCapsuleGeometry_new(*radius, *height / 2.0) };
+                // physx capsules lie along x, stand it up
+                let upright = Quaternion::from_euler_angles_zyx(&Vec3::new(0.0, 0.0, 90.0));
+                Some((ShapeGeometry::Capsule(geometry), px_transform_rotated(Vec3::zero(), upright)))
+            }
+            ColliderShape::Convex(mesh_name, scale) => match foundation.cooked_mesh(game_data_path, mesh_name, true)? {
+                CookedMesh::Convex(mesh) => {
+                    let scale = unsafe { PxMeshScale_new_2(&px_vec3(*scale)) };
+                    let geometry = unsafe { PxConvexMeshGeometry_new(mesh.0, &scale, PxConvexMeshGeometryFlags::from_bits(0).unwrap()) };
+                    Some((ShapeGeometry::Convex(geometry), local_pose))
+                }
+                CookedMesh::Triangles(_) => None,
             },
-            q: PxQuat {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
+            ColliderShape::Mesh(mesh_name, scale) => match foundation.cooked_mesh(game_data_path, mesh_name, false)? {
+                CookedMesh::Triangles(mesh) => {
+                    let scale = unsafe { PxMeshScale_new_2(&px_vec3(*scale)) };
+                    let geometry = unsafe { PxTriangleMeshGeometry_new(mesh.0, &scale, PxMeshGeometryFlags::from_bits(0).unwrap()) };
+                    Some((ShapeGeometry::Mesh(geometry), local_pose))
+                }
+                CookedMesh::Convex(_) => None,
             },
-        };
-
-        let geometry = unsafe { PxBoxGeometry_new(size.x / 2.0, size.y / 2.0, size.z / 2.0) };
-
-        let material = self.physics_materials.get(&material).unwrap();
+            _ => None,
+        }
+    }
 
-        let box_actor = unsafe { PxPhysics_createRigidStatic_mut(*self.physics.lock().unwrap(), &transform) };
-        let shape_flags = PxShapeFlag::SimulationShape as u8 | PxShapeFlag::SceneQueryShape as u8;
+    // a static actor with a single shape
+    fn create_static_actor(&self, transform: PxTransform, geometry: &ShapeGeometry, local_pose: PxTransform, material: *mut PxMaterial, shape_flags: u8) -> *mut PxRigidStatic {
+        let foundation = &self.scene.foundation;
+        let lock = foundation.lock.lock().unwrap();
+        let actor = unsafe { PxPhysics_createRigidStatic_mut(foundation.physics.0, &transform) };
         let shape_flags = PxShapeFlags::from_bits(shape_flags).unwrap();
-        let box_shape = unsafe {
-            PxPhysics_createShape_mut(
-                *self.physics.lock().unwrap(),
-                &geometry as *const PxBoxGeometry as *const PxGeometry,
-                material.material, true, shape_flags)
+        let shape = unsafe {
+            PxPhysics_createShape_mut(foundation.physics.0, geometry.as_ptr(), material, true, shape_flags)
         };
 
         unsafe {
-            PxRigidActor_attachShape_mut(box_actor as *mut PxRigidActor, box_shape);
+            PxShape_setLocalPose_mut(shape, &local_pose);
+            PxRigidActor_attachShape_mut(actor as *mut PxRigidActor, shape);
+            // the actor holds its own reference to the shape
+            PxShape_release_mut(shape);
         }
-
         drop(lock);
-        Some(PhysicsBoxColliderStatic {
-            actor: box_actor,
-            shape: box_shape,
-            ref_count: Arc::new(Default::default()),
+        actor
+    }
+
+    /// a collider that never moves. only Convex and Mesh shapes need game_data_path
+    pub fn create_collider_static(&self, game_data_path: &str, shape: &ColliderShape, position: Vec3, rotation: Quaternion, material: Materials) -> Option<PhysicsColliderStatic> {
+        let (geometry, local_pose) = self.shape_geometry(game_data_path, shape)?;
+        let shape_flags = PxShapeFlag::SimulationShape as u8 | PxShapeFlag::SceneQueryShape as u8;
+        let material = self.scene.foundation.material(&material);
+        let actor = self.create_static_actor(px_transform_rotated(position, rotation), &geometry, local_pose, material, shape_flags);
+        Some(PhysicsColliderStatic {
+            actor: self.add_actor(actor as *mut PxRigidActor),
         })
     }
 
-    pub fn create_sphere_actor(&self, position: Vec3, radius: f32, material: Materials) -> Option<PhysicsSphereColliderDynamic> {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        // physx defines the center of the box as the center of the bottom face
-        // ht2 defines the center of the box as the top right of the bottom face
-        let position = position;
-        let radius = radius;
-
-        let transform = PxTransform {
-            p: PxVec3 {
-                x: position.x,
-                y: position.y,
-                z: position.z,
-            },
-            q: PxQuat {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
-            },
+    /// a body that's moved by the simulation, or by set_kinematic_target if it's kinematic
+    pub fn create_rigid_body(&self, game_data_path: &str, body: &RigidBodyDesc, position: Vec3, rotation: Quaternion) -> Option<PhysicsRigidBody> {
+        // physx can't simulate triangle meshes
+        if matches!(body.shape, ColliderShape::Mesh(..)) {
+            return None;
+        }
+        let (geometry, local_pose) = self.shape_geometry(game_data_path, &body.shape)?;
+        let foundation = &self.scene.foundation;
+        let material = foundation.custom_material(body.friction, body.restitution);
+        let lock = foundation.lock.lock().unwrap();
+        let actor = unsafe {
+            phys_PxCreateDynamic(foundation.physics.0,
+                                 &px_transform_rotated(position, rotation),
+                                 geometry.as_ptr(),
+                                 material,
+                                 1.0,
+                                 &local_pose,
+            )
         };
+        if actor.is_null() {
+            return None;
+        }
+        unsafe {
+            // it was created with a density of 1, scale that up (or down) to the mass that was asked for
+            let rigid_body = actor as *mut PxRigidBody;
+            let unit_mass = PxRigidBody_getMass(rigid_body);
+            if body.mass > 0.0 && unit_mass > 0.0 {
+                let inertia = PxRigidBody_getMassSpaceInertiaTensor(rigid_body);
+                PxRigidBody_setMass_mut(rigid_body, body.mass);
+                PxRigidBody_setMassSpaceInertiaTensor_mut(rigid_body, &px_vec3(Vec3::new(inertia.x, inertia.y, inertia.z) * (body.mass / unit_mass)));
+            }
+            PxRigidBody_setAngularDamping_mut(rigid_body, 0.5);
+            if body.kinematic {
+                PxRigidBody_setRigidBodyFlag_mut(rigid_body, PxRigidBodyFlag::Kinematic, true);
+            }
+        }
+        drop(lock);
+        Some(PhysicsRigidBody {
+            actor: self.add_actor(actor as *mut PxRigidActor),
+            kinematic: body.kinematic,
+        })
+    }
 
+    pub fn create_sphere_actor(&self, position: Vec3, radius: f32, material: Materials) -> Option<PhysicsSphereColliderDynamic> {
+        let foundation = &self.scene.foundation;
+        let lock = foundation.lock.lock().unwrap();
         let geometry = unsafe { PxSphereGeometry_new(radius * 2.0) };
 
-        let material = self.physics_materials.get(&material).unwrap();
-
         let actor = unsafe {
-            phys_PxCreateDynamic(*self.physics.lock().unwrap(),
-                                 &transform,
+            phys_PxCreateDynamic(foundation.physics.0,
+                                 &px_transform(position),
                                  &geometry as *const PxSphereGeometry as *const PxGeometry,
-                                 material.material,
+                                 foundation.material(&material),
                                  1.0,
                                  &PxTransform_new_2(PxIDENTITY::PxIdentity),
             )
@@ -281,56 +823,95 @@ impl PhysicsSystem {
 
         drop(lock);
         Some(PhysicsSphereColliderDynamic {
-            actor,
-            ref_count: Arc::new(Default::default()),
+            actor: self.add_actor(actor as *mut PxRigidActor),
         })
     }
 
-    pub fn create_trigger_shape(&self, position: Vec3, size: Vec3, material: Materials) -> Option<PhysicsTriggerShape> {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+    pub fn create_trigger_shape(&self, position: Vec3, rotation: Quaternion, size: Vec3, material: Materials) -> Option<PhysicsTriggerShape> {
         // physx defines the center of the box as the center of the bottom face
-        // ht2 defines the center of the box as the top right of the bottom face
-        let position = position + Vec3::new(size.x / 2.0, size.y / 2.0, -size.z / 2.0);
-        let size = size;
-
-        let transform = PxTransform {
-            p: PxVec3 {
-                x: position.x,
-                y: position.y,
-                z: position.z,
-            },
-            q: PxQuat {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
-            },
-        };
-
-        let geometry = unsafe { PxBoxGeometry_new(size.x / 2.0, size.y / 2.0, size.z / 2.0) };
-
-        let material = self.physics_materials.get(&material).unwrap();
+        // ht2 defines the center of the box as the top right of the bottom face, so the box hangs off that corner and turns about it
+        let corner = Vec3::new(size.x / 2.0, size.y / 2.0, -size.z / 2.0);
+        let geometry = ShapeGeometry::Box(unsafe { PxBoxGeometry_new(size.x.abs() / 2.0, size.y.abs() / 2.0, size.z.abs() / 2.0) });
+        let material = self.scene.foundation.material(&material);
+        let box_actor = self.create_static_actor(px_transform_rotated(position, rotation), &geometry, px_transform(corner), material, PxShapeFlag::TriggerShape as u8);
+        Some(PhysicsTriggerShape {
+            actor: self.add_actor(box_actor as *mut PxRigidActor),
+        })
+    }
 
-        let box_actor = unsafe { PxPhysics_createRigidStatic_mut(*self.physics.lock().unwrap(), &transform) };
-        let shape_flags = PxShapeFlag::TriggerShape as u8;
-        let shape_flags = PxShapeFlags::from_bits(shape_flags).unwrap();
-        let box_shape = unsafe {
-            PxPhysics_createShape_mut(
-                *self.physics.lock().unwrap(),
-                &geometry as *const PxBoxGeometry as *const PxGeometry,
-                material.material, true, shape_flags)
+    /// the closest thing along a line, ignoring anything belonging to `ignore` (usually whoever's looking)
+    /// triggers are never hit
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32, ignore: Option<ActorOwner>) -> Option<QueryHit> {
+        if direction == Vec3::zero() || max_distance <= 0.0 {
+            return None;
+        }
+        let direction = px_vec3(*direction.normalize().deref());
+        let mut hits = (0..QUERY_BUFFER_SIZE).map(|_| unsafe { std::mem::zeroed::<PxRaycastHit>() }).collect::<Vec<PxRaycastHit>>();
+        let mut blocking = false;
+        let lock = self.scene.lock.lock().unwrap();
+        let count = unsafe {
+            PxSceneQueryExt_raycastMultiple(self.scene.scene.0, &px_vec3(origin), &direction, max_distance,
+                                            PxHitFlags::from_bits(PxHitFlag::Default as u16).unwrap(),
+                                            hits.as_mut_ptr(), QUERY_BUFFER_SIZE as u32, &mut blocking,
+                                            &query_filter(), null_mut(), null())
         };
+        drop(lock);
+        // -1 means there were more hits than would fit, the buffer is still full of valid ones
+        let count = if count < 0 { QUERY_BUFFER_SIZE } else { count as usize };
+        closest_hit(&self.scene, hits[..count].iter().map(|hit| (hit.actor, hit.position, hit.normal, hit.distance)), ignore)
+    }
 
-        unsafe {
-            PxRigidActor_attachShape_mut(box_actor as *mut PxRigidActor, box_shape);
+    /// the first thing a shape would run into if it was moved along a line, ignoring anything belonging to `ignore`
+    /// triangle meshes can't be swept
+    pub fn sweep(&self, game_data_path: &str, shape: &ColliderShape, origin: Vec3, direction: Vec3, max_distance: f32, ignore: Option<ActorOwner>) -> Option<QueryHit> {
+        if direction == Vec3::zero() || max_distance <= 0.0 || matches!(shape, ColliderShape::Mesh(..)) {
+            return None;
         }
+        let (geometry, local_pose) = self.shape_geometry(game_data_path, shape)?;
+        let pose = PxTransform { p: px_vec3(origin), q: local_pose.q };
+        let direction = px_vec3(*direction.normalize().deref());
+        let mut hits = (0..QUERY_BUFFER_SIZE).map(|_| unsafe { std::mem::zeroed::<PxSweepHit>() }).collect::<Vec<PxSweepHit>>();
+        let mut blocking = false;
+        let lock = self.scene.lock.lock().unwrap();
+        let count = unsafe {
+            PxSceneQueryExt_sweepMultiple(self.scene.scene.0, geometry.as_ptr(), &pose, &direction, max_distance,
+                                          PxHitFlags::from_bits(PxHitFlag::Default as u16).unwrap(),
+                                          hits.as_mut_ptr(), QUERY_BUFFER_SIZE as u32, &mut blocking,
+                                          &query_filter(), null_mut(), null(), 0.0)
+        };
+        drop(lock);
+        let count = if count < 0 { QUERY_BUFFER_SIZE } else { count as usize };
+        closest_hit(&self.scene, hits[..count].iter().map(|hit| (hit.actor, hit.position, hit.normal, hit.distance)), ignore)
+    }
 
+    /// everything that owns something overlapping a shape at `position`, apart from `ignore`
+    /// things that don't belong to anything aren't included, and triangle meshes can't be used as the shape
+    pub fn overlap(&self, game_data_path: &str, shape: &ColliderShape, position: Vec3, ignore: Option<ActorOwner>) -> Vec<ActorOwner> {
+        if matches!(shape, ColliderShape::Mesh(..)) {
+            return Vec::new();
+        }
+        let (geometry, local_pose) = match self.shape_geometry(game_data_path, shape) {
+            Some(geometry) => geometry,
+            None => return Vec::new(),
+        };
+        let pose = PxTransform { p: px_vec3(position), q: local_pose.q };
+        let mut hits = (0..QUERY_BUFFER_SIZE).map(|_| unsafe { std::mem::zeroed::<PxOverlapHit>() }).collect::<Vec<PxOverlapHit>>();
+        let lock = self.scene.lock.lock().unwrap();
+        let count = unsafe {
+            PxSceneQueryExt_overlapMultiple(self.scene.scene.0, geometry.as_ptr(), &pose,
+                                            hits.as_mut_ptr(), QUERY_BUFFER_SIZE as u32,
+                                            &query_filter(), null_mut())
+        };
         drop(lock);
-        Some(PhysicsTriggerShape {
-            actor: box_actor,
-            shape: box_shape,
-            ref_count: Arc::new(Default::default()),
-        })
+        let count = if count < 0 { QUERY_BUFFER_SIZE } else { count as usize };
+        let mut owners = Vec::new();
+        for hit in hits[..count].iter() {
+            match self.scene.callback_data.actor_owner(hit.actor as *const PxActor) {
+                Some(owner) if Some(owner) != ignore && !owners.contains(&owner) => owners.push(owner),
+                _ => {}
+            }
+        }
+        owners
     }
 }
 
@@ -358,23 +939,75 @@ impl CollisionFlags {
     }
 }
 
-#[derive(Clone)]
-pub struct PhysicsCharacterController {
-    pub controller: Arc<Mutex<*mut PxController>>,
-    pub flags: Arc<Mutex<CollisionFlags>>,
-    y_velocity: Arc<UnsafeCell<f32>>,
+/// how a character controller deals with gravity, see move_by
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MovementMode {
+    #[default]
+    Walk,
+    Climb, // on a ladder, there's no gravity and whatever vertical movement it's given is used as is
+    Swim(f32), // in water of this buoyancy. 1.0 neither sinks nor floats, more than that bobs up to the surface
 }
 
-unsafe impl Send for PhysicsCharacterController {}
+struct ControllerState {
+    flags: CollisionFlags,
+    y_velocity: f32,
+    mode: MovementMode,
+    crouch_height: Option<f32>, // None when standing
+    ground_velocity: Vec3, // how fast whatever it's standing on is carrying it, kept while it's in the air
+    gravity: f32,
+    jump_velocity: f32,
+    terminal_velocity: f32,
+    radius: f32,
+    height: f32, // standing height, crouching shrinks it
+}
 
-unsafe impl Sync for PhysicsCharacterController {}
+impl ControllerState {
+    fn new(radius: f32, height: f32) -> Self {
+        Self {
+            flags: CollisionFlags::default(),
+            y_velocity: 0.0,
+            mode: MovementMode::default(),
+            crouch_height: None,
+            ground_velocity: Vec3::zero(),
+            gravity: PLAYER_GRAVITY,
+            jump_velocity: PLAYER_JUMP_VELOCITY,
+            terminal_velocity: PLAYER_TERMINAL_VELOCITY,
+            radius,
+            height,
+        }
+    }
+}
+
+struct ControllerInner {
+    controller: PxPtr<PxController>,
+    scene: Arc<PhysicsScene>,
+    state: Mutex<ControllerState>,
+}
+
+impl Drop for ControllerInner {
+    fn drop(&mut self) {
+        let lock = self.scene.lock.lock().unwrap();
+        unsafe {
+            self.scene.callback_data.clear_actor_owner(PxController_getActor(self.controller.0) as *mut PxActor);
+            PxController_release_mut(self.controller.0);
+        }
+        drop(lock);
+    }
+}
+
+/// a capsule character controller, removed from its scene when the last clone of it is dropped
+#[derive(Clone)]
+pub struct PhysicsCharacterController {
+    inner: Arc<ControllerInner>,
+}
 
 impl PhysicsCharacterController {
     pub fn move_by(&mut self, displacement: Vec3, jump: bool, server: Option<bool>, cheat: bool, delta_time: f32, frame_delta: f32) -> Vec3 {
-        let lock = PHYSICS_LOCK.lock().unwrap();
         if delta_time <= 0.0 || frame_delta <= 0.0 {
             return Vec3::zero();
         }
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let mut state = self.inner.state.lock().unwrap();
 
         let mut displacement = PxVec3 {
             x: displacement.x,
@@ -388,317 +1021,699 @@ impl PhysicsCharacterController {
             true
         };
 
-        if jump && self.is_on_ground() {
-            unsafe {
-                *self.y_velocity.get() = PLAYER_JUMP_VELOCITY;
-            }
-        } else if !self.is_on_ground() {
-            if do_gravity {
-                let gravity = PLAYER_GRAVITY;
-                let mut velocity = unsafe { *self.y_velocity.get() };
-                velocity += gravity * delta_time;
-                velocity = velocity.clamp(-PLAYER_TERMINAL_VELOCITY, PLAYER_TERMINAL_VELOCITY);
-                unsafe {
-                    *self.y_velocity.get() = velocity;
+        let on_ground = state.flags.colliding_bottom;
+        match state.mode {
+            MovementMode::Walk => {
+                if jump && on_ground {
+                    state.y_velocity = state.jump_velocity;
+                } else if !on_ground {
+                    if do_gravity {
+                        let gravity = state.gravity;
+                        let velocity = state.y_velocity + gravity * delta_time;
+                        state.y_velocity = velocity.clamp(-state.terminal_velocity, state.terminal_velocity);
+                    }
+                } else if cheat {
+                    state.y_velocity = 100.0;
                 }
             }
-        } else if cheat {
-            unsafe {
-                *self.y_velocity.get() = 100.0;
+            MovementMode::Climb => state.y_velocity = 0.0,
+            MovementMode::Swim(buoyancy) => {
+                if jump {
+                    state.y_velocity = PLAYER_SWIM_VELOCITY;
+                } else if do_gravity {
+                    // sinks or floats depending on the water, and the water slows it down either way
+                    let velocity = state.y_velocity + state.gravity * (1.0 - buoyancy) * delta_time;
+                    state.y_velocity = velocity * (1.0 - WATER_DRAG * delta_time).max(0.0);
+                }
             }
         }
 
-        if do_gravity {
-            displacement.y = unsafe { *self.y_velocity.get() };
+        if do_gravity && state.mode != MovementMode::Climb {
+            displacement.y = state.y_velocity;
             displacement.y *= delta_time;
         }
 
+        // moving platforms carry it along, and it keeps going sideways when it jumps or walks off one
+        match state.mode {
+            MovementMode::Walk if on_ground => state.ground_velocity = unsafe { self.carry_velocity() },
+            MovementMode::Walk => state.ground_velocity.y = 0.0,
+            _ => state.ground_velocity = Vec3::zero(),
+        }
+        // the carrying isn't part of what it returns, that's only the movement it was asked for
+        let carried = PxVec3 {
+            x: displacement.x + state.ground_velocity.x * delta_time,
+            y: displacement.y + state.ground_velocity.y * delta_time,
+            z: displacement.z + state.ground_velocity.z * delta_time,
+        };
+
         unsafe {
-            let flags = PxController_move_mut(*self.controller.lock().unwrap(),
-                                              &displacement,
+            let flags = PxController_move_mut(self.inner.controller.0,
+                                              &carried,
                                               0.0,
                                               delta_time,
                                               &PxControllerFilters_new(null_mut(), null_mut(), null_mut()), null_mut());
-            *self.flags.lock().unwrap() = CollisionFlags::from_bits(flags.bits());
+            state.flags = CollisionFlags::from_bits(flags.bits());
         }
 
+        drop(state);
         drop(lock);
         Vec3::new(displacement.x / frame_delta, displacement.y / delta_time, displacement.z / frame_delta)
     }
 
+    pub fn set_owner(&self, owner: ActorOwner) {
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let actor = unsafe { PxController_getActor(self.inner.controller.0) };
+        drop(lock);
+        self.inner.scene.callback_data.set_actor_owner(actor as *mut PxActor, owner);
+    }
+
+    pub fn clear_owner(&self) {
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let actor = unsafe { PxController_getActor(self.inner.controller.0) };
+        drop(lock);
+        self.inner.scene.callback_data.clear_actor_owner(actor as *mut PxActor);
+    }
+
     pub fn is_on_ground(&self) -> bool {
-        let flags = self.flags.lock().unwrap();
-        flags.colliding_bottom
+        self.inner.state.lock().unwrap().flags.colliding_bottom
+    }
+
+    pub fn movement_mode(&self) -> MovementMode {
+        self.inner.state.lock().unwrap().mode
+    }
+
+    pub fn set_movement_mode(&self, mode: MovementMode) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.mode != mode {
+            // don't carry a fall into the water, or off the end of a ladder
+            state.y_velocity = 0.0;
+            state.mode = mode;
+        }
+    }
+
+    pub fn is_crouching(&self) -> bool {
+        self.inner.state.lock().unwrap().crouch_height.is_some()
+    }
+
+    /// how it falls and jumps, see MovementSettings
+    pub fn set_gravity(&self, gravity: f32, jump_velocity: f32, terminal_velocity: f32) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.gravity = gravity;
+        state.jump_velocity = jump_velocity;
+        state.terminal_velocity = terminal_velocity;
+    }
+
+    /// resizes the capsule, its feet stay where they are. if it's crouching it stays crouched
+    pub fn set_dimensions(&self, radius: f32, height: f32, step_offset: f32) {
+        // same order as move_by, scene then state
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let mut state = self.inner.state.lock().unwrap();
+        state.radius = radius;
+        state.height = height;
+        state.crouch_height = state.crouch_height.map(|crouch_height| crouch_height.min(height));
+        let current_height = state.crouch_height.unwrap_or(height);
+        unsafe {
+            PxCapsuleController_setRadius_mut(self.inner.controller.0 as *mut PxCapsuleController, radius);
+            PxController_setStepOffset_mut(self.inner.controller.0, step_offset);
+            PxController_resize_mut(self.inner.controller.0, current_height);
+        }
+        drop(state);
+        drop(lock);
+    }
+
+    /// shrinks the capsule to `height`, its feet stay where they are
+    pub fn crouch(&self, height: f32) {
+        if self.is_crouching() || height >= self.inner.state.lock().unwrap().height {
+            return;
+        }
+        let lock = self.inner.scene.lock.lock().unwrap();
+        unsafe {
+            PxController_resize_mut(self.inner.controller.0, height);
+        }
+        drop(lock);
+        self.inner.state.lock().unwrap().crouch_height = Some(height);
+    }
+
+    /// grows the capsule back to the height it was made with, if there's room above it. returns whether it's standing
+    pub fn stand(&self) -> bool {
+        let (crouch_height, radius, height) = {
+            let state = self.inner.state.lock().unwrap();
+            match state.crouch_height {
+                Some(crouch_height) => (crouch_height, state.radius, state.height),
+                None => return true,
+            }
+        };
+        // sweep from the top of the crouched capsule up to where the top of the standing one would be
+        let pose = px_transform(self.get_position() + Vec3::new(0.0, crouch_height / 2.0, 0.0));
+        let up = px_vec3(Vec3::new(0.0, 1.0, 0.0));
+        let geometry = ShapeGeometry::Sphere(unsafe { PxSphereGeometry_new(radius * HEADROOM_SKIN) });
+        let mut hits = (0..QUERY_BUFFER_SIZE).map(|_| unsafe { std::mem::zeroed::<PxSweepHit>() }).collect::<Vec<PxSweepHit>>();
+        let mut blocking = false;
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let own_actor = unsafe { PxController_getActor(self.inner.controller.0) } as *mut PxRigidActor;
+        let count = unsafe {
+            PxSceneQueryExt_sweepMultiple(self.inner.scene.scene.0, geometry.as_ptr(), &pose, &up,
+                                          height - crouch_height,
+                                          PxHitFlags::from_bits(PxHitFlag::Default as u16).unwrap(),
+                                          hits.as_mut_ptr(), QUERY_BUFFER_SIZE as u32, &mut blocking,
+                                          &query_filter(), null_mut(), null(), 0.0)
+        };
+        let count = if count < 0 { QUERY_BUFFER_SIZE } else { count as usize };
+        if hits[..count].iter().any(|hit| hit.actor != own_actor) {
+            drop(lock);
+            return false;
+        }
+        unsafe {
+            PxController_resize_mut(self.inner.controller.0, height);
+        }
+        drop(lock);
+        self.inner.state.lock().unwrap().crouch_height = None;
+        true
+    }
+
+    /// how fast whatever it was standing on during the last move_by was carrying it
+    pub fn ground_velocity(&self) -> Vec3 {
+        self.inner.state.lock().unwrap().ground_velocity
+    }
+
+    /// the surface it's standing on, None if it's in the air or on something without one (like another player)
+    pub fn ground_material(&self) -> Option<SurfaceMaterial> {
+        if !self.is_on_ground() {
+            return None;
+        }
+        let lock = self.inner.scene.lock.lock().unwrap();
+        let material = unsafe {
+            let mut material = null_mut();
+            match self.probe_ground() {
+                Some(hit) if !hit.shape.is_null() && PxShape_getMaterials(hit.shape, &mut material, 1, 0) == 1 => Some(material),
+                _ => None,
+            }
+        };
+        drop(lock);
+        self.inner.scene.foundation.surface_of(material?)
+    }
+
+    // what's just under its feet, the scene lock has to be held
+    unsafe fn probe_ground(&self) -> Option<PxRaycastHit> {
+        // the foot position is a little below the capsule, so the ray can't hit the controller itself
+        let foot = PxController_getFootPosition(self.inner.controller.0);
+        let origin = PxVec3 { x: foot.x as f32, y: foot.y as f32 + 0.05, z: foot.z as f32 };
+        let down = px_vec3(Vec3::new(0.0, -1.0, 0.0));
+        let mut hit = PxRaycastHit_new();
+        let filter = PxQueryFilterData_new();
+        let hit_flags = PxHitFlags::from_bits(PxHitFlag::Default as u16).unwrap();
+        let found = PxSceneQueryExt_raycastSingle(self.inner.scene.scene.0, &origin, &down, GROUND_PROBE_DISTANCE, hit_flags, &mut hit, &filter, null_mut(), null());
+        found.then_some(hit)
+    }
+
+    // the carry velocity of whatever's under its feet, the scene lock has to be held
+    unsafe fn carry_velocity(&self) -> Vec3 {
+        let velocities = self.inner.scene.carry_velocities.lock().unwrap();
+        if velocities.is_empty() {
+            return Vec3::zero();
+        }
+        match self.probe_ground() {
+            Some(hit) => velocities.get(&(hit.actor as usize)).copied().unwrap_or_default(),
+            None => Vec3::zero(),
+        }
     }
 
     pub fn get_position(&self) -> Vec3 {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+        let lock = self.inner.scene.lock.lock().unwrap();
         let position = unsafe {
-            PxController_getPosition(*self.controller.lock().unwrap())
+            *PxController_getPosition(self.inner.controller.0)
         };
-        let x = unsafe { (*position).x };
-        let y = unsafe { (*position).y };
-        let z = unsafe { (*position).z };
         drop(lock);
-        Vec3::new(x as f32, y as f32, z as f32)
+        Vec3::new(position.x as f32, position.y as f32, position.z as f32)
     }
 
     pub fn get_foot_position(&self) -> Vec3 {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+        let lock = self.inner.scene.lock.lock().unwrap();
         let position = unsafe {
-            PxController_getFootPosition(*self.controller.lock().unwrap())
+            PxController_getFootPosition(self.inner.controller.0)
         };
-        let x = (position).x;
-        let y = (position).y;
-        let z = (position).z;
         drop(lock);
-        Vec3::new(x as f32, y as f32, z as f32)
+        Vec3::new(position.x as f32, position.y as f32, position.z as f32)
     }
 
     pub fn set_position(&self, position: Vec3) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
         let position = PxExtendedVec3 {
             x: position.x as f64,
             y: position.y as f64,
             z: position.z as f64,
         };
+        let lock = self.inner.scene.lock.lock().unwrap();
         unsafe {
-            PxController_setPosition_mut(*self.controller.lock().unwrap(), &position);
+            PxController_setPosition_mut(self.inner.controller.0, &position);
         }
         drop(lock);
     }
 
     pub fn set_foot_position(&self, position: Vec3) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
         let position = PxExtendedVec3 {
             x: position.x as f64,
             y: position.y as f64,
             z: position.z as f64,
         };
+        let lock = self.inner.scene.lock.lock().unwrap();
         unsafe {
-            PxController_setFootPosition_mut(*self.controller.lock().unwrap(), &position);
+            PxController_setFootPosition_mut(self.inner.controller.0, &position);
         }
         drop(lock);
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Materials {
-    Player,
+    Player, // frictionless, so character controllers don't stick to walls
+    Surface(String), // a named material from materials.yaml
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct PhysicsMaterial {
-    pub material: *mut PxMaterial,
+impl Materials {
+    /// the named surface, or "default" if the name is empty
+    pub fn surface(name: &str) -> Self {
+        if name.is_empty() {
+            Materials::Surface("default".to_string())
+        } else {
+            Materials::Surface(name.to_string())
+        }
+    }
 }
 
-unsafe impl Send for PhysicsMaterial {}
-
-unsafe impl Sync for PhysicsMaterial {}
+/// a named surface from <game_data_path>/materials.yaml
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SurfaceMaterial {
+    #[serde(skip)]
+    pub name: String,
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    pub restitution: f32,
+    pub speed: f32, // multiplies how fast players can walk on it
+    pub footstep: String, // sound in snd/ for walking on it, empty for none
+}
 
-pub struct PhysicsBoxColliderStatic {
-    pub actor: *mut PxRigidStatic,
-    pub shape: *mut PxShape,
-    ref_count: Arc<AtomicUsize>,
+impl Default for SurfaceMaterial {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            static_friction: 0.5,
+            dynamic_friction: 0.5,
+            restitution: 0.0,
+            speed: 1.0,
+            footstep: String::new(),
+        }
+    }
 }
 
-unsafe impl Send for PhysicsBoxColliderStatic {}
+// an actor that's been added to a scene, removed and released when the last handle to it is dropped
+struct SceneActor {
+    actor: PxPtr<PxRigidActor>,
+    scene: Arc<PhysicsScene>,
+}
 
-unsafe impl Sync for PhysicsBoxColliderStatic {}
+impl SceneActor {
+    fn get_position(&self) -> Vec3 {
+        self.get_pose().0
+    }
 
-impl PhysicsBoxColliderStatic {
-    pub fn add_self_to_scene(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+    // sets REPORT_CONTACTS on the actor's shape, so filter_shader asks physx to tell on_contact about it
+    fn report_contacts(&self) {
+        let lock = self.scene.lock.lock().unwrap();
         unsafe {
-            PxScene_addActor_mut(physics.scene, self.actor as *mut PxActor, null_mut());
+            let mut shape = null_mut();
+            if PxRigidActor_getShapes(self.actor.0, &mut shape, 1, 0) == 1 {
+                let mut filter_data = PxShape_getSimulationFilterData(shape);
+                filter_data.word0 |= REPORT_CONTACTS;
+                PxShape_setSimulationFilterData_mut(shape, &filter_data);
+            }
         }
-        BOX_COLLIDERS.lock().unwrap().push(self.clone());
         drop(lock);
     }
 
-    /// # Safety
-    /// could cause a double free, use drop instead
-    pub unsafe fn remove_self(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+    fn get_pose(&self) -> (Vec3, Quaternion) {
+        let lock = self.scene.lock.lock().unwrap();
+        let pose = unsafe {
+            PxRigidActor_getGlobalPose(self.actor.0)
+        };
+        drop(lock);
+        (Vec3::new(pose.p.x, pose.p.y, pose.p.z), Quaternion::new(pose.q.x, pose.q.y, pose.q.z, pose.q.w))
+    }
+
+    fn set_position(&self, position: Vec3) {
+        let lock = self.scene.lock.lock().unwrap();
         unsafe {
-            PxScene_removeActor_mut(physics.scene, self.actor as *mut PxActor, false);
-            PxRigidActor_release_mut(self.actor as *mut PxRigidActor);
+            PxRigidActor_setGlobalPose_mut(self.actor.0, &px_transform(position), true);
         }
         drop(lock);
     }
 }
 
-impl Drop for PhysicsBoxColliderStatic {
+impl Drop for SceneActor {
     fn drop(&mut self) {
-        let ref_count = self.ref_count.fetch_sub(1, Ordering::SeqCst);
-        if ref_count == 0 {
-            unsafe {
-                self.remove_self(PHYSICS_SYSTEM.lock().unwrap().as_ref().unwrap().clone());
-            }
+        self.scene.callback_data.clear_actor_owner(self.actor.0 as *mut PxActor);
+        self.scene.carry_velocities.lock().unwrap().remove(&(self.actor.0 as usize));
+        let lock = self.scene.lock.lock().unwrap();
+        unsafe {
+            PxScene_removeActor_mut(self.scene.scene.0, self.actor.0 as *mut PxActor, false);
+            PxRigidActor_release_mut(self.actor.0);
         }
+        drop(lock);
     }
 }
 
-impl Clone for PhysicsBoxColliderStatic {
-    fn clone(&self) -> Self {
-        self.ref_count.fetch_add(1, Ordering::SeqCst);
-        Self {
-            actor: self.actor,
-            shape: self.shape,
-            ref_count: self.ref_count.clone(),
-        }
+/// a static collider of any shape, removed from its scene when the last clone of it is dropped
+#[derive(Clone)]
+pub struct PhysicsColliderStatic {
+    actor: Arc<SceneActor>,
+}
+
+impl PhysicsColliderStatic {
+    /// so that queries know which entity they hit
+    pub fn set_owner(&self, entity_id: EntityId) {
+        self.actor.scene.callback_data.set_actor_owner(self.actor.actor.0 as *mut PxActor, ActorOwner::Other(entity_id));
     }
 }
 
+/// a dynamic sphere, removed from its scene when the last clone of it is dropped
+#[derive(Clone)]
 pub struct PhysicsSphereColliderDynamic {
-    pub actor: *mut PxRigidDynamic,
-    ref_count: Arc<AtomicUsize>,
+    actor: Arc<SceneActor>,
 }
 
-unsafe impl Send for PhysicsSphereColliderDynamic {}
-
-unsafe impl Sync for PhysicsSphereColliderDynamic {}
-
 impl PhysicsSphereColliderDynamic {
-    pub fn add_self_to_scene(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        unsafe {
-            PxScene_addActor_mut(physics.scene, self.actor as *mut PxActor, null_mut());
-        }
-        drop(lock);
-    }
-
     pub fn set_velocity(&self, velocity: Vec3) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
         let velocity = PxVec3 {
             x: velocity.x,
             y: velocity.y,
             z: velocity.z,
         };
 
+        let lock = self.actor.scene.lock.lock().unwrap();
         unsafe {
-            PxRigidBody_addForce_mut(self.actor as *mut PxRigidBody, &velocity,
+            PxRigidBody_addForce_mut(self.actor.actor.0 as *mut PxRigidBody, &velocity,
                                      PxForceMode::VelocityChange, true);
         }
         drop(lock);
     }
 
-    /// # Safety
-    /// could cause a double free, use drop instead
-    pub unsafe fn remove_self(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        unsafe {
-            PxScene_removeActor_mut(physics.scene, self.actor as *mut PxActor, false);
-            PxRigidActor_release_mut(self.actor as *mut PxRigidActor);
-        }
-        drop(lock);
-    }
-
     pub fn get_position(&self) -> Vec3 {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let position = unsafe {
-            PxRigidActor_getGlobalPose(self.actor as *const PxRigidActor).p
-        };
-        let x = (position).x;
-        let y = (position).y;
-        let z = (position).z;
-        drop(lock);
-        Vec3::new(x, y, z)
+        self.actor.get_position()
     }
 
     pub fn set_position(&self, position: Vec3) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
-        let position = PxTransform {
-            p: PxVec3 {
-                x: position.x,
-                y: position.y,
-                z: position.z,
-            },
-            q: PxQuat {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
-            },
-        };
-        unsafe {
-            PxRigidActor_setGlobalPose_mut(self.actor as *mut PxRigidActor, &position, true);
-        }
-        drop(lock);
+        self.actor.set_position(position)
     }
-}
 
-impl Drop for PhysicsSphereColliderDynamic {
-    fn drop(&mut self) {
-        let ref_count = self.ref_count.fetch_sub(1, Ordering::SeqCst);
-        if ref_count == 0 {
-            unsafe {
-                self.remove_self(PHYSICS_SYSTEM.lock().unwrap().as_ref().unwrap().clone());
-            }
-        }
+    /// what contact events will say this is
+    pub fn set_owner(&self, owner: ActorOwner) {
+        self.actor.scene.callback_data.set_actor_owner(self.actor.actor.0 as *mut PxActor, owner);
     }
-}
 
-impl Clone for PhysicsSphereColliderDynamic {
-    fn clone(&self) -> Self {
-        self.ref_count.fetch_add(1, Ordering::SeqCst);
-        Self {
-            actor: self.actor,
-            ref_count: self.ref_count.clone(),
-        }
+    /// reports everything it bumps into, see drain_contact_events
+    pub fn report_contacts(&self) {
+        self.actor.report_contacts();
     }
 }
 
+/// a static trigger box, removed from its scene when the last clone of it is dropped
+#[derive(Clone)]
 pub struct PhysicsTriggerShape {
-    pub actor: *mut PxRigidStatic,
-    pub shape: *mut PxShape,
-    ref_count: Arc<AtomicUsize>,
+    actor: Arc<SceneActor>,
+}
+
+impl PhysicsTriggerShape {
+    pub fn set_owner(&self, entity_id: EntityId) {
+        self.actor.scene.callback_data.set_actor_owner(self.actor.actor.0 as *mut PxActor, ActorOwner::Trigger(entity_id));
+    }
+}
+
+/// a dynamic (or kinematic) body, removed from its scene when the last clone of it is dropped
+#[derive(Clone)]
+pub struct PhysicsRigidBody {
+    actor: Arc<SceneActor>,
+    kinematic: bool,
 }
 
-unsafe impl Send for PhysicsTriggerShape {}
+impl PhysicsRigidBody {
+    pub fn is_kinematic(&self) -> bool {
+        self.kinematic
+    }
 
-unsafe impl Sync for PhysicsTriggerShape {}
+    pub fn set_owner(&self, entity_id: EntityId) {
+        self.actor.scene.callback_data.set_actor_owner(self.actor.actor.0 as *mut PxActor, ActorOwner::Other(entity_id));
+    }
 
-impl PhysicsTriggerShape {
-    pub fn add_self_to_scene(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+    pub fn get_pose(&self) -> (Vec3, Quaternion) {
+        self.actor.get_pose()
+    }
+
+    /// teleports the body, use set_kinematic_target to move a kinematic body smoothly
+    pub fn set_pose(&self, position: Vec3, rotation: Quaternion) {
+        let lock = self.actor.scene.lock.lock().unwrap();
+        unsafe {
+            PxRigidActor_setGlobalPose_mut(self.actor.actor.0, &px_transform_rotated(position, rotation), true);
+        }
+        drop(lock);
+    }
+
+    /// where a kinematic body should be after the next tick, anything in the way gets pushed
+    pub fn set_kinematic_target(&self, position: Vec3, rotation: Quaternion) {
+        if !self.kinematic {
+            return;
+        }
+        let lock = self.actor.scene.lock.lock().unwrap();
         unsafe {
-            PxScene_addActor_mut(physics.scene, self.actor as *mut PxActor, null_mut());
+            PxRigidDynamic_setKinematicTarget_mut(self.actor.actor.0 as *mut PxRigidDynamic, &px_transform_rotated(position, rotation));
         }
-        TRIGGER_SHAPES.lock().unwrap().push(self.clone());
         drop(lock);
     }
 
-    /// # Safety
-    /// could cause a double free, use drop instead
-    pub unsafe fn remove_self(&self, physics: PhysicsSystem) {
-        let lock = PHYSICS_LOCK.lock().unwrap();
+    /// how fast character controllers standing on this get carried along, for moving platforms
+    /// physx doesn't move controllers with what's under them, so this has to be told separately from the kinematic target
+    pub fn set_carry_velocity(&self, velocity: Vec3) {
+        let mut velocities = self.actor.scene.carry_velocities.lock().unwrap();
+        if velocity == Vec3::zero() {
+            velocities.remove(&(self.actor.actor.0 as usize));
+        } else {
+            velocities.insert(self.actor.actor.0 as usize, velocity);
+        }
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        let lock = self.actor.scene.lock.lock().unwrap();
+        let sleeping = unsafe { PxRigidDynamic_isSleeping(self.actor.actor.0 as *const PxRigidDynamic) };
+        drop(lock);
+        sleeping
+    }
+
+    pub fn add_velocity(&self, velocity: Vec3) {
+        if self.kinematic {
+            return;
+        }
+        let lock = self.actor.scene.lock.lock().unwrap();
         unsafe {
-            PxScene_removeActor_mut(physics.scene, self.actor as *mut PxActor, false);
-            PxRigidActor_release_mut(self.actor as *mut PxRigidActor);
+            PxRigidBody_addForce_mut(self.actor.actor.0 as *mut PxRigidBody, &px_vec3(velocity), PxForceMode::VelocityChange, true);
         }
         drop(lock);
     }
 }
 
-impl Drop for PhysicsTriggerShape {
-    fn drop(&mut self) {
-        let ref_count = self.ref_count.fetch_sub(1, Ordering::SeqCst);
-        if ref_count == 0 {
-            unsafe {
-                self.remove_self(PHYSICS_SYSTEM.lock().unwrap().as_ref().unwrap().clone());
-            }
+/// physx for tests, every test shares one foundation but gets a scene to itself
+#[cfg(test)]
+pub mod testing {
+    use gfx_maths::{Quaternion, Vec3};
+    use super::{ColliderShape, Materials, PhysicsCharacterController, PhysicsColliderStatic, PhysicsSystem};
+    use crate::server::server_player::{DEFAULT_HEIGHT, DEFAULT_RADIUS, DEFAULT_STEPHEIGHT};
+
+    pub const STEP: f32 = 0.05;
+
+    lazy_static! {
+        // physx only allows one foundation per process
+        static ref PHYSICS: PhysicsSystem = {
+            let physics = PhysicsSystem::init();
+            physics.load_materials("base");
+            physics
+        };
+    }
+
+    /// for things that make their own scene from it, like servers
+    pub fn shared() -> PhysicsSystem {
+        PHYSICS.clone()
+    }
+
+    /// an empty scene, so a test can put things wherever it likes
+    pub fn scene() -> PhysicsSystem {
+        PHYSICS.copy_with_new_scene()
+    }
+
+    /// a player sized controller with its feet at `position`
+    pub fn controller(physics: &PhysicsSystem, position: Vec3) -> PhysicsCharacterController {
+        let controller = physics.create_character_controller(DEFAULT_RADIUS, DEFAULT_HEIGHT, DEFAULT_STEPHEIGHT, Materials::Player).unwrap();
+        controller.set_foot_position(position);
+        controller
+    }
+
+    /// a player sized controller that has landed on a wide floor made of `surface`, the top of the floor is at y = 0
+    /// the floor is only there for as long as it's kept
+    pub fn controller_on_floor(physics: &PhysicsSystem, surface: &str) -> (PhysicsCharacterController, PhysicsColliderStatic) {
+        let floor = physics.create_collider_static("base", &ColliderShape::Box(Vec3::new(40.0, 1.0, 40.0)), Vec3::new(0.0, -0.5, 0.0), Quaternion::identity(), Materials::surface(surface)).unwrap();
+        let mut controller = controller(physics, Vec3::new(0.0, 0.5, 0.0));
+        idle(&mut controller, 1.0);
+        (controller, floor)
+    }
+
+    /// lets it fall, float or stand there for a while
+    pub fn idle(controller: &mut PhysicsCharacterController, seconds: f32) {
+        for _ in 0..(seconds / STEP).round() as usize {
+            controller.move_by(Vec3::zero(), false, None, false, STEP, STEP);
         }
     }
 }
 
-impl Clone for PhysicsTriggerShape {
-    fn clone(&self) -> Self {
-        self.ref_count.fetch_add(1, Ordering::SeqCst);
-        Self {
-            actor: self.actor,
-            shape: self.shape,
-            ref_count: self.ref_count.clone(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::{controller, controller_on_floor, idle, scene, STEP};
+    use crate::server::server_player::DEFAULT_CROUCH_HEIGHT;
+
+    #[test]
+    fn controllers_know_what_they_are_standing_on() {
+        let physics = scene();
+        let (controller, _mud) = controller_on_floor(&physics, "mud");
+        let surface = controller.ground_material().expect("controller isn't standing on anything");
+        assert_eq!(surface.name, "mud");
+        assert!(surface.speed < 1.0);
+        assert!(surface.footstep.is_empty());
+
+        let bouncy = scene();
+        let (controller, _floor) = controller_on_floor(&bouncy, "bouncy");
+        assert_eq!(controller.ground_material().map(|surface| surface.footstep), Some("donk.wav".to_string()));
+    }
+
+    #[test]
+    fn queries_report_what_they_hit() {
+        let physics = scene();
+        let (player, _floor) = controller_on_floor(&physics, "");
+        let me = ActorOwner::Player(1);
+        player.set_owner(me);
+        let eye = player.get_foot_position() + Vec3::new(0.0, 2.0, 0.0);
+        let forward = Vec3::new(1.0, 0.0, 0.0);
+        let target = physics.create_collider_static("base", &ColliderShape::Sphere(0.5), eye + forward * 6.0, Quaternion::identity(), Materials::surface("")).unwrap();
+        target.set_owner(2);
+        physics.tick(STEP);
+
+        // this starts inside the player's own capsule, which is what it hits unless it's ignored
+        let hit = physics.raycast(eye, forward, 20.0, Some(me)).expect("raycast missed the target");
+        assert_eq!(hit.entity_id(), Some(2));
+        assert!((hit.distance - 5.5).abs() < 0.05, "raycast hit at {}", hit.distance);
+        assert!(hit.normal.x < -0.9, "raycast hit normal is {:?}", hit.normal);
+        assert_eq!(physics.raycast(eye, forward, 20.0, None).and_then(|hit| hit.owner), Some(me));
+        assert!(physics.raycast(eye, forward, 4.0, Some(me)).is_none());
+
+        let hit = physics.sweep("base", &ColliderShape::Sphere(0.25), eye, forward, 20.0, Some(me)).expect("sweep missed the target");
+        assert_eq!(hit.entity_id(), Some(2));
+        assert!((hit.distance - 5.25).abs() < 0.05, "sweep hit at {}", hit.distance);
+        // convex shapes get cooked the first time they're used, even when that's in a query
+        let banana = ColliderShape::Convex("banana".to_string(), Vec3::new(1.0, 1.0, 1.0));
+        assert!(physics.sweep("base", &banana, eye, forward, 20.0, Some(me)).is_some());
+
+        let overlapping = physics.overlap("base", &ColliderShape::Capsule(0.5, 1.0), eye + forward * 6.0, None);
+        assert!(overlapping.contains(&ActorOwner::Other(2)), "overlap found {:?}", overlapping);
+        assert!(!overlapping.contains(&me));
+    }
+
+    #[test]
+    fn meshes_are_cooked_when_colliders_need_them() {
+        let physics = scene();
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        assert!(physics.create_collider_static("base", &ColliderShape::Mesh("level".to_string(), one), Vec3::zero(), Quaternion::identity(), Materials::Player).is_some());
+        assert!(physics.create_collider_static("base", &ColliderShape::Convex("banana".to_string(), one), Vec3::zero(), Quaternion::identity(), Materials::Player).is_some());
+        assert!(physics.create_collider_static("base", &ColliderShape::Mesh("not_a_mesh".to_string(), one), Vec3::zero(), Quaternion::identity(), Materials::Player).is_none());
+    }
+
+    #[test]
+    fn controllers_crouch_climb_and_swim() {
+        let physics = scene();
+        let (mut controller, _floor) = controller_on_floor(&physics, "");
+        // low enough that only a crouching controller fits under it
+        let _ceiling = physics.create_collider_static("base", &ColliderShape::Box(Vec3::new(10.0, 1.0, 10.0)), Vec3::new(0.0, 4.4, 0.0), Quaternion::identity(), Materials::surface("")).unwrap();
+        controller.set_foot_position(Vec3::new(10.0, 0.5, 0.0));
+        idle(&mut controller, 1.0);
+        controller.crouch(DEFAULT_CROUCH_HEIGHT);
+        assert!(controller.is_crouching());
+        for _ in 0..20 {
+            controller.move_by(Vec3::new(-0.5, 0.0, 0.0), false, None, false, STEP, STEP);
+        }
+        assert!(controller.get_foot_position().x < 1.0, "crouching controller didn't fit under the ceiling");
+        assert!(!controller.stand(), "stood up into the ceiling");
+        for _ in 0..20 {
+            controller.move_by(Vec3::new(0.5, 0.0, 0.0), false, None, false, STEP, STEP);
         }
+        assert!(controller.stand(), "couldn't stand up in the open");
+        assert!(!controller.is_crouching());
+
+        // ladders hold it still until it's moved up them
+        let start = controller.get_foot_position();
+        controller.set_foot_position(start + Vec3::new(0.0, 5.0, 0.0));
+        controller.set_movement_mode(MovementMode::Climb);
+        idle(&mut controller, 1.0);
+        assert!((controller.get_foot_position().y - (start.y + 5.0)).abs() < 0.01, "fell off the ladder to {:?}", controller.get_foot_position());
+        for _ in 0..20 {
+            controller.move_by(Vec3::new(0.0, 0.1, 0.0), false, None, false, STEP, STEP);
+        }
+        assert!(controller.get_foot_position().y > start.y + 6.5, "didn't climb, got to {:?}", controller.get_foot_position());
+
+        // buoyant water floats it up, heavy water lets it sink slowly
+        let climbed = controller.get_foot_position();
+        controller.set_movement_mode(MovementMode::Swim(2.0));
+        idle(&mut controller, 1.0);
+        assert!(controller.get_foot_position().y > climbed.y, "sank in buoyant water to {:?}", controller.get_foot_position());
+        let floating = controller.get_foot_position();
+        controller.set_movement_mode(MovementMode::Swim(0.5));
+        idle(&mut controller, 0.5);
+        let sunk = floating.y - controller.get_foot_position().y;
+        // falling for half a second would drop it about 3
+        assert!(sunk > 0.0 && sunk < 1.5, "sank {} in half a second", sunk);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn moving_bodies_carry_controllers_standing_on_them() {
+        let physics = scene();
+        let desc = RigidBodyDesc {
+            shape: ColliderShape::Box(Vec3::new(6.0, 1.0, 6.0)),
+            mass: 100.0,
+            friction: 0.5,
+            restitution: 0.0,
+            kinematic: true,
+        };
+        let mut position = Vec3::new(0.0, -0.5, 0.0);
+        let platform = physics.create_rigid_body("base", &desc, position, Quaternion::identity()).unwrap();
+        let mut rider = controller(&physics, Vec3::new(0.0, 0.5, 0.0));
+        for _ in 0..10 {
+            physics.tick(STEP);
+            rider.move_by(Vec3::zero(), false, None, false, STEP, STEP);
+        }
+
+        let start = rider.get_foot_position();
+        let velocity = Vec3::new(2.0, 0.0, 0.0);
+        platform.set_carry_velocity(velocity);
+        for _ in 0..20 {
+            position += velocity * STEP;
+            platform.set_kinematic_target(position, Quaternion::identity());
+            physics.tick(STEP);
+            rider.move_by(Vec3::zero(), false, None, false, STEP, STEP);
+        }
+        let carried = rider.get_foot_position();
+        assert!(carried.x - start.x > 1.5, "rider was left behind at {:?}", carried);
+        assert!(carried.y > start.y - 0.1, "rider fell through the platform to {:?}", carried);
+
+        // once it stops, so does whoever's on it
+        platform.set_carry_velocity(Vec3::zero());
+        for _ in 0..10 {
+            physics.tick(STEP);
+            rider.move_by(Vec3::zero(), false, None, false, STEP, STEP);
+        }
+        assert!((rider.get_foot_position().x - carried.x).abs() < 0.01, "rider kept going after the platform stopped");
+    }
+}
@@ -496,7 +496,7 @@ impl ht_renderer {
                 let final_texture = int_texture_container.lock().unwrap().take();
                 let final_texture = Texture::load_from_intermidiary(final_texture)?;
                 self.textures.insert(name.to_string(), final_texture);
-                self.loading_meshes.remove(name);
+                self.loading_textures.remove(name);
                 return Ok(true)
             } else {
                 return Ok(false)
@@ -547,6 +547,59 @@ impl ht_renderer {
         Ok(())
     }
 
+    /// starts loading a fresh copy of an already loaded texture, the old one keeps being used until finish_reloads swaps it out
+    pub fn reload_texture(&mut self, name: &str) {
+        if !self.textures.contains_key(name) {
+            return;
+        }
+        let (done, container) = Texture::new_from_name_asynch_begin(name);
+        self.loading_textures.insert(name.to_string(), (done, container));
+    }
+
+    /// starts loading a fresh copy of an already loaded mesh, the old one keeps being used until finish_reloads swaps it out
+    pub fn reload_mesh(&mut self, name: &str) {
+        if !self.meshes.contains_key(name) {
+            return;
+        }
+        let (done, container) = Mesh::new_from_name_asynch_begin(format!("base/models/{}.glb", name).as_str(), name);
+        self.loading_meshes.insert(name.to_string(), (done, container));
+    }
+
+    /// swaps in any reloaded textures and meshes that have finished loading
+    /// (anything in loading_textures/loading_meshes that is also already loaded is a reload)
+    pub fn finish_reloads(&mut self) {
+        let textures = self.loading_textures.iter()
+            .filter(|(name, (done, _))| self.textures.contains_key(name.as_str()) && done.load(Ordering::Relaxed))
+            .map(|(name, (_, container))| (name.clone(), container.clone()))
+            .collect::<Vec<_>>();
+        for (name, container) in textures {
+            self.loading_textures.remove(&name);
+            let final_texture = container.lock().unwrap().take();
+            match Texture::load_from_intermidiary(final_texture) {
+                Ok(texture) => {
+                    info!("reloaded texture {}", name);
+                    self.textures.insert(name, texture);
+                }
+                Err(e) => warn!("failed to reload texture {}: {:?}", name, e),
+            }
+        }
+        let meshes = self.loading_meshes.iter()
+            .filter(|(name, (done, _))| self.meshes.contains_key(name.as_str()) && done.load(Ordering::Relaxed))
+            .map(|(name, (_, container))| (name.clone(), container.clone()))
+            .collect::<Vec<_>>();
+        for (name, container) in meshes {
+            self.loading_meshes.remove(&name);
+            let final_mesh = container.lock().unwrap().take();
+            match Mesh::load_from_intermidiary(final_mesh, self) {
+                Ok(mesh) => {
+                    info!("reloaded mesh {}", name);
+                    self.meshes.insert(name, mesh);
+                }
+                Err(e) => warn!("failed to reload mesh {}: {:?}", name, e),
+            }
+        }
+    }
+
     /*pub fn load_terrain_if_not_already_loaded(&mut self, name: &str) -> Result<(), String> {
         if !self.terrains.contains_key(name) {
             let terrain = Terrain::new_from_name(name, self)?;
@@ -0,0 +1,315 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex as StdMutex};
+use gfx_maths::{Quaternion, Vec3};
+use rand::Rng;
+use tokio::time::Instant;
+use crate::helpers;
+use crate::server::{Connection, ConnectionUUID, generate_uuid, Server, SteadyPacket};
+use crate::server::server_player::ServerPlayerContainer;
+use crate::worldmachine::EntityId;
+use crate::worldmachine::navmesh::NavMesh;
+use crate::worldmachine::player::MovementInfo;
+
+const THINK_INTERVAL: f32 = 1.0 / 30.0;
+const WANDER_RADIUS: f32 = 20.0; // from where the bot spawned
+const ARRIVE_DISTANCE: f32 = 1.0;
+const STUCK_TIME: f32 = 2.0; // give up on a wander target after not getting any closer for this long
+const CHASE_RADIUS: f32 = 25.0;
+const KEEP_DISTANCE: f32 = 4.0; // chasing bots stop this far away instead of walking into you
+const THROW_RANGE: f32 = 15.0;
+const AIM_HEIGHT: f32 = 1.0; // above the target's feet
+const EYE_HEIGHT: f32 = 1.5;
+const WAYPOINT_DISTANCE: f32 = 0.5; // close enough to a waypoint to head for the next one
+const REPATH_DISTANCE: f32 = 1.0; // find a new path once the target has moved this far from where the last one went
+
+const BOT_NAMES: [&str; 8] = ["snowbert", "frosty", "sleet", "flurry", "blizzard", "slush", "icicle", "hail"];
+
+/// a player run by the server (Connection::Bot), it moves and throws snowballs through the same code as everyone else
+pub struct Bot {
+    pub uuid: ConnectionUUID,
+    brain: StdMutex<BotBrain>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BotGoal {
+    Wander(Vec3),
+    Chase(EntityId),
+}
+
+struct BotBrain {
+    goal: Option<BotGoal>,
+    home: Option<Vec3>, // where it spawned, wandering stays around here
+    last_think: Instant,
+    path: Vec<Vec3>, // waypoints to the goal, empty if there's no navmesh or no way there
+    path_target: Option<Vec3>, // where the path was found to
+    closest: f32, // closest it's been to the current goal, along the path if there is one
+    since_progress: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+// what a bot decided to do this think, the server carries it out
+struct BotAction {
+    direction: Vec3, // which way it wants to walk, zero to stand still
+    jumped: bool,
+    rotation: Quaternion,
+    head_rotation: Quaternion,
+    throw: bool,
+    delta: f32,
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self {
+            uuid: generate_uuid(),
+            brain: StdMutex::new(BotBrain {
+                goal: None,
+                home: None,
+                last_think: Instant::now(),
+                path: Vec::new(),
+                path_target: None,
+                closest: f32::MAX,
+                since_progress: 0.0,
+                yaw: 0.0,
+                pitch: 0.0,
+            }),
+        }
+    }
+
+    pub fn goal(&self) -> Option<BotGoal> {
+        self.brain.lock().unwrap().goal
+    }
+}
+
+impl BotBrain {
+    fn set_goal(&mut self, goal: Option<BotGoal>) {
+        if goal != self.goal {
+            self.goal = goal;
+            self.path.clear();
+            self.path_target = None;
+            self.closest = f32::MAX;
+            self.since_progress = 0.0;
+        }
+    }
+
+    // where to head for right now to get to the target, the next waypoint if there's a navmesh
+    // if there's no way there it walks straight at it and hopes for the best
+    fn steer(&mut self, position: Vec3, target: Vec3, navmesh: Option<&NavMesh>) -> Vec3 {
+        let navmesh = match navmesh {
+            Some(navmesh) => navmesh,
+            None => return target,
+        };
+        if self.path_target.map_or(true, |old| helpers::distance(old, target) > REPATH_DISTANCE) {
+            self.path = navmesh.find_path(position, target).unwrap_or_default();
+            self.path_target = Some(target);
+        }
+        while self.path.len() > 1 && helpers::flat_distance(position, self.path[0]) < WAYPOINT_DISTANCE {
+            self.path.remove(0);
+        }
+        self.path.first().cloned().unwrap_or(target)
+    }
+
+    // how far there is left to walk, along the path if there is one
+    fn remaining(&self, position: Vec3, target: Vec3) -> f32 {
+        if self.path.is_empty() {
+            return helpers::flat_distance(position, target);
+        }
+        let mut remaining = helpers::flat_distance(position, self.path[0]);
+        for pair in self.path.windows(2) {
+            remaining += helpers::flat_distance(pair[0], pair[1]);
+        }
+        remaining
+    }
+
+    fn plan(&mut self, position: Vec3, others: &[(EntityId, Vec3)], navmesh: Option<&NavMesh>, delta: f32) -> BotAction {
+        let home = *self.home.get_or_insert(position);
+
+        let nearest = others.iter()
+            .map(|(entity_id, other)| (*entity_id, *other, helpers::distance(position, *other)))
+            .filter(|(_, _, distance)| *distance < CHASE_RADIUS)
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+        match nearest {
+            Some((entity_id, _, _)) => self.set_goal(Some(BotGoal::Chase(entity_id))),
+            None if matches!(self.goal, Some(BotGoal::Chase(_))) => self.set_goal(None),
+            None => {}
+        }
+
+        let target = match self.goal {
+            Some(BotGoal::Chase(_)) => nearest.map(|(_, other, _)| other).unwrap(),
+            Some(BotGoal::Wander(target)) => target,
+            None => {
+                let mut rng = rand::thread_rng();
+                let target = home + Vec3::new(rng.gen_range(-WANDER_RADIUS..WANDER_RADIUS), 0.0, rng.gen_range(-WANDER_RADIUS..WANDER_RADIUS));
+                self.set_goal(Some(BotGoal::Wander(target)));
+                target
+            }
+        };
+        let chasing = matches!(self.goal, Some(BotGoal::Chase(_)));
+
+        let distance = helpers::flat_distance(position, target);
+        let steer = self.steer(position, target, navmesh);
+        let mut flat = steer - position;
+        flat.y = 0.0;
+        let remaining = self.remaining(position, target);
+        if remaining < self.closest - 0.1 {
+            self.closest = remaining;
+            self.since_progress = 0.0;
+        } else {
+            self.since_progress += delta;
+        }
+
+        let mut moving = distance > if chasing { KEEP_DISTANCE } else { ARRIVE_DISTANCE };
+        if !chasing && (!moving || self.since_progress > STUCK_TIME) {
+            // pick somewhere else next time
+            self.set_goal(None);
+            moving = false;
+        }
+        let direction = if moving && flat != Vec3::zero() {
+            *flat.normalize().deref()
+        } else {
+            Vec3::zero()
+        };
+
+        // chasers look at who they're chasing, wanderers look where they're going
+        let look = if chasing {
+            Some((target + Vec3::new(0.0, AIM_HEIGHT, 0.0)) - (position + Vec3::new(0.0, EYE_HEIGHT, 0.0)))
+        } else if moving {
+            Some(flat)
+        } else {
+            None
+        };
+        if let Some(look) = look {
+            let length = (look.x * look.x + look.y * look.y + look.z * look.z).sqrt();
+            if length > 0.001 {
+                // the inverse of how the camera turns (0, 0, 1) into its front vector
+                self.yaw = -look.x.atan2(look.z).to_degrees();
+                self.pitch = (look.y / length).asin().to_degrees().clamp(-89.0, 89.0);
+            }
+        }
+        let rotation = Quaternion::from_euler_angles_zyx(&Vec3::new(0.0, self.yaw, 0.0));
+        let head_rotation = Quaternion::from_euler_angles_zyx(&Vec3::new(self.pitch, 0.0, 0.0)) * rotation;
+
+        BotAction {
+            direction,
+            // hop over whatever's in the way
+            jumped: moving && self.since_progress > STUCK_TIME / 2.0,
+            rotation,
+            head_rotation,
+            throw: chasing && distance < THROW_RANGE,
+            delta,
+        }
+    }
+}
+
+impl Server {
+    /// adds a bot player, returning its name
+    pub async fn add_bot(&self) -> Option<String> {
+        let bot = Arc::new(Bot::new());
+        let player = self.begin_connection(Connection::Bot(bot.clone())).await?;
+        let name = self.pick_bot_name().await;
+        *player.player.name.lock().await = name.clone();
+        self.broadcast_steady_packet(SteadyPacket::SetName(bot.uuid.clone(), name.clone())).await;
+        info!("added bot {}", name);
+        Some(name)
+    }
+
+    /// removes bots by name or uuid, or all of them, returning the names of the ones removed
+    pub async fn remove_bots(&self, who: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        for bot in self.bots().await {
+            let name = bot.player.name.lock().await.clone();
+            if who != "all" && who != name && who != bot.player.uuid.as_str() {
+                continue;
+            }
+            if let Some(entity_id) = bot.entity_id {
+                self.disconnect_player(bot.player.uuid.to_string(), entity_id).await;
+            }
+            info!("removed bot {}", name);
+            removed.push(name);
+        }
+        removed
+    }
+
+    pub async fn bots(&self) -> Vec<ServerPlayerContainer> {
+        let players = self.worldmachine.lock().await.players.clone();
+        match players {
+            Some(players) => players.lock().await.values().filter(|player| matches!(player.connection, Connection::Bot(_))).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    async fn pick_bot_name(&self) -> String {
+        let players = self.worldmachine.lock().await.players.clone().unwrap();
+        let players = players.lock().await.clone();
+        let mut taken = Vec::new();
+        for player in players.values() {
+            taken.push(player.player.name.lock().await.clone());
+        }
+        let mut i = 0;
+        loop {
+            // once the nice names run out, number them
+            let name = match BOT_NAMES.get(i) {
+                Some(name) => name.to_string(),
+                None => format!("bot {}", i - BOT_NAMES.len() + 1),
+            };
+            if !taken.contains(&name) {
+                return name;
+            }
+            i += 1;
+        }
+    }
+
+    /// lets every bot think and act, they only actually do so every THINK_INTERVAL
+    pub async fn tick_bots(&self) {
+        let bots = self.bots().await;
+        if bots.is_empty() {
+            return;
+        }
+        let players = self.worldmachine.lock().await.players.clone().unwrap();
+        let players = players.lock().await.clone();
+        let mut positions = Vec::new();
+        for player in players.values() {
+            if let Some(entity_id) = player.entity_id {
+                positions.push((entity_id, player.player.get_position(None, None).await));
+            }
+        }
+        for player in bots {
+            if let Connection::Bot(bot) = &player.connection {
+                self.bot_think(&player, bot, &positions).await;
+            }
+        }
+    }
+
+    async fn bot_think(&self, player: &ServerPlayerContainer, bot: &Bot, positions: &[(EntityId, Vec3)]) {
+        if player.player.respawning.load(std::sync::atomic::Ordering::Relaxed) {
+            return;
+        }
+        let position = player.player.get_position(None, None).await;
+        let others = positions.iter().filter(|(entity_id, _)| Some(*entity_id) != player.entity_id).cloned().collect::<Vec<_>>();
+        let navmesh = self.worldmachine.lock().await.navmesh();
+        let action = {
+            let mut brain = bot.brain.lock().unwrap();
+            let now = Instant::now();
+            let delta = (now - brain.last_think).as_secs_f32();
+            if delta < THINK_INTERVAL {
+                return;
+            }
+            brain.last_think = now;
+            brain.plan(position, &others, navmesh.as_deref(), delta)
+        };
+
+        // a bot moves like a client would: claim where it ends up, then ask the server to go there
+        let new_position = player.player.predict_move(action.direction, action.delta).await;
+        let movement_info = MovementInfo {
+            jumped: action.jumped,
+            speed: if action.direction == Vec3::zero() { 0.0 } else { 1.0 },
+            wish_direction: action.direction,
+            ..Default::default()
+        };
+        player.player.attempt_position_change(new_position, action.direction, action.rotation, action.head_rotation, movement_info, player.entity_id, self.worldmachine.clone()).await;
+        if action.throw {
+            // the cooldown is the server's business, same as for everyone else
+            self.steady_packet(player, SteadyPacket::ThrowSnowball(String::new(), Vec3::default(), Vec3::default())).await;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use tokio::io::{AsyncBufReadExt, BufReader};
+use crate::server::{Connection, Connections, Server};
+use crate::server::bots::BotGoal;
+use crate::server::lan::{DEFAULT_NETWORK_CONDITIONS, LanConnection, NetworkConditions};
+
+const HELP: &str = "commands:
+  help
+  players                            list connected players
+  bot add [count]                    add bots
+  bot remove <who>                   remove bots by name or uuid, or \"all\" of them
+  bots                               list bots and what they're up to
+  net                                show network conditions
+  net <who> <setting> <value>        change a network condition
+  net <who> clear                    back to a perfect network
+<who> is a player name or uuid, \"all\" for everyone connected, or \"default\" for whoever connects next
+settings: latency, jitter, tcp-latency (ms), loss, duplicate, reorder (0 to 1), bandwidth (KB/s, 0 for unlimited)";
+
+impl Server {
+    /// reads commands from stdin, for running a dedicated server
+    pub async fn console_thread(&self) {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    let output = self.run_console_command(&line).await;
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                }
+                Ok(None) => {
+                    debug!("stdin closed, console stopping");
+                    break;
+                }
+                Err(e) => {
+                    warn!("failed to read console input: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// runs one console command, returning what it has to say about it
+    pub async fn run_console_command(&self, line: &str) -> String {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            [] => String::new(),
+            ["help"] => HELP.to_string(),
+            ["players"] => {
+                let players = self.player_names().await;
+                if players.is_empty() {
+                    return "nobody's here".to_string();
+                }
+                players.iter().map(|(uuid, name)| format!("{} {}", uuid, name)).collect::<Vec<String>>().join("\n")
+            }
+            ["bot", "add", count @ ..] if count.len() <= 1 => {
+                let count = match count.first().map(|count| count.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return format!("{} isn't a number", count[0]),
+                };
+                let mut output = Vec::new();
+                for _ in 0..count {
+                    match self.add_bot().await {
+                        Some(name) => output.push(format!("added {}", name)),
+                        None => output.push("failed to add bot".to_string()),
+                    }
+                }
+                output.join("\n")
+            }
+            ["bot", "remove", who] => {
+                let removed = self.remove_bots(who).await;
+                if removed.is_empty() {
+                    format!("no bot called {}", who)
+                } else {
+                    format!("removed {}", removed.join(", "))
+                }
+            }
+            ["bots"] => {
+                let mut output = Vec::new();
+                for bot in self.bots().await {
+                    let name = bot.player.name.lock().await.clone();
+                    let goal = match &bot.connection {
+                        Connection::Bot(brain) => match brain.goal() {
+                            Some(BotGoal::Wander(target)) => format!("wandering to {:.1} {:.1} {:.1}", target.x, target.y, target.z),
+                            Some(BotGoal::Chase(entity_id)) => format!("chasing entity {}", entity_id),
+                            None => "thinking".to_string(),
+                        },
+                        _ => unreachable!(),
+                    };
+                    output.push(format!("{} {}: {}", bot.player.uuid, name, goal));
+                }
+                if output.is_empty() {
+                    "no bots".to_string()
+                } else {
+                    output.join("\n")
+                }
+            }
+            ["net"] => {
+                let mut output = vec![format!("default: {}", *DEFAULT_NETWORK_CONDITIONS.lock().unwrap())];
+                let names = self.player_names().await;
+                for connection in self.lan_connections().await {
+                    let name = names.iter().find(|(uuid, _)| *uuid == connection.uuid).map(|(_, name)| name.as_str()).unwrap_or("(joining)");
+                    output.push(format!("{} {}: {}", connection.uuid, name, *connection.conditions.lock().unwrap()));
+                }
+                output.join("\n")
+            }
+            ["net", who, "clear"] => {
+                self.change_network_conditions(who, |conditions| {
+                    *conditions = NetworkConditions::default();
+                    Some(())
+                }).await
+            }
+            ["net", who, setting, value] => {
+                if !NetworkConditions::SETTINGS.contains(setting) {
+                    return format!("no such setting {}, try one of {}", setting, NetworkConditions::SETTINGS.join(", "));
+                }
+                self.change_network_conditions(who, |conditions| conditions.set(setting, value)).await
+            }
+            _ => format!("don't know how to {}, try help", line.trim()),
+        }
+    }
+
+    async fn player_names(&self) -> Vec<(String, String)> {
+        let players = self.worldmachine.lock().await.players.clone();
+        let mut names = Vec::new();
+        if let Some(players) = players {
+            let players = players.lock().await.clone();
+            for (uuid, player) in players.iter() {
+                names.push((uuid.clone(), player.player.name.lock().await.clone()));
+            }
+        }
+        names
+    }
+
+    async fn lan_connections(&self) -> Vec<LanConnection> {
+        match &self.connections {
+            Connections::Lan(_, connections) => connections.lock().await.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    async fn change_network_conditions(&self, who: &str, change: impl Fn(&mut NetworkConditions) -> Option<()>) -> String {
+        if who == "default" {
+            let mut conditions = DEFAULT_NETWORK_CONDITIONS.lock().unwrap();
+            return match change(&mut conditions) {
+                Some(_) => format!("default: {}", *conditions),
+                None => "bad value".to_string(),
+            };
+        }
+        let names = self.player_names().await;
+        let connections = self.lan_connections().await.into_iter().filter(|connection| {
+            who == "all" || connection.uuid == who || names.iter().any(|(uuid, name)| *uuid == connection.uuid && name == who)
+        }).collect::<Vec<LanConnection>>();
+        if connections.is_empty() {
+            return format!("nobody called {} is connected (only lan connections have network conditions)", who);
+        }
+        let mut output = Vec::new();
+        for connection in connections {
+            let mut conditions = connection.conditions.lock().unwrap();
+            let mut changed = *conditions;
+            if change(&mut changed).is_none() {
+                return "bad value".to_string();
+            }
+            *conditions = changed;
+            output.push(format!("{}: {}", connection.uuid, changed));
+        }
+        output.join("\n")
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Deref;
+use std::time::Instant;
+use gfx_maths::{Quaternion, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::keyboard::{self, HTKey};
+use crate::mouse;
+use crate::ht_renderer;
+use crate::server::{ConnectionUUID, FastPacket, SteadyPacket};
+use crate::worldmachine::ecs::{Entity, System};
+
+// bump this whenever the packets change in a way that breaks old demos
+pub const DEMO_VERSION: u32 = 2;
+
+const FLUSH_INTERVAL: f32 = 1.0;
+const SEEK_STEP: f64 = 5.0;
+const MIN_SPEED: f32 = 0.125;
+const MAX_SPEED: f32 = 8.0;
+const CAMERA_SPEED: f32 = 8.0;
+const CAMERA_FAST_SPEED: f32 = 24.0;
+
+/// the world as it was when recording started, everything after it is packets
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DemoHeader {
+    pub version: u32,
+    pub map: String,
+    pub entities: Vec<Entity>,
+    pub systems: Vec<System>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum DemoPacket {
+    Steady(SteadyPacket),
+    Fast(FastPacket),
+    // sent to one connection only, like respawns, clock syncs and corrections. playback has no player of its own so it skips these
+    SteadyTo(ConnectionUUID, SteadyPacket),
+    FastTo(ConnectionUUID, FastPacket),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DemoFrame {
+    pub time: f64, // seconds since recording started
+    pub packet: DemoPacket,
+}
+
+/// writes everything the server broadcasts to a file (--record-demo <file>), and what it sends to each player tagged with who it went to
+/// the file is a msgpack DemoHeader followed by DemoFrames until the end of the file
+pub struct DemoRecorder {
+    writer: BufWriter<File>,
+    started: Instant,
+    last_flush: Instant,
+}
+
+impl DemoRecorder {
+    pub fn new(path: &str, header: &DemoHeader) -> Option<Self> {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("failed to create demo file {}: {:?}", path, e);
+                return None;
+            }
+        };
+        let mut writer = BufWriter::new(file);
+        if let Err(e) = rmp_serde::encode::write(&mut writer, header) {
+            error!("failed to write demo header: {:?}", e);
+            return None;
+        }
+        info!("recording demo to {}", path);
+        Some(Self {
+            writer,
+            started: Instant::now(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, packet: DemoPacket) {
+        let frame = DemoFrame {
+            time: self.started.elapsed().as_secs_f64(),
+            packet,
+        };
+        if let Err(e) = rmp_serde::encode::write(&mut self.writer, &frame) {
+            warn!("failed to write demo frame: {:?}", e);
+        }
+        // the server usually gets killed rather than shut down, so don't keep too much in the buffer
+        if self.last_flush.elapsed().as_secs_f32() >= FLUSH_INTERVAL {
+            let _ = self.writer.flush();
+            self.last_flush = Instant::now();
+        }
+    }
+}
+
+impl Drop for DemoRecorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// plays a recorded demo back through ConnectionClientside::Demo (--play-demo <file>)
+pub struct DemoPlayer {
+    pub header: DemoHeader,
+    frames: Vec<DemoFrame>,
+    cursor: usize, // next frame to hand out
+    time: f64,
+    pub speed: f32,
+    pub paused: bool,
+    restart: bool, // the world has to be rebuilt from the header, set at the start and when seeking backwards
+}
+
+impl DemoPlayer {
+    pub fn load(path: &str) -> Option<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("failed to open demo {}: {:?}", path, e);
+                return None;
+            }
+        };
+        let mut deserializer = rmp_serde::Deserializer::new(BufReader::new(file));
+        let header: DemoHeader = match Deserialize::deserialize(&mut deserializer) {
+            Ok(header) => header,
+            Err(e) => {
+                error!("failed to read demo header: {:?}", e);
+                return None;
+            }
+        };
+        if header.version != DEMO_VERSION {
+            error!("demo {} is version {}, we can only play version {}", path, header.version, DEMO_VERSION);
+            return None;
+        }
+        let mut frames = Vec::new();
+        // a demo from a server that was killed can end halfway through a frame, so just stop at the first bad one
+        while let Ok(frame) = DemoFrame::deserialize(&mut deserializer) {
+            frames.push(frame);
+        }
+        info!("loaded demo {} on {} ({} frames, {:.1}s)", path, header.map, frames.len(), frames.last().map(|frame| frame.time).unwrap_or(0.0));
+        Some(Self {
+            header,
+            frames,
+            cursor: 0,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            restart: true,
+        })
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn length(&self) -> f64 {
+        self.frames.last().map(|frame| frame.time).unwrap_or(0.0)
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        if !self.paused {
+            self.time = (self.time + (delta * self.speed) as f64).min(self.length());
+        }
+    }
+
+    /// packets can't be undone, so going backwards replays everything from the start
+    pub fn seek(&mut self, time: f64) {
+        let time = time.clamp(0.0, self.length());
+        if time < self.time {
+            self.cursor = 0;
+            self.restart = true;
+        }
+        self.time = time;
+    }
+
+    pub fn take_restart(&mut self) -> bool {
+        std::mem::replace(&mut self.restart, false)
+    }
+
+    /// every packet up to the current time that hasn't been handed out yet
+    pub fn due_packets(&mut self) -> Vec<DemoPacket> {
+        let start = self.cursor;
+        while self.cursor < self.frames.len() && self.frames[self.cursor].time <= self.time {
+            self.cursor += 1;
+        }
+        self.frames[start..self.cursor].iter().map(|frame| frame.packet.clone()).collect()
+    }
+
+    /// space pauses, left/right seek, minus/equal change speed
+    pub fn handle_input(&mut self) {
+        if keyboard::check_key_released(HTKey::Space) {
+            self.paused = !self.paused;
+        }
+        if keyboard::check_key_released(HTKey::Left) {
+            self.seek(self.time - SEEK_STEP);
+        }
+        if keyboard::check_key_released(HTKey::Right) {
+            self.seek(self.time + SEEK_STEP);
+        }
+        if keyboard::check_key_released(HTKey::Minus) {
+            self.speed = (self.speed / 2.0).max(MIN_SPEED);
+        }
+        if keyboard::check_key_released(HTKey::Equal) {
+            self.speed = (self.speed * 2.0).min(MAX_SPEED);
+        }
+    }
+}
+
+/// a noclip camera for watching demos, wasd to move, shift to go faster, mouse to look
+#[derive(Default)]
+pub struct DemoCamera {
+    last_mouse_pos: Option<gfx_maths::Vec2>,
+    pitch: f64,
+    wasd: [bool; 4],
+    fast: bool,
+}
+
+impl DemoCamera {
+    pub fn handle_input(&mut self, renderer: &mut ht_renderer, delta: f32) {
+        let mouse_pos = mouse::get_mouse_pos();
+        let last_mouse_pos = self.last_mouse_pos.unwrap_or(mouse_pos);
+        self.last_mouse_pos = Some(mouse_pos);
+        let ang_x = -(mouse_pos.x as f64 - last_mouse_pos.x as f64);
+        let mut ang_y = -(mouse_pos.y as f64 - last_mouse_pos.y as f64);
+        // same clamping as the player, so you can't flip over
+        ang_y = (self.pitch + ang_y).clamp(-89.0, 89.0) - self.pitch;
+        self.pitch += ang_y;
+
+        let camera = &mut renderer.camera;
+        let horiz = Quaternion::from_euler_angles_zyx(&Vec3::new(0.0, ang_x as f32, 0.0));
+        let vert = Quaternion::from_euler_angles_zyx(&Vec3::new(ang_y as f32, 0.0, 0.0));
+        camera.set_rotation(vert * camera.get_rotation() * horiz);
+
+        for (i, key) in [HTKey::W, HTKey::A, HTKey::S, HTKey::D].into_iter().enumerate() {
+            if keyboard::check_key_pressed(key) {
+                self.wasd[i] = true;
+            }
+            if keyboard::check_key_released(key) {
+                self.wasd[i] = false;
+            }
+        }
+        if keyboard::check_key_pressed(HTKey::LeftShift) {
+            self.fast = true;
+        }
+        if keyboard::check_key_released(HTKey::LeftShift) {
+            self.fast = false;
+        }
+
+        let mut movement = Vec3::new(0.0, 0.0, 0.0);
+        let front = camera.get_front();
+        if self.wasd[0] {
+            movement += front;
+        }
+        if self.wasd[1] {
+            movement += camera.get_right();
+        }
+        if self.wasd[2] {
+            movement -= front;
+        }
+        if self.wasd[3] {
+            movement -= camera.get_right();
+        }
+        if movement != Vec3::new(0.0, 0.0, 0.0) {
+            let speed = if self.fast { CAMERA_FAST_SPEED } else { CAMERA_SPEED };
+            let movement = *movement.normalize().deref() * speed * delta;
+            camera.set_position(camera.get_position() + movement);
+        }
+    }
+}
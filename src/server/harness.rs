@@ -0,0 +1,530 @@
+// a headless server with scripted fake clients, for tests
+// everything happens on the test's thread with tokio's clock paused (#[tokio::test(start_paused = true)]),
+// the server only moves when step() is called, so a test does the same thing every time it runs
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard};
+use std::time::Duration;
+use gfx_maths::{Quaternion, Vec3};
+use crate::physics;
+use crate::server::{Connection, Connections, FastPacket, FastPacketData, generate_uuid, LocalConnectionClientSide, Server, SteadyPacket, SteadyPacketData};
+use crate::server::server_player::{DEFAULT_MOVESPEED, ServerPlayerContainer};
+use crate::worldmachine::EntityId;
+use crate::worldmachine::components::{COMPONENT_TYPE_NPC, COMPONENT_TYPE_PLATFORM, COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_TRANSFORM, CapsuleCollider, Jukebox, Npc, Platform, RigidBody, Transform};
+use crate::worldmachine::ecs::{Component, ComponentType, Entity, Parameter, ParameterValue};
+use crate::worldmachine::jukebox::JukeboxCommand;
+use crate::worldmachine::movement::MovementSettings;
+use crate::worldmachine::player::MovementInfo;
+
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+// how many packets each connection gets to handle per step, comfortably more than a fake client sends in one
+const POLLS_PER_STEP: usize = 16;
+
+lazy_static! {
+    // entity ids and component types are global, so only one test server can exist at a time
+    static ref TEST_LOCK: StdMutex<()> = StdMutex::new(());
+}
+
+pub struct TestServer {
+    pub server: Server,
+    pub clients: Vec<FakeClient>,
+    compensation_delta: f32,
+    _lock: MutexGuard<'static, ()>,
+}
+
+/// a client with no worldmachine or renderer, it sends whatever it's told to and remembers everything it receives
+pub struct FakeClient {
+    pub player: ServerPlayerContainer, // the server's side of this client
+    pub connection: LocalConnectionClientSide,
+    pub uuid: String,
+    pub entity_id: EntityId,
+    pub position: Vec3, // where this client thinks it is, corrected by the server like a real client would be
+    pub steady_received: Vec<SteadyPacket>,
+    pub fast_received: Vec<FastPacket>,
+}
+
+impl TestServer {
+    pub async fn new(map_name: &str) -> Self {
+        // a failed test poisons the lock, that doesn't make the next one any less runnable
+        let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        Self {
+            server: Server::new(map_name, physics::testing::shared()),
+            clients: Vec::new(),
+            compensation_delta: 0.0,
+            _lock: lock,
+        }
+    }
+
+    /// connects a fake client the same way join_local_server does, minus the task that polls the connection
+    /// returns the client's index in `clients`
+    pub async fn join(&mut self) -> usize {
+        let (connection, client_side) = self.server.create_local_connection();
+        let connection = Arc::new(connection);
+        if let Connections::Local(connections) = &self.server.connections {
+            connections.lock().await.push(connection.clone());
+        }
+        let player = self.server.begin_connection(Connection::Local(connection.clone())).await.expect("fake client failed to connect");
+        self.clients.push(FakeClient {
+            uuid: connection.uuid.clone(),
+            entity_id: player.entity_id.expect("player has no entity"),
+            player,
+            connection: client_side,
+            position: Vec3::zero(),
+            steady_received: Vec::new(),
+            fast_received: Vec::new(),
+        });
+        self.settle().await;
+        self.clients.len() - 1
+    }
+
+    /// one fixed timestep: handle what the clients sent, step physics and players, then send out the results
+    pub async fn step(&mut self) {
+        for client in self.clients.iter() {
+            for _ in 0..POLLS_PER_STEP {
+                self.server.poll_connection(&client.player, &mut None).await;
+            }
+        }
+        tokio::time::advance(Duration::from_secs_f32(TIMESTEP)).await;
+        match self.server.physics_step(TIMESTEP, self.compensation_delta).await {
+            Some(delta) => self.compensation_delta += delta,
+            None => self.compensation_delta = 0.0,
+        }
+        self.server.tick_players(TIMESTEP).await;
+        self.server.tick_bots().await;
+        self.settle().await;
+    }
+
+    pub async fn run_for(&mut self, seconds: f32) {
+        let steps = (seconds / TIMESTEP).round() as usize;
+        for _ in 0..steps {
+            self.step().await;
+        }
+    }
+
+    // queue_updates hands updates over on another task, let that run before sending them
+    async fn settle(&mut self) {
+        tokio::task::yield_now().await;
+        self.server.send_world_updates().await;
+        for client in self.clients.iter_mut() {
+            client.receive();
+        }
+    }
+
+    /// where the server has a client's player entity
+    pub async fn player_position(&self, client: usize) -> Vec3 {
+        let worldmachine = self.server.worldmachine.lock().await;
+        let position = worldmachine.get_entity(self.clients[client].entity_id)
+            .and_then(|entity| entity.get_component(COMPONENT_TYPE_PLAYER.clone()))
+            .map(|player| player.get_parameter("position").value.clone());
+        match position {
+            Some(ParameterValue::Vec3(position)) => position,
+            _ => panic!("client {} has no player position", client),
+        }
+    }
+
+    /// spawns an entity serverside, with a Transform at `position` and the given components
+    pub async fn spawn(&self, name: &str, position: Vec3, components: Vec<Component>) -> EntityId {
+        let mut entity = Entity::new(name);
+        entity.add_component(Transform::new(position, Quaternion::identity(), Vec3::new(1.0, 1.0, 1.0)));
+        for component in components {
+            entity.add_component(component);
+        }
+        self.server.worldmachine.lock().await.spawn(entity, None).expect("failed to spawn entity")
+    }
+
+    /// what the server has for one of an entity's parameters
+    pub async fn parameter(&self, entity_id: EntityId, component_type: ComponentType, name: &str) -> ParameterValue {
+        let worldmachine = self.server.worldmachine.lock().await;
+        let entity = worldmachine.get_entity(entity_id).expect("entity went missing");
+        match entity.get_component(component_type.clone()) {
+            Some(component) => component.get_parameter(name).value.clone(),
+            None => panic!("entity {} has no {}", entity_id, component_type.name),
+        }
+    }
+
+    /// where the server has an entity, according to its Transform
+    pub async fn entity_position(&self, entity_id: EntityId) -> Vec3 {
+        match self.parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "position").await {
+            ParameterValue::Vec3(position) => position,
+            _ => panic!("entity {} has no position", entity_id),
+        }
+    }
+
+    pub async fn player_count(&self) -> usize {
+        self.server.worldmachine.lock().await.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone()).count()
+    }
+
+    pub async fn snowball_count(&self) -> usize {
+        self.server.worldmachine.lock().await.snowballs.len()
+    }
+}
+
+impl FakeClient {
+    fn receive(&mut self) {
+        while let Ok(packet) = self.connection.steady_update_receiver.try_recv() {
+            match &packet.packet {
+                SteadyPacket::InitialisePlayer(_, _, _, position, _, _) => self.position = *position,
+                SteadyPacket::Respawn(position) => self.position = *position,
+                _ => {}
+            }
+            self.steady_received.push(packet.packet);
+        }
+        while let Ok(packet) = self.connection.fast_update_receiver.try_recv() {
+            if let Some(packet) = packet.packet {
+                if let FastPacket::PlayerFuckYouMoveHere(position) = packet {
+                    self.position = position;
+                }
+                self.fast_received.push(packet);
+            }
+        }
+    }
+
+    pub fn received(&self, predicate: impl Fn(&SteadyPacket) -> bool) -> bool {
+        self.steady_received.iter().any(predicate)
+    }
+
+    pub fn received_fast(&self, predicate: impl Fn(&FastPacket) -> bool) -> bool {
+        self.fast_received.iter().any(predicate)
+    }
+
+    async fn send_fast(&mut self, packet: FastPacket) {
+        self.connection.fast_update_sender.send(FastPacketData {
+            packet: Some(packet),
+        }).await.expect("server hung up");
+    }
+
+    async fn send_steady(&mut self, packet: SteadyPacket) {
+        self.connection.steady_update_sender.send(SteadyPacketData {
+            packet,
+            uuid: generate_uuid(),
+        }).await.expect("server hung up");
+    }
+
+    /// moves one timestep in a direction at walking speed, like holding a movement key for a frame
+    pub async fn walk(&mut self, direction: Vec3, jumped: bool) {
+        let displacement = direction * DEFAULT_MOVESPEED;
+        self.position = self.position + displacement * TIMESTEP;
+        let movement_info = MovementInfo {
+            jumped,
+            speed: 1.0,
+            wish_direction: direction,
+            ..Default::default()
+        };
+        self.send_fast(FastPacket::PlayerMove(self.uuid.clone(), self.position, displacement, Quaternion::identity(), Quaternion::identity(), Some(movement_info))).await;
+    }
+
+    pub async fn jump(&mut self) {
+        self.walk(Vec3::zero(), true).await;
+    }
+
+    pub async fn throw_snowball(&mut self) {
+        self.send_steady(SteadyPacket::ThrowSnowball(String::new(), Vec3::default(), Vec3::default())).await;
+    }
+
+    pub async fn chat(&mut self, message: &str) {
+        self.send_steady(SteadyPacket::ChatMessage(String::new(), message.to_string())).await;
+    }
+
+    pub async fn rename(&mut self, name: &str) {
+        self.send_steady(SteadyPacket::SetName(String::new(), name.to_string())).await;
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn clients_join_and_see_each_other() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        let b = test.join().await;
+        test.step().await;
+
+        assert_eq!(test.player_count().await, 2);
+        for client in test.clients.iter() {
+            assert!(client.received(|packet| matches!(packet, SteadyPacket::FinaliseMapLoad)));
+            // from the test map's on_player_join
+            assert!(client.received(|packet| matches!(packet, SteadyPacket::ChatMessage(who, message) if who == "server" && message.starts_with("welcome"))));
+        }
+        let b_entity = test.clients[b].entity_id;
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::InitialiseEntity(id, _) if *id == b_entity)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn walking_moves_the_player_for_everyone() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        let b = test.join().await;
+        test.step().await;
+
+        let start = test.player_position(a).await;
+        for _ in 0..30 {
+            test.clients[a].walk(Vec3::new(1.0, 0.0, 0.0), false).await;
+            test.step().await;
+        }
+        let end = test.player_position(a).await;
+        assert!(end.x - start.x > 1.0, "player only got from {:?} to {:?}", start, end);
+
+        let a_entity = test.clients[a].entity_id;
+        assert!(test.clients[b].received_fast(|packet| matches!(packet, FastPacket::PlayerMoved(id, _, _, _) if *id == a_entity)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jumping_keeps_the_player_in_the_world() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+
+        for i in 0..120 {
+            if i % 40 == 0 {
+                test.clients[a].jump().await;
+            } else {
+                test.clients[a].walk(Vec3::new(0.0, 0.0, 1.0), false).await;
+            }
+            test.step().await;
+        }
+        let position = test.player_position(a).await;
+        assert!(position.y > -20.0, "player fell out of the world: {:?}", position);
+        assert!(!test.clients[a].received(|packet| matches!(packet, SteadyPacket::Respawn(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snowballs_are_thrown_and_rate_limited() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.join().await;
+        test.step().await;
+
+        test.clients[a].throw_snowball().await;
+        test.step().await;
+        assert_eq!(test.snowball_count().await, 1);
+        for client in test.clients.iter() {
+            assert!(client.received(|packet| matches!(packet, SteadyPacket::ThrowSnowball(_, _, _))));
+        }
+
+        // still cooling down from the last one
+        test.clients[a].throw_snowball().await;
+        test.step().await;
+        assert_eq!(test.snowball_count().await, 1);
+
+        // the first one may well have splatted by now, so count what was thrown rather than what's still flying
+        test.run_for(1.0).await;
+        test.clients[a].throw_snowball().await;
+        test.step().await;
+        let thrown = test.clients[a].steady_received.iter().filter(|packet| matches!(packet, SteadyPacket::ThrowSnowball(_, _, _))).count();
+        assert_eq!(thrown, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snowballs_splat_when_they_land() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+
+        test.clients[a].throw_snowball().await;
+        test.step().await;
+        assert_eq!(test.snowball_count().await, 1);
+        // nowhere near its time to live, it should have come down on something by now
+        test.run_for(3.0).await;
+        assert_eq!(test.snowball_count().await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn chat_and_names_reach_everyone() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.join().await;
+        test.step().await;
+
+        let a_uuid = test.clients[a].uuid.clone();
+        test.clients[a].chat("hello").await;
+        test.clients[a].rename("snowman").await;
+        test.step().await;
+        test.step().await;
+
+        for client in test.clients.iter() {
+            assert!(client.received(|packet| matches!(packet, SteadyPacket::ChatMessage(who, message) if *who == a_uuid && message == "hello")));
+            assert!(client.received(|packet| matches!(packet, SteadyPacket::SetName(who, name) if *who == a_uuid && name == "snowman")));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bots_chase_and_throw_snowballs() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+
+        let name = test.server.add_bot().await.expect("failed to add bot");
+        test.run_for(1.0).await;
+
+        assert_eq!(test.player_count().await, 2);
+        let bot = test.server.bots().await.pop().expect("bot went missing");
+        let bot_uuid = bot.player.uuid.to_string();
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::SetName(who, bot_name) if *who == bot_uuid && *bot_name == name)));
+        // everyone spawns in the same place, so the bot should be right on top of us and throwing
+        // at that range they splat almost straight away, so look for the throw rather than the snowball
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::ThrowSnowball(_, _, _))));
+
+        assert_eq!(test.server.remove_bots(&name).await, vec![name.clone()]);
+        test.step().await;
+        assert_eq!(test.player_count().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn navmesh_finds_a_path_across_the_spawn() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+
+        let start = test.player_position(a).await;
+        let worldmachine = test.server.worldmachine.lock().await;
+        let navmesh = worldmachine.navmesh().expect("test map has no navmesh");
+        assert!(!navmesh.nodes.is_empty());
+        let goal = start + Vec3::new(2.0, 0.0, 0.0);
+        let path = worldmachine.find_path(start, goal).expect("no path across the spawn");
+        let end = *path.last().unwrap();
+        assert!(crate::helpers::flat_distance(end, goal) < 1.5, "path to {:?} ended at {:?}", goal, end);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn npcs_patrol_and_flee() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+        let start = test.player_position(a).await;
+
+        // far enough away that it has nothing to run from, it should idle for a bit then head for its waypoint
+        let patrol_start = start + Vec3::new(0.0, 0.0, 4.0);
+        let waypoint = patrol_start + Vec3::new(3.0, 0.0, 0.0);
+        let waypoints = format!("{} {} {}", waypoint.x, waypoint.y, waypoint.z);
+        let patroller = test.spawn("npc", patrol_start, vec![Npc::new("player", "default", "idle", &waypoints, 4.0, 0.0)]).await;
+        test.run_for(3.0).await;
+        let patrol_end = test.entity_position(patroller).await;
+        assert!(patrol_end.x - patrol_start.x > 1.0, "patrolling npc only got from {:?} to {:?}", patrol_start, patrol_end);
+
+        let scaredy = test.spawn("npc", start + Vec3::new(0.0, 0.0, 3.0), vec![Npc::new("player", "default", "idle", "", 4.0, 8.0)]).await;
+        test.run_for(0.5).await;
+        let position = test.entity_position(scaredy).await;
+        assert_eq!(test.parameter(scaredy, COMPONENT_TYPE_NPC.clone(), "state").await, ParameterValue::String("flee".to_string()));
+        assert!(crate::helpers::flat_distance(position, start) > 4.0, "fleeing npc is still at {:?}", position);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jukeboxes_are_played_by_the_server() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::ServerTime(_))));
+
+        let length = crate::worldmachine::jukebox::track_length("base", "donk.wav").expect("couldn't read donk.wav");
+        assert!(length > 0.0);
+        let start = test.player_position(a).await;
+        let mut jukebox = Jukebox::new();
+        jukebox.parameters.insert("track".to_string(), Parameter::new("track", ParameterValue::String("donk.wav".to_string())));
+        jukebox.parameters.insert("uuid".to_string(), Parameter::new("uuid", ParameterValue::String(generate_uuid())));
+        let jukebox = test.spawn("jukebox", start, vec![jukebox]).await;
+        test.step().await;
+
+        test.server.worldmachine.lock().await.jukebox_command(jukebox, JukeboxCommand::Toggle).unwrap();
+        test.step().await;
+        let state = test.server.worldmachine.lock().await.jukebox_state(jukebox).unwrap();
+        assert!(state.playing);
+
+        test.run_for(0.5).await;
+        test.server.worldmachine.lock().await.jukebox_command(jukebox, JukeboxCommand::Toggle).unwrap();
+        let state = test.server.worldmachine.lock().await.jukebox_state(jukebox).unwrap();
+        assert!(!state.playing);
+        assert!(state.position > 0.0 && state.position <= length, "stopped at {} of {}", state.position, length);
+
+        // seeking while stopped is remembered for the next play
+        test.server.worldmachine.lock().await.jukebox_command(jukebox, JukeboxCommand::Seek(length / 2.0)).unwrap();
+        let state = test.server.worldmachine.lock().await.jukebox_state(jukebox).unwrap();
+        assert_eq!(state.position, length / 2.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shaped_colliders_block_players() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+        let start = test.player_position(a).await;
+
+        test.spawn("pillar", start + Vec3::new(3.0, 1.0, 0.0), vec![CapsuleCollider::new(Vec3::zero(), 0.5, 2.0)]).await;
+        test.step().await;
+        for _ in 0..30 {
+            test.clients[a].walk(Vec3::new(1.0, 0.0, 0.0), false).await;
+            test.step().await;
+        }
+        let end = test.player_position(a).await;
+        assert!(end.x - start.x > 0.5, "player didn't move at all, from {:?} to {:?}", start, end);
+        assert!(end.x - start.x < 2.5, "player walked through the pillar, from {:?} to {:?}", start, end);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rigid_bodies_fall_and_get_pushed() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        test.step().await;
+        let start = test.player_position(a).await;
+
+        let ball = test.spawn("ball", start + Vec3::new(0.0, 4.0, 6.0), vec![RigidBody::new("sphere", Vec3::zero(), 0.3, 0.0, "", 1.0, 0.5, 0.5, false)]).await;
+        let crate_ = test.spawn("crate", start + Vec3::new(3.0, 0.5, 0.0), vec![RigidBody::default()]).await;
+        let crate_start = test.entity_position(crate_).await;
+        for _ in 0..30 {
+            test.clients[a].walk(Vec3::new(1.0, 0.0, 0.0), false).await;
+            test.step().await;
+        }
+        let ball_end = test.entity_position(ball).await;
+        assert!(ball_end.y < start.y + 3.0, "ball is still up at {:?}", ball_end);
+        let crate_end = test.entity_position(crate_).await;
+        assert!(crate_end.x - crate_start.x > 0.5, "crate only got from {:?} to {:?}", crate_start, crate_end);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn platforms_are_moved_by_the_server() {
+        let mut test = TestServer::new("test").await;
+        // well away from the map, so nothing else gets in the way
+        let start = Vec3::new(-500.0, -300.0, -500.0);
+        let platform = test.spawn("platform", start, vec![
+            RigidBody::new("box", Vec3::new(6.0, 1.0, 6.0), 0.0, 0.0, "", 100.0, 0.5, 0.0, false),
+            Platform::new(&format!("{} {} {}", start.x + 10.0, start.y, start.z), 5.0, 0.0),
+        ]).await;
+        test.run_for(1.0).await;
+
+        let moved = test.entity_position(platform).await;
+        assert!((moved.x - start.x - 5.0).abs() < 0.5, "platform only got to {:?}", moved);
+        assert_eq!(test.parameter(platform, COMPONENT_TYPE_PLATFORM.clone(), "velocity").await, ParameterValue::Vec3(Vec3::new(5.0, 0.0, 0.0)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn moving_things_are_only_sent_reliably_once_they_stop() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        let start = Vec3::new(-500.0, -300.0, -500.0);
+        let platform = test.spawn("platform", start, vec![
+            RigidBody::new("box", Vec3::new(6.0, 1.0, 6.0), 0.0, 0.0, "", 100.0, 0.5, 0.0, false),
+            Platform::new(&format!("{} {} {}", start.x + 10.0, start.y, start.z), 5.0, 0.0),
+        ]).await;
+        let settled = |client: &FakeClient| client.steady_received.iter()
+            .filter(|packet| matches!(packet, SteadyPacket::EntitySetParameter(entity_id, component_type, name, ..) if *entity_id == platform && *component_type == *COMPONENT_TYPE_TRANSFORM && name == "position"))
+            .count();
+
+        test.run_for(1.0).await;
+        assert!(test.clients[a].received_fast(|packet| matches!(packet, FastPacket::EntitySetParameter(entity_id, ..) if *entity_id == platform)));
+        assert_eq!(settled(&test.clients[a]), 0, "a moving platform was sent reliably");
+        // it gets to the end after 2 seconds, then has to sit there for a bit
+        test.run_for(1.5).await;
+        assert_eq!(settled(&test.clients[a]), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn movement_settings_reach_clients() {
+        let mut test = TestServer::new("test").await;
+        let a = test.join().await;
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::MovementSettings(settings) if *settings == MovementSettings::default())));
+
+        let mut low_gravity = MovementSettings::default();
+        low_gravity.set("gravity", -4.0);
+        test.server.worldmachine.lock().await.set_movement_settings(low_gravity);
+        test.step().await;
+        assert!(test.clients[a].received(|packet| matches!(packet, SteadyPacket::MovementSettings(settings) if *settings == low_gravity)));
+    }
+}
@@ -2,7 +2,8 @@ use std::cell::UnsafeCell;
 use halfbrown::HashMap;
 use std::collections::{VecDeque};
 use std::fmt::format;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex};
+use rand::Rng;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
 use tokio::sync::{mpsc};
@@ -21,8 +22,145 @@ use crate::server::{ConnectionUUID, FastPacket, FastPacketData, generate_uuid, P
 use crate::server::connections::SteadyMessageQueue;
 
 pub const FAST_QUEUE_LIMIT: usize = 4;
-pub const FAKE_LAG: bool = false;
-pub const FAKE_LAG_TIME: u64 = 10;
+// how much longer than usual a reordered udp packet is held back, enough to land behind the next few
+const REORDER_DELAY: u64 = 50;
+
+lazy_static! {
+    // what new connections start with, set by the --net-* flags or "net default" in the console
+    pub static ref DEFAULT_NETWORK_CONDITIONS: StdMutex<NetworkConditions> = StdMutex::new(NetworkConditions::default());
+}
+
+/// a simulated bad network, for testing prediction and interpolation on localhost
+/// applied to both directions by whichever end of the connection has it set
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    pub latency: u64, // ms, added to every udp packet
+    pub jitter: u64, // ms, up to this much extra latency at random
+    pub loss: f32, // chance of a udp packet never arriving
+    pub duplication: f32, // chance of a udp packet arriving twice
+    pub reordering: f32, // chance of a udp packet arriving after ones sent later
+    pub tcp_latency: u64, // ms
+    pub bandwidth: u64, // tcp kilobytes per second, 0 for no limit
+}
+
+impl NetworkConditions {
+    pub const SETTINGS: [&'static str; 7] = ["latency", "jitter", "loss", "duplicate", "reorder", "tcp-latency", "bandwidth"];
+
+    /// sets one of SETTINGS from a string, none if the setting doesn't exist or the value doesn't make sense
+    pub fn set(&mut self, setting: &str, value: &str) -> Option<()> {
+        let chance = |value: &str| value.parse::<f32>().ok().filter(|v| (0.0..=1.0).contains(v));
+        match setting {
+            "latency" => self.latency = value.parse().ok()?,
+            "jitter" => self.jitter = value.parse().ok()?,
+            "loss" => self.loss = chance(value)?,
+            "duplicate" => self.duplication = chance(value)?,
+            "reorder" => self.reordering = chance(value)?,
+            "tcp-latency" => self.tcp_latency = value.parse().ok()?,
+            "bandwidth" => self.bandwidth = value.parse().ok()?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// how long after sending each copy of a udp packet should arrive, empty if it got lost
+    pub fn udp_delays(&self) -> Vec<Duration> {
+        let mut rng = rand::thread_rng();
+        if self.loss > 0.0 && rng.gen::<f32>() < self.loss {
+            return Vec::new();
+        }
+        let copies = if self.duplication > 0.0 && rng.gen::<f32>() < self.duplication { 2 } else { 1 };
+        (0..copies).map(|_| {
+            let mut delay = self.latency;
+            if self.jitter > 0 {
+                delay += rng.gen_range(0..=self.jitter);
+            }
+            if self.reordering > 0.0 && rng.gen::<f32>() < self.reordering {
+                delay += REORDER_DELAY;
+            }
+            Duration::from_millis(delay)
+        }).collect()
+    }
+
+    fn transmit_time(&self, size: usize) -> Duration {
+        if self.bandwidth == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(size as f64 / (self.bandwidth as f64 * 1000.0))
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkConditions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == Self::default() {
+            return write!(f, "perfect");
+        }
+        write!(f, "latency {}ms, jitter {}ms, loss {}%, duplicate {}%, reorder {}%, tcp latency {}ms, bandwidth ",
+               self.latency, self.jitter, self.loss * 100.0, self.duplication * 100.0, self.reordering * 100.0, self.tcp_latency)?;
+        if self.bandwidth == 0 {
+            write!(f, "unlimited")
+        } else {
+            write!(f, "{}KB/s", self.bandwidth)
+        }
+    }
+}
+
+// sends a udp packet now, later, twice or not at all, depending on the conditions
+// to is only for unconnected sockets
+async fn send_conditioned(socket: &Arc<UdpSocket>, data: &[u8], to: Option<SocketAddr>, conditions: NetworkConditions) -> std::io::Result<usize> {
+    if conditions == NetworkConditions::default() {
+        return match to {
+            Some(to) => socket.send_to(data, to).await,
+            None => socket.send(data).await,
+        };
+    }
+    for delay in conditions.udp_delays() {
+        let socket = socket.clone();
+        let data = data.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let res = match to {
+                Some(to) => socket.send_to(&data, to).await,
+                None => socket.send(&data).await,
+            };
+            if let Err(e) = res {
+                debug!("failed to send delayed udp packet: {:?}", e);
+            }
+        });
+    }
+    // as far as the sender knows, it went out fine
+    Ok(data.len())
+}
+
+/// holds tcp packets back to simulate latency and limited bandwidth, keeping them in order like tcp would
+struct DelayLine<T> {
+    queue: VecDeque<(Instant, T)>,
+    link_free: Instant, // when the last packet finishes going over the simulated link
+}
+
+impl<T> DelayLine<T> {
+    fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            link_free: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, item: T, size: usize, conditions: &NetworkConditions) {
+        let start = self.link_free.max(Instant::now());
+        self.link_free = start + conditions.transmit_time(size);
+        self.queue.push_back((self.link_free + Duration::from_millis(conditions.tcp_latency), item));
+    }
+
+    // waits for the next packet to be due, forever if there isn't one (so it can sit in a select!)
+    async fn next(&mut self) -> T {
+        match self.queue.front() {
+            Some((due, _)) => tokio::time::sleep_until(*due).await,
+            None => std::future::pending::<()>().await,
+        }
+        self.queue.pop_front().unwrap().1
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct FastUpdateQueue<T> {
@@ -79,6 +217,7 @@ pub struct LanConnection {
     pub remote_addr: SocketAddr,
     pub uuid: ConnectionUUID,
     pub last_successful_ping: Arc<AtomicU64>,
+    pub conditions: Arc<StdMutex<NetworkConditions>>,
 }
 
 unsafe impl Send for LanConnection {}
@@ -92,6 +231,7 @@ pub struct ClientLanConnection {
     pub steady_sender_queue: mpsc::Sender<SteadyPacketData>,
     pub steady_receiver_queue: Arc<Mutex<SteadyMessageQueue>>,
     pub uuid: ConnectionUUID,
+    pub conditions: Arc<StdMutex<NetworkConditions>>,
 }
 
 unsafe impl Send for ClientLanConnection {}
@@ -131,6 +271,7 @@ pub struct LanListener {
     pub fast_update: Arc<UdpSocket>,
     steady_update: Arc<Mutex<TcpListener>>,
     fast_update_map: Arc<Mutex<HashMap<ConnectionUUID, FastUpdateQueue<FastPacketLan>>>>,
+    conditions: Arc<StdMutex<HashMap<ConnectionUUID, Arc<StdMutex<NetworkConditions>>>>>, // for packets coming in
 }
 
 unsafe impl Send for LanListener {}
@@ -153,6 +294,7 @@ impl LanListener {
             fast_update: Arc::new(udp_socket),
             steady_update: Arc::new(Mutex::new(tcp_listener)),
             fast_update_map,
+            conditions: Arc::new(StdMutex::new(HashMap::new())),
         };
 
         let the_clone = the_self.clone();
@@ -274,26 +416,27 @@ impl LanListener {
             //    remote_addr: peer_addr,
             //    uuid: uuid_real,
             //});
-            return Some(LanConnection::new(uuid_real, reader, peer_addr));
+            let connection = LanConnection::new(uuid_real, reader, peer_addr);
+            self.conditions.lock().unwrap().insert(connection.uuid.clone(), connection.conditions.clone());
+            return Some(connection);
         }
 
         None
     }
 
+    pub fn forget_connection(&self, uuid: &ConnectionUUID) {
+        self.conditions.lock().unwrap().remove(uuid);
+    }
+
     async fn send_fast_update(&self, connection: LanConnection, data: &[u8]) -> std::io::Result<usize> {
-        if FAKE_LAG {
-            tokio::time::sleep(Duration::from_millis(FAKE_LAG_TIME)).await;
-        }
-        self.fast_update.send_to(data, connection.remote_addr).await
+        let conditions = *connection.conditions.lock().unwrap();
+        send_conditioned(&self.fast_update, data, Some(connection.remote_addr), conditions).await
     }
 
     pub async fn udp_thread(&self) {
         loop {
             let mut buf = [0; 4096];
             let fast_update = &self.fast_update;
-            if FAKE_LAG {
-                tokio::time::sleep(Duration::from_millis(FAKE_LAG_TIME)).await;
-            }
             let (len, addr) = fast_update.recv_from(&mut buf).await.expect("failed to receive from udp socket");
             let mut deserialiser = rmp_serde::Deserializer::new(&buf[..len]);
             let packet = FastPacketLan::deserialize(&mut deserialiser);
@@ -302,18 +445,35 @@ impl LanListener {
                 continue;
             }
             let mut packet = packet.unwrap();
-            let mut fast_update_map = self.fast_update_map.lock().await;
             packet.socket_addr = Some(addr);
-            if let Some(updates) = fast_update_map.get_mut(&packet.uuid) {
-                updates.push(packet);
-            } else {
-                let new_queue = FastUpdateQueue::<FastPacketLan>::new(Some(packet.clone()));
-                fast_update_map.insert(packet.clone().uuid, new_queue);
+            // connections that are still handshaking don't have conditions yet
+            let conditions = self.conditions.lock().unwrap().get(&packet.uuid).map(|conditions| *conditions.lock().unwrap()).unwrap_or_default();
+            if conditions == NetworkConditions::default() {
+                Self::push_fast_update(&self.fast_update_map, packet).await;
+                continue;
+            }
+            for delay in conditions.udp_delays() {
+                let fast_update_map = self.fast_update_map.clone();
+                let packet = packet.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    Self::push_fast_update(&fast_update_map, packet).await;
+                });
             }
-            drop(fast_update_map);
         }
     }
 
+    async fn push_fast_update(fast_update_map: &Arc<Mutex<HashMap<ConnectionUUID, FastUpdateQueue<FastPacketLan>>>>, packet: FastPacketLan) {
+        let mut fast_update_map = fast_update_map.lock().await;
+        if let Some(updates) = fast_update_map.get_mut(&packet.uuid) {
+            updates.push(packet);
+        } else {
+            let new_queue = FastUpdateQueue::<FastPacketLan>::new(Some(packet.clone()));
+            fast_update_map.insert(packet.clone().uuid, new_queue);
+        }
+        drop(fast_update_map);
+    }
+
     pub async fn check_for_fast_update(&self, uuid: &ConnectionUUID) -> Option<FastPacketLan> {
         let mut fast_update_map = self.fast_update_map.lock().await;
         let update = fast_update_map.get_mut(uuid);
@@ -340,6 +500,7 @@ impl LanConnection {
             remote_addr: peer_addr,
             uuid,
             last_successful_ping: Arc::new(AtomicU64::new(0)),
+            conditions: Arc::new(StdMutex::new(*DEFAULT_NETWORK_CONDITIONS.lock().unwrap())),
         };
         let the_clone = the_self.clone();
         tokio::spawn(async move {
@@ -387,17 +548,20 @@ impl LanConnection {
 
     // sender receives from client and sends to other threads, receiver receives from other threads and sends to client
     pub async fn tcp_thread(&self, sender: mpsc::Sender<SteadyPacketData>, mut receiver: mpsc::Receiver<SteadyPacketData>, mut reader: Framed<TcpStream, LengthDelimitedCodec>) {
+        // everything goes through these, with no network conditions they're let straight through
+        let mut incoming = DelayLine::new();
+        let mut outgoing = DelayLine::new();
         loop {
             tokio::select! {
                 attempt = StreamExt::next(&mut reader) => {
                     if let Some(packet) = attempt {
                         if let Ok(packet) = packet {
                             let mut deserialiser = rmp_serde::Deserializer::new(&packet[..]);
-                            let packet = SteadyPacketData::deserialize(&mut deserialiser);
-                            if let Ok(packet) = packet {
-                                debug!("received steady packet: {:?}", packet);
-                                let uuid = packet.uuid.clone();
-                                sender.send(packet).await.unwrap();
+                            let data = SteadyPacketData::deserialize(&mut deserialiser);
+                            if let Ok(data) = data {
+                                debug!("received steady packet: {:?}", data);
+                                let conditions = *self.conditions.lock().unwrap();
+                                incoming.push(data, packet.len(), &conditions);
                             }
                         }
                     } else {
@@ -406,15 +570,23 @@ impl LanConnection {
                         break;
                     }
                 }
+                packet = incoming.next() => {
+                    sender.send(packet).await.unwrap();
+                }
                 attempt = receiver.recv() => {
                     if let Some(packet) = attempt {
                         debug!("sending steady packet: {:?}", packet);
                         let mut buffer = Vec::new();
                         let mut serialiser = rmp_serde::Serializer::new(&mut buffer);
                         packet.serialize(&mut serialiser).unwrap();
-                        reader.send(Bytes::from(buffer)).await.unwrap();
+                        let conditions = *self.conditions.lock().unwrap();
+                        let size = buffer.len();
+                        outgoing.push(Bytes::from(buffer), size, &conditions);
                     }
                 }
+                buffer = outgoing.next() => {
+                    reader.send(buffer).await.unwrap();
+                }
             }
         }
     }
@@ -491,6 +663,7 @@ impl ClientLanConnection {
                 steady_sender_queue: sender,
                 steady_receiver_queue: Arc::new(Mutex::new(SteadyMessageQueue::new())),
                 uuid,
+                conditions: Arc::new(StdMutex::new(*DEFAULT_NETWORK_CONDITIONS.lock().unwrap())),
             }, reader, receiver));
         }
 
@@ -498,7 +671,8 @@ impl ClientLanConnection {
     }
 
     async fn send_fast_update(&self, data: &[u8]) -> std::io::Result<usize> {
-        self.fast_update.send(data).await
+        let conditions = *self.conditions.lock().unwrap();
+        send_conditioned(&self.fast_update, data, None, conditions).await
     }
 
     async fn block_receive_fast_update(&self, data: &mut [u8]) -> std::io::Result<usize> {
@@ -534,12 +708,26 @@ impl ClientLanConnection {
             }
             let packet = packet.unwrap();
             if let FastPacketPotentials::FastPacket(packet) = packet.data {
-                self.fast_update_queue.lock().await.push(packet);
+                let conditions = *self.conditions.lock().unwrap();
+                if conditions == NetworkConditions::default() {
+                    self.fast_update_queue.lock().await.push(packet);
+                    continue;
+                }
+                for delay in conditions.udp_delays() {
+                    let fast_update_queue = self.fast_update_queue.clone();
+                    let packet = packet.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        fast_update_queue.lock().await.push(packet);
+                    });
+                }
             }
         }
     }
 
     pub async fn tcp_listener_thread(&self, mut reader: Framed<TcpStream, LengthDelimitedCodec>, mut receiver: mpsc::Receiver<SteadyPacketData>) {
+        let mut incoming = DelayLine::new();
+        let mut outgoing = DelayLine::new();
         loop {
             tokio::select! {
                 attempt = StreamExt::next(&mut reader) => {
@@ -551,8 +739,8 @@ impl ClientLanConnection {
                                 warn!("failed to deserialise steady update: {:?}", packet);
                             } else {
                                 let packet = packet.unwrap();
-                                let uuid = packet.uuid.clone();
-                                self.steady_receiver_queue.lock().await.push(packet);
+                                let conditions = *self.conditions.lock().unwrap();
+                                incoming.push(packet, attempt.len(), &conditions);
                             }
                         }
                     } else {
@@ -560,6 +748,9 @@ impl ClientLanConnection {
                         break;
                     }
                 }
+                packet = incoming.next() => {
+                    self.steady_receiver_queue.lock().await.push(packet);
+                }
                 attempt = receiver.recv() => {
                     if let Some(attempt) = attempt {
                         debug!("sending steady update: {:?}", attempt);
@@ -567,10 +758,15 @@ impl ClientLanConnection {
                         let mut buffer = Vec::new();
                         let mut serialiser = rmp_serde::Serializer::new(&mut buffer);
                         attempt.serialize(&mut serialiser).unwrap();
-                        let attempt = reader.send(Bytes::from(buffer)).await;
-                        if attempt.is_err() {
-                            warn!("failed to send steady update: {:?}", attempt);
-                        }
+                        let conditions = *self.conditions.lock().unwrap();
+                        let size = buffer.len();
+                        outgoing.push(Bytes::from(buffer), size, &conditions);
+                    }
+                }
+                buffer = outgoing.next() => {
+                    let attempt = reader.send(buffer).await;
+                    if attempt.is_err() {
+                        warn!("failed to send steady update: {:?}", attempt);
                     }
                 }
             }
@@ -12,16 +12,26 @@ use tokio::time::{Instant, Duration};
 use serde::{Serialize, Deserialize};
 use tokio::net::TcpStream;
 use tokio_util::codec::Encoder;
+use crate::hotreload::{HotReloader, ReloadEvent};
+use crate::maps::triggers::WhoTriggered;
 use crate::physics::PhysicsSystem;
+use crate::server::bots::Bot;
 use crate::server::connections::SteadyMessageQueue;
+use crate::server::demo::{DemoHeader, DemoPacket, DemoPlayer, DemoRecorder, DEMO_VERSION};
 use crate::server::lan::{ClientLanConnection, LanConnection, LanListener};
-use crate::server::server_player::{ServerPlayer, ServerPlayerContainer};
+use crate::server::server_player::{DEFAULT_HEIGHT, ServerPlayer, ServerPlayerContainer};
 use crate::worldmachine::{EntityId, WorldMachine, WorldUpdate};
-use crate::worldmachine::ecs::{ComponentType, Entity, ParameterValue};
+use crate::worldmachine::ecs::{ComponentType, Entity, ParameterValue, Replication, System};
+use crate::worldmachine::movement::MovementSettings;
 use crate::worldmachine::player::{MovementInfo, PlayerComponent};
 use crate::worldmachine::snowballs::Snowball;
 
+pub mod bots;
 pub mod connections;
+pub mod console;
+pub mod demo;
+#[cfg(test)]
+pub mod harness;
 pub mod server_player;
 pub mod lan;
 
@@ -32,6 +42,7 @@ pub type ConnectionUUID = String;
 pub enum Connection {
     Local(Arc<LocalConnection>),
     Lan(LanListener, LanConnection),
+    Bot(Arc<Bot>), // a player the server controls itself, nothing is sent anywhere
 }
 
 impl Debug for Connection {
@@ -39,6 +50,7 @@ impl Debug for Connection {
         match self {
             Connection::Local(_) => write!(f, "LocalConnection"),
             Connection::Lan(_, _) => write!(f, "LanConnection"),
+            Connection::Bot(_) => write!(f, "BotConnection"),
         }
     }
 }
@@ -47,6 +59,7 @@ impl Debug for Connection {
 pub enum ConnectionClientside {
     Local(Arc<Mutex<LocalConnectionClientSide>>),
     Lan(ClientLanConnection),
+    Demo(Arc<Mutex<DemoPlayer>>), // not a server at all, packets come from a recording
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -55,7 +68,7 @@ pub enum FastPacket {
     ChangeRotation(EntityId, Quaternion),
     ChangeScale(EntityId, Vec3),
     PlayerMoved(EntityId, Vec3, Quaternion, Quaternion),
-    EntitySetParameter(EntityId, ComponentType, String, ParameterValue),
+    EntitySetParameter(EntityId, ComponentType, String, ParameterValue, u64), // the last one is the server tick it changed on
     PlayerMove(ConnectionUUID, Vec3, Vec3, Quaternion, Quaternion, Option<MovementInfo>),
     // connection uuid, position, displacement_vector, rotation, head rotation, movement info
     PlayerJump(ConnectionUUID),
@@ -85,6 +98,9 @@ pub enum NameRejectionReason {
 pub enum SteadyPacket {
     InitialiseEntity(EntityId, Entity),
     RemoveEntity(EntityId),
+    SetParent(EntityId, Option<EntityId>), // entity, new parent
+    EntitySetParameter(EntityId, ComponentType, String, ParameterValue, u64), // for parameters that can't be dropped, see Replication
+    InitialiseSystems(Vec<System>),
     FinaliseMapLoad,
     InitialisePlayer(ConnectionUUID, EntityId, String, Vec3, Quaternion, Vec3),
     // uuid, (entity id so we know to ignore updates from that entity), name, position, rotation, scale
@@ -93,7 +109,10 @@ pub enum SteadyPacket {
     SetName(ConnectionUUID, String),
     NameRejected(NameRejectionReason),
     Respawn(Vec3), // position
+    Use(ConnectionUUID), // the player pressed use, the server fills in the uuid
     ThrowSnowball(String, Vec3, Vec3), // uuid, position, initial velocity
+    ServerTime(f64), // the answer to a ping, seconds since the server started (see WorldMachine::server_time)
+    MovementSettings(MovementSettings), // how players move on this map, sent after InitialisePlayer and whenever it changes
 
     Ping,
 }
@@ -134,6 +153,7 @@ pub struct Server {
     pub connections: Connections,
     pub connections_incoming: Arc<Mutex<VecDeque<TcpStream>>>,
     pub worldmachine: Arc<Mutex<WorldMachine>>,
+    pub demo: Option<Arc<Mutex<DemoRecorder>>>,
 }
 
 pub fn generate_uuid() -> PacketUUID {
@@ -154,6 +174,7 @@ impl Server {
             connections: Connections::Local(Arc::new(Mutex::new(Vec::new()))),
             connections_incoming: Arc::new(Mutex::new(VecDeque::new())),
             worldmachine: Arc::new(Mutex::new(worldmachine)),
+            demo: None,
         }
     }
 
@@ -170,6 +191,7 @@ impl Server {
             connections: Connections::Lan(listener.clone(), Arc::new(Mutex::new(Vec::new()))),
             connections_incoming: Arc::new(Mutex::new(VecDeque::new())),
             worldmachine: Arc::new(Mutex::new(worldmachine)),
+            demo: None,
         };
         let the_clone = the_self.clone();
         let listener_clone = listener;
@@ -183,6 +205,25 @@ impl Server {
         the_self
     }
 
+    /// starts recording everything sent to all clients, call this before cloning the server
+    pub async fn record_demo(&mut self, path: &str) {
+        let worldmachine = self.worldmachine.lock().await;
+        let header = DemoHeader {
+            version: DEMO_VERSION,
+            map: worldmachine.current_map().to_string(),
+            entities: worldmachine.world.entities.iter().map(|(_, entity)| entity.clone()).collect(),
+            systems: worldmachine.world.systems.clone(),
+        };
+        drop(worldmachine);
+        self.demo = DemoRecorder::new(path, &header).map(|recorder| Arc::new(Mutex::new(recorder)));
+    }
+
+    async fn record(&self, packet: DemoPacket) {
+        if let Some(demo) = &self.demo {
+            demo.lock().await.record(packet);
+        }
+    }
+
     async fn connection_listening_thread(&self, listener: LanListener) {
         loop {
             let new_connection = listener.poll_new_connection().await;
@@ -216,6 +257,7 @@ impl Server {
         match connection {
             Connection::Local(local_connection) => local_connection.uuid.clone(),
             Connection::Lan(_, lan_connection) => lan_connection.uuid.clone(),
+            Connection::Bot(bot) => bot.uuid.clone(),
         }
     }
 
@@ -231,6 +273,7 @@ impl Server {
                 let res = connection.serialise_and_send_steady(packet.clone()).await;
                 return res.is_ok()
             }
+            Connection::Bot(_) => {}
         }
         true
     }
@@ -257,9 +300,30 @@ impl Server {
                     self.send_steady_packet_unsafe(&Connection::Lan(listener, connection), packet_data).await
                 }
             }
+            // bots read the world directly, they don't need telling
+            Connection::Bot(_) => true,
+        }
+    }
+
+    /// sends a steady packet to every connection, and to the demo if one is being recorded
+    pub async fn broadcast_steady_packet(&self, packet: SteadyPacket) {
+        self.record(DemoPacket::Steady(packet.clone())).await;
+        for connection in self.get_all_connections().await {
+            self.send_steady_packet(&connection, packet.clone()).await;
         }
     }
 
+    /// sends a steady packet meant for one player, the demo gets it too along with who it was for
+    async fn send_targeted_steady_packet(&self, connection: &Connection, packet: SteadyPacket) -> bool {
+        self.record(DemoPacket::SteadyTo(self.get_connection_uuid(connection).await, packet.clone())).await;
+        self.send_steady_packet(connection, packet).await
+    }
+
+    async fn send_targeted_fast_packet(&self, connection: &Connection, packet: FastPacket) {
+        self.record(DemoPacket::FastTo(self.get_connection_uuid(connection).await, packet.clone())).await;
+        self.send_fast_packet(connection, packet).await
+    }
+
     pub async fn send_fast_packet(&self, connection: &Connection, packet: FastPacket) {
         match connection.clone() {
             Connection::Local(connection) => {
@@ -275,6 +339,7 @@ impl Server {
                 };
                 connection.serialise_and_send_fast(connection.uuid.clone(), listener.clone(), packet_data).await.unwrap();
             }
+            Connection::Bot(_) => {}
         }
     }
 
@@ -294,6 +359,7 @@ impl Server {
                     return Some(packet.packet.unwrap());
                 }
             }
+            Connection::Bot(_) => {}
         }
         None
     }
@@ -303,15 +369,20 @@ impl Server {
         // for each entity in the worldmachine, send an initialise packet
         let world_clone = worldmachine.world.clone();
         let physics = worldmachine.physics.lock().unwrap().clone().unwrap();
+        let movement_settings = worldmachine.movement_settings();
         // drop worldmachine so we don't hold the lock while we send packets
         drop(worldmachine);
-        for entity in world_clone.entities.iter() {
+        for (_, entity) in world_clone.entities.iter() {
             let res = self.send_steady_packet(&connection, SteadyPacket::InitialiseEntity(entity.uid, entity.clone())).await;
             if !res {
                 return None;
             }
         }
         debug!("sent all entity initialise packets");
+        let res = self.send_steady_packet(&connection, SteadyPacket::InitialiseSystems(world_clone.systems.clone())).await;
+        if !res {
+            return None;
+        }
         let uuid = self.get_connection_uuid(&connection).await;
 
         let name = "morbius";
@@ -323,18 +394,20 @@ impl Server {
         let mut player = ServerPlayer::new(uuid.as_str(), name, position, rotation, scale);
 
         player.init(physics.clone()).await;
+        player.set_movement_settings(movement_settings).await;
 
         let mut player_entity = Entity::new(player.name.lock().await.as_str());
         let entity_uuid = player_entity.uid;
+        player.set_entity(entity_uuid).await;
         let player_component = PlayerComponent::new(name, uuid.clone(), position, rotation, scale);
         player_entity.add_component(player_component);
 
         // relock worldmachine
         let mut worldmachine = self.worldmachine.lock().await;
-        worldmachine.world.entities.push(player_entity.clone());
+        worldmachine.world.entities.insert(player_entity.clone());
 
         drop(worldmachine);
-        let res = self.send_steady_packet(&connection, SteadyPacket::InitialisePlayer(
+        let res = self.send_targeted_steady_packet(&connection, SteadyPacket::InitialisePlayer(
             player.uuid.to_string(),
             entity_uuid,
             player.name.lock().await.clone(),
@@ -345,9 +418,13 @@ impl Server {
             return None;
         }
         debug!("sent player initialise packet");
+        let res = self.send_targeted_steady_packet(&connection, SteadyPacket::MovementSettings(movement_settings)).await;
+        if !res {
+            return None;
+        }
         let mut worldmachine = self.worldmachine.lock().await;
-        worldmachine.world.entities.push(player_entity.clone());
         worldmachine.queue_update(WorldUpdate::InitEntity(entity_uuid, player_entity.clone())).await;
+        worldmachine.run_script_hook("on_player_join", (entity_uuid as i64, name.to_string()));
 
         let players = worldmachine.players.clone();
         drop(worldmachine);
@@ -360,6 +437,9 @@ impl Server {
         });
 
 
+        // a first guess at the clock so that jukeboxes start in the right place, pings will refine it
+        let server_time = self.worldmachine.lock().await.server_time();
+        self.send_targeted_steady_packet(&connection, SteadyPacket::ServerTime(server_time)).await;
         let res = self.send_steady_packet(&connection, SteadyPacket::FinaliseMapLoad).await;
 
         if res {
@@ -377,9 +457,12 @@ impl Server {
                 debug!("client sent initialise packet");
             }
             SteadyPacket::InitialisePlayer(_, _, _, _, _, _) => {}
+            SteadyPacket::InitialiseSystems(_) => {}
             SteadyPacket::Message(_) => {}
             SteadyPacket::FinaliseMapLoad => {}
             SteadyPacket::RemoveEntity(_) => {}
+            SteadyPacket::SetParent(_, _) => {}
+            SteadyPacket::EntitySetParameter(_, _, _, _, _) => {}
             SteadyPacket::ChatMessage(_who_sent, message) => {
                 // mirror to all other clients
                 let who_sent = match player.connection.clone() {
@@ -389,22 +472,11 @@ impl Server {
                     Connection::Lan(listener, connection) => {
                         connection.uuid.clone()
                     }
-                };
-                let packet = SteadyPacket::ChatMessage(who_sent, message);
-                match &self.connections {
-                    Connections::Local(local_connections) => {
-                        let cons = local_connections.lock().await.clone();
-                        for connection in cons.iter() {
-                            self.send_steady_packet(&Connection::Local(connection.clone()), packet.clone()).await;
-                        }
-                    }
-                    Connections::Lan(listener, connections) => {
-                        let cons = connections.lock().await.clone();
-                        for a_connection in cons.iter() {
-                            self.send_steady_packet(&Connection::Lan(listener.clone(), a_connection.clone()), packet.clone()).await;
-                        }
+                    Connection::Bot(bot) => {
+                        bot.uuid.clone()
                     }
-                }
+                };
+                self.broadcast_steady_packet(SteadyPacket::ChatMessage(who_sent, message)).await;
             }
             SteadyPacket::SetName(_who_sent, new_name) => {
                 // mirror to all other clients
@@ -415,14 +487,13 @@ impl Server {
                     Connection::Lan(listener, connection) => {
                         connection.uuid.clone()
                     }
+                    Connection::Bot(bot) => {
+                        bot.uuid.clone()
+                    }
                 };
-                let packet = SteadyPacket::SetName(who_sent, new_name.clone());
                 match &self.connections {
-                    Connections::Local(local_connections) => {
-                        let cons = local_connections.lock().await.clone();
-                        for connection in cons.iter() {
-                            self.send_steady_packet(&Connection::Local(connection.clone()), packet.clone()).await;
-                        }
+                    Connections::Local(_) => {
+                        self.broadcast_steady_packet(SteadyPacket::SetName(who_sent, new_name.clone())).await;
                     }
                     Connections::Lan(listener, connections) => {
                         // check if name is taken
@@ -438,7 +509,7 @@ impl Server {
                         drop(players);
                         drop(wm);
                         let connection = match &player.connection {
-                            Connection::Local(_) => unreachable!(),
+                            Connection::Local(_) | Connection::Bot(_) => unreachable!(),
                             Connection::Lan(_, connection) => connection,
                         };
                         if name_taken {
@@ -453,18 +524,14 @@ impl Server {
                             drop(wm);
 
                             // send to all other clients
-                            let packet = SteadyPacket::SetName(connection.uuid.clone(), new_name);
-                            let cons = connections.lock().await.clone();
-                            for a_connection in cons {
-                                self.send_steady_packet(&Connection::Lan(listener.clone(), a_connection.clone()), packet.clone()).await;
-                            }
+                            self.broadcast_steady_packet(SteadyPacket::SetName(connection.uuid.clone(), new_name)).await;
                         }
                     }
                 }
             }
             SteadyPacket::ThrowSnowball(_uuid, _positon, _initial_velocity) => {
                 // as server is authoritative, calculate the snowball's position and velocity ourselves
-                // position will be the player's position
+                // position will be in front of the player's head, from their feet it'd splat on the ground straight away
                 // velocity will be the player's velocity + the player's forward vector * 10
                 debug!("player threw snowball");
                 let snowball_cooldown = *player.player.snowball_cooldown.lock().await;
@@ -475,7 +542,7 @@ impl Server {
                     rotation.w = -rotation.w;
                     let forward = rotation.forward();
                     let forward = Vec3::new(forward.x, forward.y, forward.z);
-                    let position = forward * 1.5 + Vec3::new(0.0, 0.1, 0.0) + position;
+                    let position = forward * 1.5 + Vec3::new(0.0, DEFAULT_HEIGHT, 0.0) + position;
                     let velocity = forward * 20.0 + Vec3::new(0.0, 5.0, 0.0);
                     let worldmachine = self.worldmachine.lock().await;
                     let physics = worldmachine.physics.clone();
@@ -488,24 +555,15 @@ impl Server {
                     let mut worldmachine = self.worldmachine.lock().await;
                     worldmachine.snowballs.push(snowball);
                     drop(worldmachine);
-                    match &self.connections {
-                        Connections::Local(local_connections) => {
-                            let cons = local_connections.lock().await.clone();
-                            for connection in cons.iter() {
-                                self.send_steady_packet(&Connection::Local(connection.clone()), packet.clone()).await;
-                            }
-                        }
-                        Connections::Lan(listener, connections) => {
-                            let cons = connections.lock().await.clone();
-                            for a_connection in cons.iter() {
-                                self.send_steady_packet(&Connection::Lan(listener.clone(), a_connection.clone()), packet.clone()).await;
-                            }
-                        }
-                    }
+                    self.broadcast_steady_packet(packet).await;
 
                 }
             }
+            SteadyPacket::ServerTime(_) => {}
+            SteadyPacket::MovementSettings(_) => {}
             SteadyPacket::Ping => {
+                let server_time = self.worldmachine.lock().await.server_time();
+                self.send_targeted_steady_packet(&player.connection, SteadyPacket::ServerTime(server_time)).await;
                 match &player.connection {
                     Connection::Local(local_connection) => {
                     }
@@ -513,8 +571,15 @@ impl Server {
                         let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
                         connection.last_successful_ping.store(unix_time, Ordering::Relaxed);
                     }
+                    Connection::Bot(_) => {}
                 };
             }
+            SteadyPacket::Use(_) => {
+                if let Some(entity_id) = player.entity_id {
+                    let mut worldmachine = self.worldmachine.lock().await;
+                    worldmachine.use_triggers(WhoTriggered::Player(entity_id));
+                }
+            }
             SteadyPacket::NameRejected(_) => {}
             SteadyPacket::Respawn(_) => {}
         }
@@ -546,6 +611,8 @@ impl Server {
                     }
                 }
             }
+            // bots act in tick_bots instead of sending packets
+            Connection::Bot(_) => {}
         }
         true
     }
@@ -556,7 +623,7 @@ impl Server {
                 player.player.attempt_position_change(position, displacement_vector, rotation, head_rotation, movement_info.unwrap_or_default(), player.entity_id, self.worldmachine.clone()).await
             };
             if success {} else {
-                self.send_fast_packet(&player.connection, FastPacket::PlayerFuckYouMoveHere(correct_position.unwrap())).await
+                self.send_targeted_fast_packet(&player.connection, FastPacket::PlayerFuckYouMoveHere(correct_position.unwrap())).await
             }
         }
     }
@@ -571,7 +638,7 @@ impl Server {
                 if success {} else {
                     let position = player.player.get_position(player.entity_id, Some(&mut worldmachine)).await;
                     drop(worldmachine);
-                    self.send_fast_packet(&player.connection, FastPacket::PlayerFuckYouMoveHere(position)).await
+                    self.send_targeted_fast_packet(&player.connection, FastPacket::PlayerFuckYouMoveHere(position)).await
                 }
             }
         }
@@ -614,7 +681,7 @@ impl Server {
                             FastPacket::ChangeScale(_, _) => {}
                             FastPacket::PlayerFuckYouMoveHere(_) => {}
                             FastPacket::PlayerFuckYouSetRotation(_) => {}
-                            FastPacket::EntitySetParameter(_, _, _, _) => {}
+                            FastPacket::EntitySetParameter(_, _, _, _, _) => {}
                             FastPacket::PlayerMoved(_, _, _, _) => {}
                         }
                     }
@@ -643,38 +710,42 @@ impl Server {
                         FastPacket::ChangeScale(_, _) => {}
                         FastPacket::PlayerFuckYouMoveHere(_) => {}
                         FastPacket::PlayerFuckYouSetRotation(_) => {}
-                        FastPacket::EntitySetParameter(_, _, _, _) => {}
+                        FastPacket::EntitySetParameter(_, _, _, _, _) => {}
                         FastPacket::PlayerMoved(_, _, _, _) => {}
                     }
                 }
             }
+            Connection::Bot(_) => {}
         }
     }
 
     pub async fn handle_connection(&self, connection: Connection, player: ServerPlayerContainer) -> bool {
         let mut tcp_receiver = match &connection {
-            Connection::Local(_) => { None }
+            Connection::Local(_) | Connection::Bot(_) => { None }
             Connection::Lan(_, connection) => {
                 connection.steady_receiver_passthrough.lock().await.take()
             }
         };
         loop {
-            match connection.clone() {
-                Connection::Local(local_connection) => {
-                    self.handle_fast_packets(&player).await;
-                    self.handle_steady_packets(&player, &mut None).await;
-                }
-                Connection::Lan(listener, lan_connection) => {
-                    self.handle_fast_packets(&player).await;
-                    let connection = self.handle_steady_packets(&player, &mut tcp_receiver).await;
-                    if !connection {
-                        return false;
-                    }
-                }
+            if !self.poll_connection(&player, &mut tcp_receiver).await {
+                return false;
             }
         }
     }
 
+    /// handles at most one fast and one steady packet from a player, returns false if their connection has gone away
+    pub async fn poll_connection(&self, player: &ServerPlayerContainer, tcp_receiver: &mut Option<mpsc::Receiver<SteadyPacketData>>) -> bool {
+        self.handle_fast_packets(player).await;
+        match &player.connection {
+            Connection::Local(_) => {
+                self.handle_steady_packets(player, &mut None).await;
+                true
+            }
+            Connection::Lan(_, _) => self.handle_steady_packets(player, tcp_receiver).await,
+            Connection::Bot(_) => true,
+        }
+    }
+
     async fn assert_connection_type_allowed(&self, connection: Connection) -> bool {
         match connection {
             Connection::Local(_) => {
@@ -683,33 +754,38 @@ impl Server {
             Connection::Lan(_, _) => {
                 matches!(self.connections.clone(), Connections::Lan(_, _))
             }
+            Connection::Bot(_) => true,
         }
     }
 
     async fn disconnect_player(&self, uuid: ConnectionUUID, player_entity_id: EntityId) {
-        let connections = match self.connections.clone() {
-            Connections::Lan(_, connections) => {
-                connections.clone()
+        // bots aren't in either list, so there's nothing to remove for them here
+        match self.connections.clone() {
+            Connections::Lan(listener, connections) => {
+                listener.forget_connection(&uuid);
+                let mut connections = connections.lock().await;
+                connections.retain(|x| x.uuid != uuid);
+                debug!("connections: {:?}", connections.len());
             }
-            _ => {
-                panic!("assert_connection_type_allowed failed");
+            Connections::Local(connections) => {
+                connections.lock().await.retain(|x| x.uuid != uuid);
             }
-        };
-        let mut connections = connections.lock().await;
-        connections.retain(|x| x.uuid != uuid);
-        debug!("connections: {:?}", connections.len());
-        drop(connections);
+        }
         // remove the player from the world
         let worldmachine = self.worldmachine.clone();
         let mut worldmachine = worldmachine.lock().await;
-        if worldmachine.world.entities.iter().any(|x| x.uid == player_entity_id) {
-            worldmachine.world.entities.retain(|x| x.uid != player_entity_id);
+        if worldmachine.remove_entity(player_entity_id).is_some() {
             worldmachine.queue_update(WorldUpdate::EntityNoLongerExists(player_entity_id)).await;
         }
         let players = worldmachine.players.clone();
         drop(worldmachine);
         if let Some(players) = players {
             let mut players = players.lock().await;
+            for (_, x) in players.iter() {
+                if x.entity_id == Some(player_entity_id) {
+                    x.player.clear_entity().await;
+                }
+            }
             players.retain(|_, x| x.entity_id != Some(player_entity_id));
         }
     }
@@ -764,12 +840,34 @@ impl Server {
                         self.disconnect_player(lan_connection.uuid, entity_id).await;
                     }
                 }
+                // bots come from add_bot, there's no connection to listen to
+                Connection::Bot(_) => {}
             }
         }
     }
 
     pub async fn join_local_server(&mut self) -> Arc<Mutex<LocalConnectionClientSide>> {
         info!("joining local server");
+        let (local_connection, local_connection_client_side) = self.create_local_connection();
+        struct ThreadData {
+            server: Server,
+            connection: Arc<Mutex<LocalConnectionClientSide>>,
+        }
+        let connection = Arc::new(Mutex::new(local_connection_client_side));
+        let thread_data = ThreadData {
+            server: self.clone(),
+            connection: connection.clone(),
+        };
+        tokio::spawn(async move {
+            let thread_data = thread_data;
+            let connection = Arc::new(local_connection);
+            thread_data.server.new_connection(Connection::Local(connection)).await;
+        });
+        connection
+    }
+
+    // both ends of a local connection, nothing is listening on either yet
+    fn create_local_connection(&self) -> (LocalConnection, LocalConnectionClientSide) {
         let (fast_update_sender_client, fast_update_receiver_server) = mpsc::channel(100);
         let (steady_update_sender_client, steady_update_receiver_server) = mpsc::channel(100);
         let (fast_update_sender_server, fast_update_receiver_client) = mpsc::channel(100);
@@ -791,21 +889,7 @@ impl Server {
             fast_update_receiver: fast_update_receiver_client,
             steady_update_receiver: steady_update_receiver_client,
         };
-        struct ThreadData {
-            server: Server,
-            connection: Arc<Mutex<LocalConnectionClientSide>>,
-        }
-        let connection = Arc::new(Mutex::new(local_connection_client_side));
-        let thread_data = ThreadData {
-            server: self.clone(),
-            connection: connection.clone(),
-        };
-        tokio::spawn(async move {
-            let thread_data = thread_data;
-            let connection = Arc::new(local_connection);
-            thread_data.server.new_connection(Connection::Local(connection)).await;
-        });
-        connection
+        (local_connection, local_connection_client_side)
     }
 
     async fn get_connections_affected_from_position(&mut self, position: Vec3) -> Vec<Connection> {
@@ -829,7 +913,7 @@ impl Server {
         connections_affected
     }
 
-    async fn get_all_connections(&mut self) -> Vec<Connection> {
+    async fn get_all_connections(&self) -> Vec<Connection> {
         let mut connections_final = Vec::new();
         match self.connections.clone() {
             Connections::Local(connections) => {
@@ -853,34 +937,87 @@ impl Server {
         for update in updates {
             match update {
                 WorldUpdate::SetPosition(entity_id, vec3) => {
+                    self.record(DemoPacket::Fast(FastPacket::ChangePosition(entity_id, vec3))).await;
                     let connections = self.get_connections_affected_from_position(vec3).await;
                     for connection in connections {
                         self.send_fast_packet(&connection, FastPacket::ChangePosition(entity_id, vec3)).await;
                     }
                 }
                 WorldUpdate::SetRotation(entity_id, quat) => {
+                    self.record(DemoPacket::Fast(FastPacket::ChangeRotation(entity_id, quat))).await;
                     let connections = self.get_connections_affected_from_position(Vec3::new(0.0, 0.0, 0.0)).await;
                     for connection in connections {
                         self.send_fast_packet(&connection, FastPacket::ChangeRotation(entity_id, quat)).await;
                     }
                 }
                 WorldUpdate::SetScale(entity_id, vec3) => {
+                    self.record(DemoPacket::Fast(FastPacket::ChangeScale(entity_id, vec3))).await;
                     let connections = self.get_connections_affected_from_position(Vec3::new(0.0, 0.0, 0.0)).await;
                     for connection in connections {
                         self.send_fast_packet(&connection, FastPacket::ChangeScale(entity_id, vec3)).await;
                     }
                 }
                 WorldUpdate::InitEntity(entity_id, entity_data) => {
-                    let connections = self.get_all_connections().await;
-                    for connection in connections {
-                        self.send_steady_packet(&connection, SteadyPacket::InitialiseEntity(entity_id, entity_data.clone())).await;
+                    self.broadcast_steady_packet(SteadyPacket::InitialiseEntity(entity_id, entity_data)).await;
+                }
+                WorldUpdate::SetParent(entity_id, parent) => {
+                    self.broadcast_steady_packet(SteadyPacket::SetParent(entity_id, parent)).await;
+                }
+                WorldUpdate::SetParameter(entity_id, component_type, parameter_name, value, tick) => {
+                    match component_type.replication(&parameter_name) {
+                        Replication::Reliable => {
+                            self.broadcast_steady_packet(SteadyPacket::EntitySetParameter(entity_id, component_type, parameter_name, value, tick)).await;
+                        }
+                        Replication::Unreliable => {
+                            self.record(DemoPacket::Fast(FastPacket::EntitySetParameter(entity_id, component_type.clone(), parameter_name.clone(), value.clone(), tick))).await;
+                            let connections = self.get_all_connections().await;
+                            for connection in connections {
+                                self.send_fast_packet(&connection, FastPacket::EntitySetParameter(entity_id, component_type.clone(), parameter_name.clone(), value.clone(), tick)).await;
+                            }
+                        }
                     }
                 }
-                WorldUpdate::EntityNoLongerExists(entity_id) => {
-                    let connections = self.get_all_connections().await;
-                    for connection in connections {
-                        self.send_steady_packet(&connection, SteadyPacket::RemoveEntity(entity_id)).await;
+                WorldUpdate::SettleParameter(entity_id, component_type, parameter_name, value, tick) => {
+                    self.broadcast_steady_packet(SteadyPacket::EntitySetParameter(entity_id, component_type, parameter_name, value, tick)).await;
+                }
+                WorldUpdate::ChatMessage(who, message) => {
+                    self.broadcast_steady_packet(SteadyPacket::ChatMessage(who, message)).await;
+                }
+                WorldUpdate::TeleportPlayer(entity_id, position) => {
+                    let players = self.worldmachine.lock().await.players.clone();
+                    let player = match players {
+                        Some(players) => {
+                            let players = players.lock().await;
+                            let player = players.values().find(|p| p.entity_id == Some(entity_id)).cloned();
+                            player
+                        }
+                        None => None,
+                    };
+                    if let Some(player) = player {
+                        player.player.respawning.store(true, Ordering::Relaxed);
+                        self.send_targeted_steady_packet(&player.connection, SteadyPacket::Respawn(position)).await;
+                        let mut worldmachine = self.worldmachine.lock().await;
+                        player.player.set_position(position, player.entity_id, &mut worldmachine).await;
+                        drop(worldmachine);
+                        player.player.respawning.store(false, Ordering::Relaxed);
+                    } else {
+                        warn!("teleport: entity {} is not a player", entity_id);
+                    }
+                }
+                WorldUpdate::SetSystems(systems) => {
+                    self.broadcast_steady_packet(SteadyPacket::InitialiseSystems(systems)).await;
+                }
+                WorldUpdate::SetMovementSettings(settings) => {
+                    let players = self.worldmachine.lock().await.players.clone();
+                    if let Some(players) = players {
+                        for player in players.lock().await.values() {
+                            player.player.set_movement_settings(settings).await;
+                        }
                     }
+                    self.broadcast_steady_packet(SteadyPacket::MovementSettings(settings)).await;
+                }
+                WorldUpdate::EntityNoLongerExists(entity_id) => {
+                    self.broadcast_steady_packet(SteadyPacket::RemoveEntity(entity_id)).await;
                 }
                 WorldUpdate::MovePlayerEntity(entity_id, position, rotation, head_rotation) => {
                     player_entity_movement_stack.entry(entity_id).or_insert(Vec::new()).push((position, rotation, head_rotation));
@@ -890,6 +1027,7 @@ impl Server {
         // the last player entity movement for each entity is the one that should be sent to the client
         for (entity_id, movement_stack) in player_entity_movement_stack {
             let movement = movement_stack.last().unwrap();
+            self.record(DemoPacket::Fast(FastPacket::PlayerMoved(entity_id, movement.0, movement.1, movement.2))).await;
             let connections = self.get_connections_affected_from_position(movement.0).await;
             for connection in connections {
                 self.send_fast_packet(&connection, FastPacket::PlayerMoved(entity_id, movement.0, movement.1, movement.2)).await;
@@ -897,6 +1035,40 @@ impl Server {
         }
     }
 
+    /// reloads maps, scripts and entity definitions as they change on disk, connected players stay connected
+    /// only spawned with --hot-reload
+    pub async fn hot_reload_thread(&self) {
+        let game_data_path = self.worldmachine.lock().await.game_data_path.clone();
+        let mut reloader = match HotReloader::new(&game_data_path) {
+            Some(reloader) => reloader,
+            None => return,
+        };
+        loop {
+            for event in reloader.poll() {
+                let mut worldmachine = self.worldmachine.lock().await;
+                match event {
+                    ReloadEvent::Map(map) if map == worldmachine.current_map() => {
+                        if let Err(e) = worldmachine.reload_map() {
+                            warn!("failed to reload map {}: {:?}", map, e);
+                        }
+                    }
+                    ReloadEvent::Script(map) if map == worldmachine.current_map() => {
+                        worldmachine.reload_map_script();
+                    }
+                    ReloadEvent::Movement(map) if map == worldmachine.current_map() => {
+                        let settings = MovementSettings::load(&worldmachine.game_data_path, &map);
+                        worldmachine.set_movement_settings(settings);
+                    }
+                    ReloadEvent::EntityDef(prefab) => {
+                        worldmachine.reload_prefab(&prefab);
+                    }
+                    _ => {} // textures and meshes are the client's problem
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     pub async fn physics_thread(&self) {
         loop {
         }
@@ -904,71 +1076,91 @@ impl Server {
 
     pub async fn player_and_physics_tick_thread(&self) {
         let mut compensation_delta = 0.0;// do physics tick
-        let mut delta;
         loop {
-            {
-                let worldmachine = self.worldmachine.lock().await;
-                let last_physics_tick = worldmachine.last_physics_update;
-                drop(worldmachine);
-                let current_time = std::time::Instant::now();
-                delta = (current_time - last_physics_tick).as_secs_f32();
-                if delta > 0.01 {
-                    let mut worldmachine = self.worldmachine.lock().await;
-                    let res = worldmachine.physics.lock().unwrap().as_mut().unwrap().tick(delta + compensation_delta);
-                    if let Some(delta) = res {
-                        compensation_delta += delta;
-                    } else {
-                        compensation_delta = 0.0;
-                        worldmachine.last_physics_update = current_time;
-                    }
+            let last_physics_tick = self.worldmachine.lock().await.last_physics_update;
+            let delta = (Instant::now() - last_physics_tick).as_secs_f32();
+            if delta > 0.01 {
+                if let Some(delta) = self.physics_step(delta, compensation_delta).await {
+                    compensation_delta += delta;
+                } else {
+                    compensation_delta = 0.0;
+                }
+            }
+            self.tick_players(delta).await;
+            self.tick_bots().await;
+        }
+    }
+
+    /// ticks physics, then runs systems, npcs, rigid bodies, jukeboxes, triggers, snowballs and the map script if it actually stepped,
+    /// and settles any unreliable parameters that have stopped changing
+    /// returns the time physics didn't simulate, which should be added on to the next step
+    pub async fn physics_step(&self, delta: f32, compensation_delta: f32) -> Option<f32> {
+        let current_time = Instant::now();
+        let mut worldmachine = self.worldmachine.lock().await;
+        let res = worldmachine.physics.lock().unwrap().as_mut().unwrap().tick(delta + compensation_delta);
+        if res.is_none() {
+            worldmachine.last_physics_update = current_time;
+            worldmachine.run_systems(delta);
+            worldmachine.tick_npcs(delta);
+            worldmachine.tick_platforms(delta);
+            worldmachine.tick_rigid_bodies(delta);
+            worldmachine.tick_jukeboxes();
+            worldmachine.process_trigger_events();
+            worldmachine.tick_snowballs(delta);
+            worldmachine.run_script_hook("on_tick", (delta as f64,));
+            worldmachine.settle_parameters();
+        }
+        res
+    }
+
+    /// gravity, snowball cooldowns, respawning and timeouts for every connected player
+    pub async fn tick_players(&self, delta: f32) {
+        let worldmachine = self.worldmachine.lock().await;
+        let players = worldmachine.players.clone().unwrap();
+        drop(worldmachine);
+        let mut players_to_disconnect = Vec::new();
+        let mut players = players.lock().await.clone();
+        for (_uuid, player) in players.iter_mut() {
+            let last_ping = match &player.connection {
+                Connection::Local(_) | Connection::Bot(_) => { SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() }
+                Connection::Lan(_, con) => {
+                    con.last_successful_ping.load(Ordering::Relaxed)
                 }
+            };
+            let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+            if current_time - last_ping > 60 {
+                players_to_disconnect.push((player.connection.clone(), player.entity_id.unwrap()));
+                continue;
             }
-            {
-                let worldmachine = self.worldmachine.lock().await;
-                let players = worldmachine.players.clone().unwrap();
+            if player.player.gravity_tick().await {
+                let mut worldmachine = self.worldmachine.lock().await;
+                let _pos = player.player.get_position(player.entity_id, Some(&mut worldmachine)).await;
                 drop(worldmachine);
-                let mut players_to_disconnect = Vec::new();
-                let mut players = players.lock().await.clone();
-                for (_uuid, player) in players.iter_mut() {
-                    let last_ping = match &player.connection {
-                        Connection::Local(_) => { SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() }
-                        Connection::Lan(_, con) => {
-                            con.last_successful_ping.load(Ordering::Relaxed)
-                        }
-                    };
-                    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-                    if current_time - last_ping > 60 {
-                        players_to_disconnect.push((player.connection.clone(), player.entity_id.unwrap()));
-                        continue;
-                    }
-                    if player.player.gravity_tick().await {
-                        let mut worldmachine = self.worldmachine.lock().await;
-                        let _pos = player.player.get_position(player.entity_id, Some(&mut worldmachine)).await;
-                        drop(worldmachine);
-                    }
-                    *player.player.snowball_cooldown.lock().await -= delta;
-                    let position = player.player.get_position(None, None).await;
-                    if position.y < -20.0 {
-                        player.player.respawning.store(true, Ordering::Relaxed);
-                        let respawning = player.player.respawning.clone();
-                        let packet = SteadyPacket::Respawn(Vec3::new(0.0, 0.0, 0.0));
-                        self.send_steady_packet(&player.connection, packet).await;
-                        let mut worldmachine = self.worldmachine.lock().await;
-                        player.player.set_position(Vec3::new(0.0, 0.0, 0.0), player.entity_id, &mut worldmachine).await;
-                        drop(worldmachine);
-                        respawning.store(false, Ordering::Relaxed);
-                    }
+            }
+            *player.player.snowball_cooldown.lock().await -= delta;
+            let position = player.player.get_position(None, None).await;
+            if position.y < -20.0 {
+                player.player.respawning.store(true, Ordering::Relaxed);
+                let respawning = player.player.respawning.clone();
+                let packet = SteadyPacket::Respawn(Vec3::new(0.0, 0.0, 0.0));
+                self.send_targeted_steady_packet(&player.connection, packet).await;
+                let mut worldmachine = self.worldmachine.lock().await;
+                player.player.set_position(Vec3::new(0.0, 0.0, 0.0), player.entity_id, &mut worldmachine).await;
+                drop(worldmachine);
+                respawning.store(false, Ordering::Relaxed);
+            }
+        }
+        drop(players);
+        for player in players_to_disconnect {
+            match player.0 {
+                Connection::Local(con) => {
+                    self.disconnect_player(con.uuid.clone(), player.1).await;
                 }
-                drop(players);
-                for player in players_to_disconnect {
-                    match player.0 {
-                        Connection::Local(con) => {
-                            self.disconnect_player(con.uuid.clone(), player.1).await;
-                        }
-                        Connection::Lan(_, con) => {
-                            self.disconnect_player(con.uuid.clone(), player.1).await;
-                        }
-                    }
+                Connection::Lan(_, con) => {
+                    self.disconnect_player(con.uuid.clone(), player.1).await;
+                }
+                Connection::Bot(bot) => {
+                    self.disconnect_player(bot.uuid.clone(), player.1).await;
                 }
             }
         }
@@ -980,19 +1172,20 @@ impl Server {
     // if not, run the worldmachine
     pub async fn run(&mut self) {
         loop {
-            // lock worldmachine
-            {
-                let mut worldmachine = self.worldmachine.lock().await;
-                let updates = {
-                    worldmachine.server_tick().await
-                };
-                drop(worldmachine);
-                if let Some(updates) = updates {
-                    self.handle_world_updates(updates).await;
-                }
-            }
-
+            self.send_world_updates().await;
             self.listen_for_lan_connections().await;
         }
     }
+
+    /// sends out everything the worldmachine has queued up since last time
+    pub async fn send_world_updates(&mut self) {
+        let mut worldmachine = self.worldmachine.lock().await;
+        let updates = {
+            worldmachine.server_tick().await
+        };
+        drop(worldmachine);
+        if let Some(updates) = updates {
+            self.handle_world_updates(updates).await;
+        }
+    }
 }
@@ -4,18 +4,23 @@ use std::sync::atomic::AtomicBool;
 use gfx_maths::*;
 use tokio::time::Instant;
 use crate::helpers;
-use crate::physics::{ClimbingMode, Materials, PhysicsCharacterController, PhysicsSystem};
+use crate::physics::{ActorOwner, ClimbingMode, Materials, MovementMode, PhysicsCharacterController, PhysicsSystem};
 use crate::server::{Connection, Server};
 use crate::worldmachine::{EntityId, WorldMachine, WorldUpdate};
 use crate::worldmachine::components::COMPONENT_TYPE_PLAYER;
 use crate::worldmachine::ecs::ParameterValue;
+use crate::worldmachine::movement::MovementSettings;
 use crate::worldmachine::player::MovementInfo;
 
 pub const DEFAULT_MOVESPEED: f32 = 8.15;
 pub const DEFAULT_SPRINTSPEED: f32 = 14.4;
+pub const DEFAULT_CROUCHSPEED: f32 = 4.0;
+pub const DEFAULT_CLIMBSPEED: f32 = 5.0;
+pub const DEFAULT_SWIMSPEED: f32 = 6.0;
 pub const DEFAULT_RADIUS: f32 = 1.3;
 pub const DEFAULT_HEIGHT: f32 = 1.7;
 pub const DEFAULT_STEPHEIGHT: f32 = 0.5;
+pub const DEFAULT_CROUCH_HEIGHT: f32 = 0.5;
 
 pub const ERROR_MARGIN: f32 = 5.0;
 pub const MAX_HEIGHT_BEFORE_FLIGHT: f32 = 15.0;
@@ -33,7 +38,8 @@ struct PlayerPhysics {
     rotation: Quaternion,
     pub scale: Vec3,
     physics_controller: Option<PhysicsCharacterController>,
-    movement_speed: f32,
+    movement_settings: MovementSettings,
+    velocity: Vec3, // horizontal, see MovementSettings::next_velocity
     last_move_call: Instant,
     height_gained_since_grounded: f32,
     last_height: f32,
@@ -47,7 +53,8 @@ impl Default for PlayerPhysics {
             rotation: Quaternion::new(0.0, 0.0, 0.0, 1.0),
             scale: Vec3::new(1.0, 1.0, 1.0),
             physics_controller: None,
-            movement_speed: DEFAULT_MOVESPEED,
+            movement_settings: MovementSettings::default(),
+            velocity: Vec3::new(0.0, 0.0, 0.0),
             last_move_call: Instant::now(),
             height_gained_since_grounded: 0.0,
             last_height: 0.0,
@@ -97,9 +104,33 @@ impl ServerPlayer {
 
     pub async fn init(&self, physics_system: PhysicsSystem) {
         let mut physics = self.physics.lock().await;
-        physics.physics_controller = physics_system.create_character_controller(DEFAULT_RADIUS, DEFAULT_HEIGHT, DEFAULT_STEPHEIGHT, Materials::Player);
-        if physics.physics_controller.is_none() {
-            warn!("failed to create physics controller for player");
+        let settings = physics.movement_settings;
+        physics.physics_controller = physics_system.create_character_controller(settings.radius, settings.height, settings.step_height, Materials::Player);
+        match physics.physics_controller.as_ref() {
+            Some(controller) => settings.apply(controller),
+            None => warn!("failed to create physics controller for player"),
+        }
+    }
+
+    /// the map's movement settings, the client is sent the same ones so its prediction agrees with us
+    pub async fn set_movement_settings(&self, settings: MovementSettings) {
+        let mut physics = self.physics.lock().await;
+        physics.movement_settings = settings;
+        if let Some(controller) = physics.physics_controller.as_ref() {
+            settings.apply(controller);
+        }
+    }
+
+    /// lets physics callbacks (triggers) know which entity this player's controller belongs to
+    pub async fn set_entity(&self, entity_id: EntityId) {
+        if let Some(controller) = self.physics.lock().await.physics_controller.as_ref() {
+            controller.set_owner(ActorOwner::Player(entity_id));
+        }
+    }
+
+    pub async fn clear_entity(&self) {
+        if let Some(controller) = self.physics.lock().await.physics_controller.as_ref() {
+            controller.clear_owner();
         }
     }
 
@@ -126,39 +157,63 @@ impl ServerPlayer {
             return (false, Some(last_position));
         }
 
-        if movement_info.speed.is_nan() || movement_info.strafe.is_nan() {
+        let wish_direction = movement_info.wish_direction;
+        if movement_info.speed.is_nan() || movement_info.strafe.is_nan() || wish_direction.x.is_nan() || wish_direction.y.is_nan() || wish_direction.z.is_nan() {
             return (false, Some(last_position));
         }
 
+        // ladders and water are decided here, not by what the client says
+        let mode = worldmachine.lock().await.movement_mode_at(last_position);
+
         let mut physics = self.physics.lock().await;
 
-        if movement_info.sprinting {
-            physics.movement_speed = DEFAULT_SPRINTSPEED;
-        } else {
-            physics.movement_speed = DEFAULT_MOVESPEED;
+        let settings = physics.movement_settings;
+        let mut crouching = false;
+        if let Some(controller) = physics.physics_controller.as_ref() {
+            controller.set_movement_mode(mode);
+            // the client can ask to stand up, but it stays down if there's no room here
+            if movement_info.crouching && mode == MovementMode::Walk {
+                controller.crouch(settings.crouch_height);
+            } else if controller.is_crouching() {
+                controller.stand();
+            }
+            crouching = controller.is_crouching();
         }
 
-        let mut displacement_vector = displacement_vector;
-        displacement_vector.y = 0.0;
-        displacement_vector = helpers::clamp_magnitude(displacement_vector, 1.0 * physics.movement_speed);
+        // only ladders let a player move up and down by themselves, the server works out swimming on its own
+        // like walking, this goes by what the client wanted to do (forwards is up) rather than how far it says it went
+        let climb = match mode {
+            MovementMode::Climb => movement_info.speed.clamp(-1.0, 1.0) * settings.climb_speed,
+            _ => 0.0,
+        };
 
         let current_time = Instant::now();
         let last_move_call = physics.last_move_call;
         let delta = current_time.duration_since(last_move_call.clone()).as_secs_f32();
-        displacement_vector *= delta;
         if delta >= 0.01 {
             physics.last_move_call = current_time;
-            let _final_movement = physics.physics_controller.as_mut().unwrap().move_by(displacement_vector, movement_info.jumped, Some(false), false, delta, delta);
+            // the client's velocity is only a claim, this works it out again from what it wanted to do, the same way the client did
+            let velocity = match physics.physics_controller.as_ref() {
+                Some(controller) => settings.next_velocity(physics.velocity, wish_direction, mode, crouching, movement_info.sprinting, controller, delta),
+                None => Vec3::zero(),
+            };
+            physics.velocity = velocity;
+            let mut displacement_vector = velocity * delta;
+            displacement_vector.y = climb * delta;
+            // gravity is left to gravity_tick, but water pushes players about as they move
+            let swimming = matches!(mode, MovementMode::Swim(_));
+            let _final_movement = physics.physics_controller.as_mut().unwrap().move_by(displacement_vector, movement_info.jumped, Some(swimming), false, delta, delta);
         }
         let current_time = Instant::now();
-        let delta = current_time.duration_since(Instant::from(worldmachine.lock().await.last_physics_update)).as_secs_f32();
+        let delta = current_time.duration_since(worldmachine.lock().await.last_physics_update).as_secs_f32();
         if delta >= 0.01 {
             worldmachine.lock().await.physics.lock().unwrap().as_mut().unwrap().tick(delta);
-            worldmachine.lock().await.last_physics_update = std::time::Instant::from(current_time);
+            worldmachine.lock().await.last_physics_update = current_time;
         }
         let new_position_calculated = physics.physics_controller.as_mut().unwrap().get_foot_position();
         let distance = helpers::distance(new_position_calculated, new_position);
-        if !physics.physics_controller.as_ref().unwrap().is_on_ground() {
+        // climbing and swimming count as being on the ground
+        if !physics.physics_controller.as_ref().unwrap().is_on_ground() && mode == MovementMode::Walk {
             physics.height_gained_since_grounded += physics.last_height - new_position_calculated.y;
         } else {
             physics.height_gained_since_grounded = 0.0;
@@ -181,6 +236,7 @@ impl ServerPlayer {
             drop(wm);
             let mut wm = worldmachine.lock().await;
             self.set_head_rotation(new_head_rotation, entity_id, &mut wm).await;
+            self.set_crouching(crouching, entity_id, &mut wm);
             drop(wm);
             (true, None)
         } else {
@@ -192,12 +248,27 @@ impl ServerPlayer {
             drop(wm);
             let mut wm = worldmachine.lock().await;
             self.set_head_rotation(new_head_rotation, entity_id, &mut wm).await;
+            self.set_crouching(crouching, entity_id, &mut wm);
             drop(wm);
             let position = self.get_position(None, None).await;
             (false, Some(position))
         }
     }
 
+    /// roughly where the player would end up wanting to go in wish_direction for delta seconds, with momentum, the way a client would claim it
+    /// bots have no client to do this for them. nothing moves here, attempt_position_change does the one real move and goes with its own result if this is off
+    pub async fn predict_move(&self, wish_direction: Vec3, delta: f32) -> Vec3 {
+        let physics = self.physics.lock().await;
+        let settings = physics.movement_settings;
+        match physics.physics_controller.as_ref() {
+            Some(controller) => {
+                let velocity = settings.next_velocity(physics.velocity, wish_direction, controller.movement_mode(), controller.is_crouching(), false, controller, delta);
+                controller.get_foot_position() + velocity * delta
+            }
+            None => physics.position + wish_direction * settings.move_speed * delta,
+        }
+    }
+
     pub async fn gravity_tick(&self) -> bool {
         let mut physics = self.physics.lock().await;
         let last_move_call = physics.last_move_call;
@@ -224,13 +295,11 @@ impl ServerPlayer {
             physics_controller.set_foot_position(position);
         }
         if let Some(entity_id) = entity_id {
-            let entity_index = worldmachine.get_entity_index(entity_id);
-            if let None = entity_index {
-                warn!("failed to set position of entity: {}", entity_id);
-            } else {
-                let entity_index = entity_index.unwrap();
-                worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "position", ParameterValue::Vec3(position));
+            if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "position", ParameterValue::Vec3(position));
                 worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, position, physics.rotation, physics.head_rotation)).await;
+            } else {
+                warn!("failed to set position of entity: {}", entity_id);
             }
         }
     }
@@ -239,13 +308,11 @@ impl ServerPlayer {
         let mut physics = self.physics.lock().await;
         physics.rotation = rotation;
         if let Some(entity_id) = entity_id {
-            let entity_index = worldmachine.get_entity_index(entity_id);
-            if let None = entity_index {
-                warn!("failed to set rotation of entity: {}", entity_id);
-            } else {
-                let entity_index = entity_index.unwrap();
-                worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "rotation", ParameterValue::Quaternion(rotation));
+            if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "rotation", ParameterValue::Quaternion(rotation));
                 worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, physics.position, rotation, physics.head_rotation)).await;
+            } else {
+                warn!("failed to set rotation of entity: {}", entity_id);
             }
         }
     }
@@ -254,28 +321,39 @@ impl ServerPlayer {
         let mut physics = self.physics.lock().await;
         physics.head_rotation = rotation;
         if let Some(entity_id) = entity_id {
-            let entity_index = worldmachine.get_entity_index(entity_id);
-            if let None = entity_index {
-                warn!("failed to set head rotation of entity: {}", entity_id);
-            } else {
-                let entity_index = entity_index.unwrap();
-                worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "head_rotation", ParameterValue::Quaternion(rotation));
+            if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "head_rotation", ParameterValue::Quaternion(rotation));
                 worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, physics.position, physics.rotation, rotation)).await;
+            } else {
+                warn!("failed to set head rotation of entity: {}", entity_id);
             }
         }
     }
 
+    /// lets everyone else see whether this player is crouching, for animation
+    pub fn set_crouching(&self, crouching: bool, entity_id: Option<EntityId>, worldmachine: &mut WorldMachine) {
+        let entity_id = match entity_id {
+            Some(entity_id) => entity_id,
+            None => return,
+        };
+        let old = worldmachine.get_entity(entity_id)
+            .and_then(|entity| entity.get_component(COMPONENT_TYPE_PLAYER.clone()))
+            .map(|player| player.get_parameter("crouching").value.clone());
+        let crouching = ParameterValue::Bool(crouching);
+        if matches!(old, Some(old) if old != crouching) {
+            worldmachine.set_component_parameter(entity_id, COMPONENT_TYPE_PLAYER.clone(), "crouching", crouching);
+        }
+    }
+
     pub async fn set_scale(&self, scale: Vec3, entity_id: Option<EntityId>, worldmachine: &mut WorldMachine) {
         let mut physics = self.physics.lock().await;
         physics.scale = scale;
         if let Some(entity_id) = entity_id {
-            let entity_index = worldmachine.get_entity_index(entity_id);
-            if let None = entity_index {
-                warn!("failed to set scale of entity: {}", entity_id);
-            } else {
-                let entity_index = entity_index.unwrap();
-                worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "scale", ParameterValue::Vec3(scale));
+            if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "scale", ParameterValue::Vec3(scale));
                 worldmachine.queue_update(WorldUpdate::SetScale(entity_id, scale)).await;
+            } else {
+                warn!("failed to set scale of entity: {}", entity_id);
             }
         }
     }
@@ -290,13 +368,11 @@ impl ServerPlayer {
         physics.position = position;
         if let Some(entity_id) = entity_id {
             if let Some(worldmachine) = worldmachine {
-                let entity_index = worldmachine.get_entity_index(entity_id);
-                if let None = entity_index {
-                    warn!("failed to get position of entity: {}", entity_id);
-                } else {
-                    let entity_index = entity_index.unwrap();
-                    worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "position", ParameterValue::Vec3(position));
+                if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                    entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "position", ParameterValue::Vec3(position));
                     worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, position, physics.rotation, physics.head_rotation)).await;
+                } else {
+                    warn!("failed to get position of entity: {}", entity_id);
                 }
             }
         }
@@ -307,13 +383,11 @@ impl ServerPlayer {
         let physics = self.physics.lock().await;
         if let Some(entity_id) = entity_id {
             if let Some(worldmachine) = worldmachine {
-                let entity_index = worldmachine.get_entity_index(entity_id);
-                if let None = entity_index {
-                    warn!("failed to get rotation of entity: {}", entity_id);
-                } else {
-                    let entity_index = entity_index.unwrap();
-                    worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "rotation", ParameterValue::Quaternion(physics.rotation));
+                if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                    entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "rotation", ParameterValue::Quaternion(physics.rotation));
                     worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, physics.position, physics.rotation, physics.head_rotation)).await;
+                } else {
+                    warn!("failed to get rotation of entity: {}", entity_id);
                 }
             }
         }
@@ -324,13 +398,11 @@ impl ServerPlayer {
         let physics = self.physics.lock().await;
         if let Some(entity_id) = entity_id {
             if let Some(worldmachine) = worldmachine {
-                let entity_index = worldmachine.get_entity_index(entity_id);
-                if let None = entity_index {
-                    warn!("failed to get head rotation of entity: {}", entity_id);
-                } else {
-                    let entity_index = entity_index.unwrap();
-                    worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "head_rotation", ParameterValue::Quaternion(physics.head_rotation));
+                if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                    entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "head_rotation", ParameterValue::Quaternion(physics.head_rotation));
                     worldmachine.queue_update(WorldUpdate::MovePlayerEntity(entity_id, physics.position, physics.rotation, physics.head_rotation)).await;
+                } else {
+                    warn!("failed to get head rotation of entity: {}", entity_id);
                 }
             }
         }
@@ -341,13 +413,11 @@ impl ServerPlayer {
         let physics = self.physics.lock().await;
         if let Some(entity_id) = entity_id {
             if let Some(worldmachine) = worldmachine {
-                let entity_index = worldmachine.get_entity_index(entity_id);
-                if let None = entity_index {
-                    warn!("failed to get scale of entity: {}", entity_id);
-                } else {
-                    let entity_index = entity_index.unwrap();
-                    worldmachine.world.entities[entity_index].set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "scale", ParameterValue::Vec3(physics.scale));
+                if let Some(entity) = worldmachine.get_entity_mut(entity_id) {
+                    entity.set_component_parameter(COMPONENT_TYPE_PLAYER.clone(), "scale", ParameterValue::Vec3(physics.scale));
                     worldmachine.queue_update(WorldUpdate::SetScale(entity_id, physics.scale)).await;
+                } else {
+                    warn!("failed to get scale of entity: {}", entity_id);
                 }
             }
         }
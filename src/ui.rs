@@ -14,6 +14,7 @@ lazy_static!{
     pub static ref SHOW_DEBUG_LOCATION: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
     pub static ref SHOW_FPS: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
     pub static ref SHOW_DEBUG_LOG: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+    pub static ref SHOW_NAVMESH: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     pub static ref DEBUG_LOCATION: Arc<Mutex<Vec3>> = Arc::new(Mutex::new(Vec3::new(0.0, 0.0, 0.0)));
     pub static ref BOB_T: Arc<Mutex<f32>> = Arc::new(Mutex::new(0.0));
     pub static ref FPS: Arc<Mutex<f32>> = Arc::new(Mutex::new(0.0));
@@ -13,6 +13,16 @@ lazy_static! {
     pub static ref COMPONENT_TYPE_BOX_COLLIDER: ComponentType = ComponentType::create_if_not_exists("BoxCollider");
     pub static ref COMPONENT_TYPE_JUKEBOX: ComponentType = ComponentType::create_if_not_exists("Jukebox");
     pub static ref COMPONENT_TYPE_TRIGGER: ComponentType = ComponentType::create_if_not_exists("Trigger");
+    pub static ref COMPONENT_TYPE_SPINNER: ComponentType = ComponentType::create_if_not_exists("Spinner");
+    pub static ref COMPONENT_TYPE_NPC: ComponentType = ComponentType::create_if_not_exists("Npc");
+    pub static ref COMPONENT_TYPE_SPHERE_COLLIDER: ComponentType = ComponentType::create_if_not_exists("SphereCollider");
+    pub static ref COMPONENT_TYPE_CAPSULE_COLLIDER: ComponentType = ComponentType::create_if_not_exists("CapsuleCollider");
+    pub static ref COMPONENT_TYPE_MESH_COLLIDER: ComponentType = ComponentType::create_if_not_exists("MeshCollider");
+    pub static ref COMPONENT_TYPE_CONVEX_COLLIDER: ComponentType = ComponentType::create_if_not_exists("ConvexCollider");
+    pub static ref COMPONENT_TYPE_RIGID_BODY: ComponentType = ComponentType::create_if_not_exists("RigidBody");
+    pub static ref COMPONENT_TYPE_LADDER: ComponentType = ComponentType::create_if_not_exists("Ladder");
+    pub static ref COMPONENT_TYPE_WATER: ComponentType = ComponentType::create_if_not_exists("Water");
+    pub static ref COMPONENT_TYPE_PLATFORM: ComponentType = ComponentType::create_if_not_exists("Platform");
 
     pub static ref COMPONENTS_INITIALISED: bool = {
         register_component_types();
@@ -30,6 +40,27 @@ pub fn register_component_types() {
     let _ = COMPONENT_TYPE_BOX_COLLIDER.clone();
     let _ = COMPONENT_TYPE_JUKEBOX.clone();
     let _ = COMPONENT_TYPE_TRIGGER.clone();
+    let _ = COMPONENT_TYPE_SPINNER.clone();
+    let _ = COMPONENT_TYPE_NPC.clone();
+    let _ = COMPONENT_TYPE_SPHERE_COLLIDER.clone();
+    let _ = COMPONENT_TYPE_CAPSULE_COLLIDER.clone();
+    let _ = COMPONENT_TYPE_MESH_COLLIDER.clone();
+    let _ = COMPONENT_TYPE_CONVEX_COLLIDER.clone();
+    let _ = COMPONENT_TYPE_RIGID_BODY.clone();
+    let _ = COMPONENT_TYPE_LADDER.clone();
+    let _ = COMPONENT_TYPE_WATER.clone();
+    let _ = COMPONENT_TYPE_PLATFORM.clone();
+
+    // these change constantly and only the latest value matters, so losing one is fine
+    for parameter in ["position", "rotation", "scale"] {
+        COMPONENT_TYPE_TRANSFORM.set_replication(parameter, Replication::Unreliable);
+    }
+    for parameter in ["position", "rotation", "head_rotation", "speed", "strafe"] {
+        COMPONENT_TYPE_PLAYER.set_replication(parameter, Replication::Unreliable);
+    }
+    for parameter in ["speed", "strafe"] {
+        COMPONENT_TYPE_NPC.set_replication(parameter, Replication::Unreliable);
+    }
 }
 
 // player component is defined in src/worldmachine/player.rs
@@ -117,12 +148,17 @@ impl Terrain {
 pub struct Jukebox {}
 
 impl Jukebox {
+    // the server is in charge of playback (see worldmachine::jukebox), started_at is in server time
+    // playlist is track names separated by semicolons, leave it empty to loop track
     pub fn new() -> Component {
         let mut parameters = BTreeMap::new();
         parameters.insert("volume".to_string(), Parameter::new("volume", ParameterValue::Float(1.0)));
         parameters.insert("playing".to_string(), Parameter::new("playing", ParameterValue::Bool(false)));
         parameters.insert("track".to_string(), Parameter::new("track", ParameterValue::String("".to_string())));
         parameters.insert("uuid".to_string(), Parameter::new("uuid", ParameterValue::String("".to_string())));
+        parameters.insert("playlist".to_string(), Parameter::new("playlist", ParameterValue::String("".to_string())));
+        parameters.insert("started_at".to_string(), Parameter::new("started_at", ParameterValue::Float(0.0)));
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Float(0.0)));
 
         Component {
             name: "Jukebox".to_string(),
@@ -133,4 +169,258 @@ impl Jukebox {
     pub fn default() -> Component {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+pub struct Spinner {}
+
+impl Spinner {
+    // speed is in degrees per second around each axis
+    pub fn new(speed: f64, axis: Vec3) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("speed".to_string(), Parameter::new("speed", ParameterValue::Float(speed)));
+        parameters.insert("axis".to_string(), Parameter::new("axis", ParameterValue::Vec3(axis)));
+
+        Component {
+            name: "Spinner".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_SPINNER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(90.0, Vec3::new(0.0, 1.0, 0.0))
+    }
+}
+
+pub struct Trigger {}
+
+impl Trigger {
+    // trigger_type is "enter" or "use", report is "players", "client", "npcs" or "all" (see maps::triggers)
+    // handler is the name of a function registered with maps::triggers::register_handler
+    pub fn new(position: Vec3, size: Vec3, handler: &str, trigger_type: &str, report: &str) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Vec3(position)));
+        parameters.insert("size".to_string(), Parameter::new("size", ParameterValue::Vec3(size)));
+        parameters.insert("handler".to_string(), Parameter::new("handler", ParameterValue::String(handler.to_string())));
+        parameters.insert("trigger_type".to_string(), Parameter::new("trigger_type", ParameterValue::String(trigger_type.to_string())));
+        parameters.insert("report".to_string(), Parameter::new("report", ParameterValue::String(report.to_string())));
+
+        Component {
+            name: "Trigger".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_TRIGGER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), "", "enter", "all")
+    }
+}
+
+pub struct Npc {}
+
+impl Npc {
+    // the server moves npcs around with their own character controller and writes where they are into their Transform
+    // state is "idle", "patrol" or "flee" (see worldmachine::npcs), waypoints are "x y z" separated by semicolons
+    // a flee_radius of 0 means it never runs away from anyone
+    pub fn new(mesh: &str, texture: &str, state: &str, waypoints: &str, move_speed: f64, flee_radius: f64) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("mesh".to_string(), Parameter::new("mesh", ParameterValue::String(mesh.to_string())));
+        parameters.insert("texture".to_string(), Parameter::new("texture", ParameterValue::String(texture.to_string())));
+        parameters.insert("state".to_string(), Parameter::new("state", ParameterValue::String(state.to_string())));
+        parameters.insert("waypoints".to_string(), Parameter::new("waypoints", ParameterValue::String(waypoints.to_string())));
+        parameters.insert("move_speed".to_string(), Parameter::new("move_speed", ParameterValue::Float(move_speed)));
+        parameters.insert("flee_radius".to_string(), Parameter::new("flee_radius", ParameterValue::Float(flee_radius)));
+        parameters.insert("speed".to_string(), Parameter::new("speed", ParameterValue::Float(0.0)));
+        parameters.insert("strafe".to_string(), Parameter::new("strafe", ParameterValue::Float(0.0)));
+
+        Component {
+            name: "Npc".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_NPC.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new("player", "default", "idle", "", 4.0, 0.0)
+    }
+}
+
+// more static colliders, like BoxCollider the position is an offset from the entity
+// every collider can have a "material", one of the surfaces in base/materials.yaml. missing or empty means default
+
+pub struct SphereCollider {}
+
+impl SphereCollider {
+    pub fn new(position: Vec3, radius: f64) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Vec3(position)));
+        parameters.insert("radius".to_string(), Parameter::new("radius", ParameterValue::Float(radius)));
+        parameters.insert("material".to_string(), Parameter::new("material", ParameterValue::String("default".to_string())));
+
+        Component {
+            name: "SphereCollider".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_SPHERE_COLLIDER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), 0.5)
+    }
+}
+
+pub struct CapsuleCollider {}
+
+impl CapsuleCollider {
+    // stands upright, height is the distance between the centres of the two rounded ends
+    pub fn new(position: Vec3, radius: f64, height: f64) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Vec3(position)));
+        parameters.insert("radius".to_string(), Parameter::new("radius", ParameterValue::Float(radius)));
+        parameters.insert("height".to_string(), Parameter::new("height", ParameterValue::Float(height)));
+        parameters.insert("material".to_string(), Parameter::new("material", ParameterValue::String("default".to_string())));
+
+        Component {
+            name: "CapsuleCollider".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_CAPSULE_COLLIDER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), 0.5, 1.0)
+    }
+}
+
+pub struct MeshCollider {}
+
+impl MeshCollider {
+    // the exact triangles of base/models/<mesh>.glb, placed with the entity's transform like a MeshRenderer
+    pub fn new(mesh: &str) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("mesh".to_string(), Parameter::new("mesh", ParameterValue::String(mesh.to_string())));
+        parameters.insert("material".to_string(), Parameter::new("material", ParameterValue::String("default".to_string())));
+
+        Component {
+            name: "MeshCollider".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_MESH_COLLIDER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new("level")
+    }
+}
+
+pub struct ConvexCollider {}
+
+impl ConvexCollider {
+    // the convex hull of base/models/<mesh>.glb, cheaper than a MeshCollider for props
+    pub fn new(mesh: &str) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("mesh".to_string(), Parameter::new("mesh", ParameterValue::String(mesh.to_string())));
+        parameters.insert("material".to_string(), Parameter::new("material", ParameterValue::String("default".to_string())));
+
+        Component {
+            name: "ConvexCollider".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_CONVEX_COLLIDER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new("banana")
+    }
+}
+
+pub struct RigidBody {}
+
+impl RigidBody {
+    // simulated by the server, which writes where it ends up into the entity's Transform. clients get a kinematic copy that follows it
+    // shape is "box" (size), "sphere" (radius), "capsule" (radius, height) or "convex" (mesh), all scaled by the Transform
+    // kinematic bodies don't fall or get pushed, they go wherever their Transform says and push everything else out of the way
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(shape: &str, size: Vec3, radius: f64, height: f64, mesh: &str, mass: f64, friction: f64, restitution: f64, kinematic: bool) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("shape".to_string(), Parameter::new("shape", ParameterValue::String(shape.to_string())));
+        parameters.insert("size".to_string(), Parameter::new("size", ParameterValue::Vec3(size)));
+        parameters.insert("radius".to_string(), Parameter::new("radius", ParameterValue::Float(radius)));
+        parameters.insert("height".to_string(), Parameter::new("height", ParameterValue::Float(height)));
+        parameters.insert("mesh".to_string(), Parameter::new("mesh", ParameterValue::String(mesh.to_string())));
+        parameters.insert("mass".to_string(), Parameter::new("mass", ParameterValue::Float(mass)));
+        parameters.insert("friction".to_string(), Parameter::new("friction", ParameterValue::Float(friction)));
+        parameters.insert("restitution".to_string(), Parameter::new("restitution", ParameterValue::Float(restitution)));
+        parameters.insert("kinematic".to_string(), Parameter::new("kinematic", ParameterValue::Bool(kinematic)));
+
+        Component {
+            name: "RigidBody".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_RIGID_BODY.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new("box", Vec3::new(1.0, 1.0, 1.0), 0.5, 1.0, "", 10.0, 0.5, 0.1, false)
+    }
+}
+
+// volumes that change how players move while they're inside them (see worldmachine::volumes)
+// like triggers, position is an offset from the entity and size is how far the box goes from there
+
+pub struct Ladder {}
+
+impl Ladder {
+    pub fn new(position: Vec3, size: Vec3) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Vec3(position)));
+        parameters.insert("size".to_string(), Parameter::new("size", ParameterValue::Vec3(size)));
+
+        Component {
+            name: "Ladder".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_LADDER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 4.0, 1.0))
+    }
+}
+
+pub struct Water {}
+
+impl Water {
+    // a buoyancy of 1 neither sinks nor floats, more than that floats up to the surface
+    pub fn new(position: Vec3, size: Vec3, buoyancy: f64) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("position".to_string(), Parameter::new("position", ParameterValue::Vec3(position)));
+        parameters.insert("size".to_string(), Parameter::new("size", ParameterValue::Vec3(size)));
+        parameters.insert("buoyancy".to_string(), Parameter::new("buoyancy", ParameterValue::Float(buoyancy)));
+
+        Component {
+            name: "Water".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_WATER.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 4.0, 4.0), 1.2)
+    }
+}
+
+pub struct Platform {}
+
+impl Platform {
+    // the server moves platforms between their waypoints and writes where they are into their Transform (see worldmachine::platforms)
+    // waypoints are "x y z" separated by semicolons like an npc's, it waits at each one for `wait` seconds and goes back to the first after the last
+    // it needs a RigidBody for anything to stand on it, which is made kinematic
+    pub fn new(waypoints: &str, speed: f64, wait: f64) -> Component {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("waypoints".to_string(), Parameter::new("waypoints", ParameterValue::String(waypoints.to_string())));
+        parameters.insert("speed".to_string(), Parameter::new("speed", ParameterValue::Float(speed)));
+        parameters.insert("wait".to_string(), Parameter::new("wait", ParameterValue::Float(wait)));
+        parameters.insert("velocity".to_string(), Parameter::new("velocity", ParameterValue::Vec3(Vec3::new(0.0, 0.0, 0.0))));
+
+        Component {
+            name: "Platform".to_string(),
+            parameters,
+            component_type: COMPONENT_TYPE_PLATFORM.clone(),
+        }
+    }
+    pub fn default() -> Component {
+        Self::new("", 4.0, 2.0)
+    }
+}
@@ -6,6 +6,7 @@ use gfx_maths::{Quaternion, Vec2, Vec3};
 use serde::{Deserialize, Serialize, Serializer};
 use serde::ser::SerializeStruct;
 use crate::worldmachine::EntityId;
+use crate::worldmachine::entity_store::{EntityHandle, EntityStore};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Parameter {
@@ -53,6 +54,7 @@ pub struct Entity {
     pub name: String,
     pub uid: EntityId,
     pub components: Vec<Component>,
+    // only used for nested entities in worlddefs, once loaded the hierarchy lives in `parent`
     pub children: Vec<Entity>,
     pub parent: Option<EntityId>,
 }
@@ -64,6 +66,44 @@ pub struct System {
     pub affected_entities: Vec<u64>,
 }
 
+/// where a system is allowed to run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemSide {
+    Server,
+    Client,
+    Both,
+}
+
+/// what a system gets to see while it runs
+/// systems only read entities directly, changes go through set_parameter so they're replicated like any other
+pub struct SystemContext<'a> {
+    pub entities: &'a EntityStore,
+    pub affected_entities: &'a [EntityId],
+    pub reads: &'a [ComponentType],
+    pub writes: &'a [ComponentType],
+    pub delta: f32,
+    pub is_server: bool,
+    pub changes: Vec<(EntityId, ComponentType, String, ParameterValue)>, // applied in order once the system has run
+}
+
+/// the code half of a system, registered at startup and looked up by name when a map references it
+#[derive(Clone)]
+pub struct SystemDefinition {
+    pub name: String,
+    pub reads: Vec<ComponentType>,
+    pub writes: Vec<ComponentType>,
+    pub side: SystemSide,
+    pub run: fn(&mut SystemContext),
+}
+
+/// how a changed parameter gets to clients
+/// reliable changes go over the steady connection, unreliable ones over the fast one and may be dropped or superseded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Replication {
+    Reliable,
+    Unreliable,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EntityDef {
     pub name: String,
@@ -156,6 +196,67 @@ impl ComponentType {
     pub fn get(name: String) -> Option<Self> {
         COMPONENT_TYPES.lock().unwrap().get(&*name).cloned()
     }
+
+    pub fn set_replication(&self, parameter_name: &str, replication: Replication) {
+        PARAMETER_REPLICATION.lock().unwrap().insert((self.name.clone(), parameter_name.to_string()), replication);
+    }
+
+    /// parameters are replicated reliably unless registered otherwise
+    pub fn replication(&self, parameter_name: &str) -> Replication {
+        PARAMETER_REPLICATION.lock().unwrap().get(&(self.name.clone(), parameter_name.to_string())).cloned().unwrap_or(Replication::Reliable)
+    }
+}
+
+impl<'a> SystemContext<'a> {
+    /// changes a parameter once the system is done, through WorldMachine::set_component_parameter
+    /// only components the system says it writes can be changed
+    pub fn set_parameter(&mut self, entity_id: EntityId, component_type: ComponentType, parameter_name: &str, value: ParameterValue) {
+        if !self.writes.contains(&component_type) {
+            warn!("a system tried to write {}.{} on entity {} without declaring it writes {}", component_type.name, parameter_name, entity_id, component_type.name);
+            return;
+        }
+        self.changes.push((entity_id, component_type, parameter_name.to_string(), value));
+    }
+
+    /// entities that have every component this system reads and writes
+    /// if the map gave the system an affected_entities list, only those entities are considered
+    pub fn matching_entities(&self) -> Vec<EntityHandle> {
+        let mut required = self.reads.to_vec();
+        required.extend(self.writes.iter().cloned());
+        let first = match required.first() {
+            Some(first) => first.clone(),
+            None => return Vec::new(),
+        };
+        self.entities.with_component(first).filter(|(_, entity)| {
+            if !self.affected_entities.is_empty() && !self.affected_entities.contains(&entity.uid) {
+                return false;
+            }
+            required.iter().all(|component_type| entity.has_component(component_type.clone()))
+        }).map(|(handle, _)| handle).collect()
+    }
+}
+
+impl SystemDefinition {
+    pub fn register(definition: SystemDefinition) {
+        let mut system_types = SYSTEM_TYPES.lock().unwrap();
+        if !system_types.contains_key(&definition.name) {
+            System::create(&mut system_types, definition.name.clone());
+        }
+        drop(system_types);
+        SYSTEM_DEFINITIONS.lock().unwrap().insert(definition.name.clone(), definition);
+    }
+
+    pub fn get(name: &str) -> Option<Self> {
+        SYSTEM_DEFINITIONS.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn runs_on(&self, is_server: bool) -> bool {
+        match self.side {
+            SystemSide::Server => is_server,
+            SystemSide::Client => !is_server,
+            SystemSide::Both => true,
+        }
+    }
 }
 
 impl System {
@@ -221,5 +322,8 @@ lazy_static! {
         let mut m = HashMap::new();
         Mutex::new(m)
     };
+    pub static ref SYSTEM_DEFINITIONS: Mutex<HashMap<String, SystemDefinition>> = Mutex::new(HashMap::new());
+    // (component name, parameter name) -> how changes to it are sent
+    pub static ref PARAMETER_REPLICATION: Mutex<HashMap<(String, String), Replication>> = Mutex::new(HashMap::new());
     pub static ref ENTITY_ID_MANAGER: Mutex<EntityIDManager> = Mutex::new(EntityIDManager::default());
 }
\ No newline at end of file
@@ -72,6 +72,25 @@ impl Entity {
     }
 }
 
+/// reads an entity definition from <game_data_path>/entities/<name>.edef
+pub fn load_entity_def(game_data_path: &str, name: &str) -> Option<EntityDef> {
+    let path = format!("{}/entities/{}.edef", game_data_path, name);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("load_entity_def: failed to open {}: {:?}", path, e);
+            return None;
+        }
+    };
+    match serde_yaml::from_reader(file) {
+        Ok(entity_def) => Some(entity_def),
+        Err(e) => {
+            warn!("load_entity_def: failed to parse {}: {:?}", path, e);
+            None
+        }
+    }
+}
+
 pub fn new_ht2_entity() -> Entity {
     let mut entity = Entity::new("ht2");
     entity.add_component(Transform::default());
@@ -0,0 +1,362 @@
+use std::fmt;
+use halfbrown::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeSeq;
+use crate::worldmachine::ecs::{ComponentType, Entity};
+use crate::worldmachine::EntityId;
+
+/// stable reference to an entity slot. a handle goes stale (and lookups return None) once the
+/// entity it points to is removed, even if the slot gets reused by another entity later
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityHandle {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Slot {
+    generation: u32,
+    entity: Option<Entity>,
+}
+
+/// generational storage for entities, indexed by both handle and EntityId
+/// serialises as a plain list of entities so that worlddefs keep the same format
+#[derive(Clone, Debug, Default)]
+pub struct EntityStore {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    ids: HashMap<EntityId, EntityHandle>,
+    by_component: HashMap<u64, Vec<EntityHandle>>,
+    children: HashMap<EntityId, Vec<EntityId>>, // parent -> children, so walking down the hierarchy doesn't have to look at every entity
+    len: usize,
+}
+
+impl EntityStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.ids.clear();
+        self.by_component.clear();
+        self.children.clear();
+        self.len = 0;
+    }
+
+    /// inserts an entity, replacing any existing entity with the same id (the old handle stays valid in that case)
+    pub fn insert(&mut self, entity: Entity) -> EntityHandle {
+        let uid = entity.uid;
+        let parent = entity.parent;
+        if let Some(handle) = self.ids.get(&uid).cloned() {
+            self.unindex_components(handle);
+            let old_parent = self.slots[handle.index as usize].entity.as_ref().and_then(|old| old.parent);
+            self.unlink_child(uid, old_parent);
+            self.slots[handle.index as usize].entity = Some(entity);
+            self.index_components(handle);
+            self.link_child(uid, parent);
+            return handle;
+        }
+
+        let handle = if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.entity = Some(entity);
+            EntityHandle {
+                index,
+                generation: slot.generation,
+            }
+        } else {
+            self.slots.push(Slot {
+                generation: 0,
+                entity: Some(entity),
+            });
+            EntityHandle {
+                index: (self.slots.len() - 1) as u32,
+                generation: 0,
+            }
+        };
+        self.ids.insert(uid, handle);
+        self.index_components(handle);
+        self.link_child(uid, parent);
+        self.len += 1;
+        handle
+    }
+
+    pub fn remove(&mut self, handle: EntityHandle) -> Option<Entity> {
+        self.get(handle)?;
+        self.unindex_components(handle);
+        let slot = &mut self.slots[handle.index as usize];
+        let entity = slot.entity.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        self.ids.remove(&entity.uid);
+        self.unlink_child(entity.uid, entity.parent);
+        self.len -= 1;
+        Some(entity)
+    }
+
+    pub fn remove_by_id(&mut self, entity_id: EntityId) -> Option<Entity> {
+        let handle = self.handle_of(entity_id)?;
+        self.remove(handle)
+    }
+
+    pub fn handle_of(&self, entity_id: EntityId) -> Option<EntityHandle> {
+        self.ids.get(&entity_id).cloned()
+    }
+
+    pub fn contains_id(&self, entity_id: EntityId) -> bool {
+        self.ids.contains_key(&entity_id)
+    }
+
+    pub fn get(&self, handle: EntityHandle) -> Option<&Entity> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entity.as_ref()
+    }
+
+    /// note: if you add or remove components through this, call `reindex` afterwards
+    pub fn get_mut(&mut self, handle: EntityHandle) -> Option<&mut Entity> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entity.as_mut()
+    }
+
+    pub fn get_by_id(&self, entity_id: EntityId) -> Option<&Entity> {
+        self.get(self.handle_of(entity_id)?)
+    }
+
+    pub fn get_by_id_mut(&mut self, entity_id: EntityId) -> Option<&mut Entity> {
+        let handle = self.handle_of(entity_id)?;
+        self.get_mut(handle)
+    }
+
+    /// moves an entity under a new parent (or to the top level), use this rather than changing `parent` in place
+    /// so that children_of stays right. returns false if the entity doesn't exist
+    pub fn set_parent(&mut self, entity_id: EntityId, parent: Option<EntityId>) -> bool {
+        let old_parent = match self.get_by_id_mut(entity_id) {
+            Some(entity) => std::mem::replace(&mut entity.parent, parent),
+            None => return false,
+        };
+        self.unlink_child(entity_id, old_parent);
+        self.link_child(entity_id, parent);
+        true
+    }
+
+    /// the entities whose parent is `entity_id`, in the order they were put there
+    pub fn children_of(&self, entity_id: EntityId) -> Vec<EntityId> {
+        self.children.get(&entity_id).cloned().unwrap_or_default()
+    }
+
+    /// rebuilds the component index for an entity whose component list was changed in place
+    pub fn reindex(&mut self, handle: EntityHandle) {
+        if self.get(handle).is_none() {
+            return;
+        }
+        self.unindex_components(handle);
+        self.index_components(handle);
+    }
+
+    /// iterates over all entities in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (EntityHandle, &Entity)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.entity.as_ref().map(|entity| (EntityHandle {
+                index: index as u32,
+                generation: slot.generation,
+            }, entity))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityHandle, &mut Entity)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation;
+            slot.entity.as_mut().map(|entity| (EntityHandle {
+                index: index as u32,
+                generation,
+            }, entity))
+        })
+    }
+
+    /// all entities that have a component of the given type, in the order they gained it
+    pub fn with_component(&self, component_type: ComponentType) -> impl Iterator<Item = (EntityHandle, &Entity)> {
+        self.handles_with_component(component_type).into_iter().filter_map(move |handle| {
+            self.get(handle).map(|entity| (handle, entity))
+        })
+    }
+
+    pub fn handles_with_component(&self, component_type: ComponentType) -> Vec<EntityHandle> {
+        self.by_component.get(&component_type.id).cloned().unwrap_or_default()
+    }
+
+    fn index_components(&mut self, handle: EntityHandle) {
+        let types = match self.get(handle) {
+            Some(entity) => entity.components.iter().map(|c| c.component_type.id).collect::<Vec<u64>>(),
+            None => return,
+        };
+        for type_id in types {
+            let handles = self.by_component.entry(type_id).or_insert_with(Vec::new);
+            if !handles.contains(&handle) {
+                handles.push(handle);
+            }
+        }
+    }
+
+    fn unindex_components(&mut self, handle: EntityHandle) {
+        for handles in self.by_component.values_mut() {
+            handles.retain(|h| *h != handle);
+        }
+    }
+
+    fn link_child(&mut self, entity_id: EntityId, parent: Option<EntityId>) {
+        if let Some(parent) = parent {
+            let children = self.children.entry(parent).or_insert_with(Vec::new);
+            if !children.contains(&entity_id) {
+                children.push(entity_id);
+            }
+        }
+    }
+
+    fn unlink_child(&mut self, entity_id: EntityId, parent: Option<EntityId>) {
+        if let Some(parent) = parent {
+            if let Some(children) = self.children.get_mut(&parent) {
+                children.retain(|child| *child != entity_id);
+                if children.is_empty() {
+                    self.children.remove(&parent);
+                }
+            }
+        }
+    }
+}
+
+impl Serialize for EntityStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for (_, entity) in self.iter() {
+            seq.serialize_element(entity)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for EntityStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entities = Vec::<Entity>::deserialize(deserializer)?;
+        let mut store = EntityStore::new();
+        for entity in entities {
+            store.insert(entity);
+        }
+        Ok(store)
+    }
+}
+
+impl fmt::Display for EntityHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldmachine::components::{COMPONENT_TYPE_LIGHT, COMPONENT_TYPE_TRANSFORM, Light, Transform};
+
+    fn entity(uid: EntityId) -> Entity {
+        Entity {
+            name: format!("entity {}", uid),
+            uid,
+            components: Vec::new(),
+            children: Vec::new(),
+            parent: None,
+        }
+    }
+
+    fn child(uid: EntityId, parent: EntityId) -> Entity {
+        let mut entity = entity(uid);
+        entity.parent = Some(parent);
+        entity
+    }
+
+    #[test]
+    fn handles_go_stale_when_their_slot_is_reused() {
+        let mut store = EntityStore::new();
+        let first = store.insert(entity(1));
+        assert_eq!(store.remove(first).map(|e| e.uid), Some(1));
+        let second = store.insert(entity(2));
+        // same slot, different generation
+        assert_eq!(first.index, second.index);
+        assert!(store.get(first).is_none());
+        assert!(store.remove(first).is_none());
+        assert_eq!(store.get(second).map(|e| e.uid), Some(2));
+        assert!(store.get_by_id(1).is_none());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn inserting_the_same_id_replaces_the_entity() {
+        let mut store = EntityStore::new();
+        let handle = store.insert(entity(1));
+        let mut replacement = entity(1);
+        replacement.name = "replacement".to_string();
+        replacement.add_component(Transform::default());
+        assert_eq!(store.insert(replacement), handle);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(handle).map(|e| e.name.as_str()), Some("replacement"));
+        assert_eq!(store.with_component(COMPONENT_TYPE_TRANSFORM.clone()).count(), 1);
+    }
+
+    #[test]
+    fn with_component_follows_reindex() {
+        let mut store = EntityStore::new();
+        let mut lit = entity(1);
+        lit.add_component(Transform::default());
+        let lit = store.insert(lit);
+        let dark = store.insert(entity(2));
+
+        store.get_mut(dark).unwrap().add_component(Light::default());
+        // changed in place, the index doesn't know yet
+        assert_eq!(store.with_component(COMPONENT_TYPE_LIGHT.clone()).count(), 0);
+        store.reindex(dark);
+        assert_eq!(store.with_component(COMPONENT_TYPE_LIGHT.clone()).map(|(handle, _)| handle).collect::<Vec<_>>(), vec![dark]);
+
+        store.get_mut(lit).unwrap().remove_component(COMPONENT_TYPE_TRANSFORM.clone());
+        store.reindex(lit);
+        assert_eq!(store.with_component(COMPONENT_TYPE_TRANSFORM.clone()).count(), 0);
+    }
+
+    #[test]
+    fn children_follow_inserts_reparenting_and_removal() {
+        let mut store = EntityStore::new();
+        store.insert(entity(1));
+        store.insert(entity(2));
+        store.insert(child(3, 1));
+        store.insert(child(4, 1));
+        assert_eq!(store.children_of(1), vec![3, 4]);
+
+        assert!(store.set_parent(3, Some(2)));
+        assert_eq!(store.children_of(1), vec![4]);
+        assert_eq!(store.children_of(2), vec![3]);
+        assert!(!store.set_parent(5, Some(2)));
+
+        // replacing an entity moves it to wherever the replacement says
+        store.insert(child(4, 2));
+        assert!(store.children_of(1).is_empty());
+        assert_eq!(store.children_of(2), vec![3, 4]);
+
+        store.remove_by_id(3);
+        assert_eq!(store.children_of(2), vec![4]);
+        store.clear();
+        assert!(store.children_of(2).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use gfx_maths::*;
+use crate::worldmachine::components::{COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_TRANSFORM};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::entity_store::EntityStore;
+use crate::worldmachine::EntityId;
+
+// deep enough for any sane scene, and stops us spinning forever if a cycle sneaks in over the network
+const MAX_HIERARCHY_DEPTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldTransform {
+    pub position: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Default for WorldTransform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl WorldTransform {
+    /// the transform of `local` once it is placed under `self`
+    /// matches calculate_model_matrix, i.e. parent * translate * rotate * scale
+    pub fn combine(&self, local: &WorldTransform) -> WorldTransform {
+        WorldTransform {
+            position: self.transform_point(local.position),
+            rotation: self.rotation * local.rotation,
+            scale: self.scale * local.scale,
+        }
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.position + rotate_vector(self.rotation, self.scale * point)
+    }
+}
+
+// standard q * v * q^-1, same convention as Mat4::rotate
+pub fn rotate_vector(q: Quaternion, v: Vec3) -> Vec3 {
+    let u = Vec3::new(q.x, q.y, q.z);
+    let s = q.w;
+    let cross = Vec3::new(u.y * v.z - u.z * v.y, u.z * v.x - u.x * v.z, u.x * v.y - u.y * v.x);
+    u * (2.0 * u.dot(v)) + v * (s * s - u.dot(u)) + cross * (2.0 * s)
+}
+
+/// the entity's own transform relative to its parent
+/// players keep their transform in the player component, so that is used if there's no transform
+pub fn local_transform(entity: &Entity) -> WorldTransform {
+    let component = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone())
+        .or_else(|| entity.get_component(COMPONENT_TYPE_PLAYER.clone()));
+    let mut transform = WorldTransform::default();
+    if let Some(component) = component {
+        if let ParameterValue::Vec3(v) = component.get_parameter("position").value {
+            transform.position = v;
+        }
+        if let ParameterValue::Quaternion(v) = component.get_parameter("rotation").value {
+            transform.rotation = v;
+        }
+        if let ParameterValue::Vec3(v) = component.get_parameter("scale").value {
+            transform.scale = v;
+        }
+    }
+    transform
+}
+
+impl EntityStore {
+    /// composes the transforms of an entity and all of its parents
+    pub fn world_transform(&self, entity_id: EntityId) -> Option<WorldTransform> {
+        let mut chain = Vec::new();
+        let mut current = Some(entity_id);
+        while let Some(id) = current {
+            if chain.len() >= MAX_HIERARCHY_DEPTH {
+                warn!("world_transform: hierarchy of entity {} is too deep (or has a cycle)", entity_id);
+                break;
+            }
+            let entity = match self.get_by_id(id) {
+                Some(entity) => entity,
+                None => {
+                    if id == entity_id {
+                        return None;
+                    }
+                    // parent doesn't exist (yet), treat what we have as the root
+                    break;
+                }
+            };
+            chain.push(local_transform(entity));
+            current = entity.parent;
+        }
+        let mut transform = WorldTransform::default();
+        for local in chain.iter().rev() {
+            transform = transform.combine(local);
+        }
+        Some(transform)
+    }
+
+    /// the entity and everything below it, parents before children
+    pub fn descendants_of(&self, entity_id: EntityId) -> Vec<EntityId> {
+        let mut result = vec![entity_id];
+        let mut i = 0;
+        while i < result.len() {
+            for child in self.children_of(result[i]) {
+                if !result.contains(&child) {
+                    result.push(child);
+                }
+            }
+            i += 1;
+        }
+        result
+    }
+
+    /// true if putting `entity_id` under `parent` would make a loop
+    /// a hierarchy above `parent` that's too deep to walk counts, since there's no telling what's up there
+    pub fn would_create_cycle(&self, entity_id: EntityId, parent: EntityId) -> bool {
+        match self.is_ancestor(entity_id, parent) {
+            Some(is_ancestor) => parent == entity_id || is_ancestor,
+            None => {
+                warn!("would_create_cycle: hierarchy above entity {} is too deep (or has a cycle)", parent);
+                true
+            }
+        }
+    }
+
+    /// whether `ancestor` is somewhere above `entity_id` in the hierarchy, None if that couldn't be worked out
+    /// because there are more than MAX_HIERARCHY_DEPTH entities above it (or a cycle)
+    pub fn is_ancestor(&self, ancestor: EntityId, entity_id: EntityId) -> Option<bool> {
+        let mut current = self.get_by_id(entity_id).and_then(|e| e.parent);
+        let mut depth = 0;
+        while let Some(id) = current {
+            if id == ancestor {
+                return Some(true);
+            }
+            depth += 1;
+            if depth >= MAX_HIERARCHY_DEPTH {
+                return None;
+            }
+            current = self.get_by_id(id).and_then(|e| e.parent);
+        }
+        Some(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::testing;
+    use crate::worldmachine::WorldMachine;
+    use crate::worldmachine::components::{CapsuleCollider, Transform};
+
+    fn entity(uid: EntityId, parent: Option<EntityId>, position: Vec3, rotation: Quaternion, scale: Vec3) -> Entity {
+        let mut entity = Entity {
+            name: format!("entity {}", uid),
+            uid,
+            components: Vec::new(),
+            children: Vec::new(),
+            parent,
+        };
+        entity.add_component(Transform::new(position, rotation, scale));
+        entity
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).magnitude() < 0.0001, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn world_transform_goes_through_every_parent() {
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let quarter_turn = Quaternion::new(0.0, std::f32::consts::FRAC_1_SQRT_2, 0.0, std::f32::consts::FRAC_1_SQRT_2);
+        let mut store = EntityStore::new();
+        store.insert(entity(1, None, Vec3::new(10.0, 0.0, 0.0), Quaternion::identity(), Vec3::new(2.0, 2.0, 2.0)));
+        store.insert(entity(2, Some(1), Vec3::new(1.0, 0.0, 0.0), quarter_turn, one));
+        store.insert(entity(3, Some(2), Vec3::new(1.0, 0.0, 0.0), Quaternion::identity(), one));
+
+        let parent = store.world_transform(2).unwrap();
+        assert_near(parent.position, Vec3::new(12.0, 0.0, 0.0));
+        // scaled by the grandparent, then turned by the parent
+        let child = store.world_transform(3).unwrap();
+        assert_near(child.position, Vec3::new(12.0, 0.0, -2.0));
+        assert_near(child.scale, Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(child.rotation, quarter_turn);
+
+        assert_eq!(store.descendants_of(1), vec![1, 2, 3]);
+        assert!(store.world_transform(4).is_none());
+    }
+
+    #[test]
+    fn cycles_are_rejected_without_making_up_ancestors() {
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        let mut store = EntityStore::new();
+        store.insert(entity(1, None, Vec3::zero(), Quaternion::identity(), one));
+        store.insert(entity(2, Some(1), Vec3::zero(), Quaternion::identity(), one));
+        assert_eq!(store.is_ancestor(1, 2), Some(true));
+        assert_eq!(store.is_ancestor(2, 1), Some(false));
+
+        // a loop that made it in anyway, e.g. from a bad worlddef
+        store.insert(entity(3, Some(4), Vec3::zero(), Quaternion::identity(), one));
+        store.insert(entity(4, Some(3), Vec3::zero(), Quaternion::identity(), one));
+        assert_eq!(store.is_ancestor(1, 3), None);
+        assert_eq!(store.is_ancestor(4, 3), Some(true));
+        assert!(store.world_transform(3).is_some());
+        // nothing can be put under something whose ancestry can't be followed to the top
+        assert!(store.would_create_cycle(1, 3));
+        assert!(store.would_create_cycle(1, 2));
+        assert!(store.would_create_cycle(2, 2));
+        assert!(!store.would_create_cycle(2, 1));
+    }
+
+    #[test]
+    fn colliders_follow_a_rotated_parent() {
+        let mut worldmachine = WorldMachine::default();
+        worldmachine.game_data_path = "base".to_string();
+        let physics = testing::scene();
+        *worldmachine.physics.lock().unwrap() = Some(physics.clone());
+
+        // a quarter turn about z, so the child's x is the world's y and its capsule lies along the world's x
+        let quarter_turn = Quaternion::new(0.0, 0.0, std::f32::consts::FRAC_1_SQRT_2, std::f32::consts::FRAC_1_SQRT_2);
+        let mut parent = Entity::new("parent");
+        parent.add_component(Transform::new(Vec3::zero(), quarter_turn, Vec3::new(1.0, 1.0, 1.0)));
+        let parent = worldmachine.spawn(parent, None).unwrap();
+        let mut child = Entity::new("child");
+        child.add_component(CapsuleCollider::new(Vec3::new(2.0, 0.0, 0.0), 0.5, 4.0));
+        worldmachine.spawn(child, Some(parent)).unwrap();
+        physics.tick(testing::STEP);
+
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        for x in [0.0, 2.0] {
+            let hit = physics.raycast(Vec3::new(x, 10.0, 0.0), down, 20.0, None).expect("missed the capsule");
+            assert!((hit.distance - 7.5).abs() < 0.05, "hit the capsule at {} from x = {}", hit.distance, x);
+        }
+        assert!(physics.raycast(Vec3::new(2.0, 10.0, 0.0) + Vec3::new(0.0, 0.0, 1.0), down, 20.0, None).is_none());
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex as StdMutex;
+use halfbrown::HashMap;
+use crate::worldmachine::components::COMPONENT_TYPE_JUKEBOX;
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::{EntityId, WorldMachine};
+
+lazy_static! {
+    // track name -> length in seconds, None if it couldn't be worked out
+    static ref TRACK_LENGTHS: StdMutex<HashMap<String, Option<f64>>> = StdMutex::new(HashMap::new());
+}
+
+/// something the server can be told to do with a jukebox
+#[derive(Clone, Debug, PartialEq)]
+pub enum JukeboxCommand {
+    Play,
+    Stop, // remembers where it got to, so play carries on from there
+    Toggle,
+    Seek(f64), // seconds into the track
+    Volume(f64),
+    Next, // next track in the playlist
+    Track(String), // play this from the start
+}
+
+/// a Jukebox component's parameters
+/// while playing, the track was at 0 seconds at server time `started_at`, so everyone can work out where it should be now
+/// while stopped, `position` is where it'll start from
+#[derive(Clone, Debug, PartialEq)]
+pub struct JukeboxState {
+    pub track: String,
+    pub playlist: Vec<String>, // empty means `track` loops forever
+    pub playing: bool,
+    pub volume: f64,
+    pub started_at: f64,
+    pub position: f64,
+    pub uuid: String,
+}
+
+impl JukeboxState {
+    pub fn from_component(component: &Component) -> Option<Self> {
+        let string = |name: &str| match &component.get_parameter(name).value {
+            ParameterValue::String(v) => Some(v.clone()),
+            _ => None,
+        };
+        let float = |name: &str| match component.get_parameter(name).value {
+            ParameterValue::Float(v) => Some(v),
+            _ => None,
+        };
+        let playing = match component.get_parameter("playing").value {
+            ParameterValue::Bool(v) => v,
+            _ => return None,
+        };
+        Some(Self {
+            track: string("track")?,
+            playlist: string("playlist").map(|playlist| parse_playlist(&playlist)).unwrap_or_default(),
+            playing,
+            volume: float("volume")?,
+            started_at: float("started_at").unwrap_or(0.0),
+            position: float("position").unwrap_or(0.0),
+            uuid: string("uuid")?,
+        })
+    }
+
+    /// how far into the track it is at server time `now`
+    pub fn position_at(&self, now: f64, length: Option<f64>) -> f64 {
+        if !self.playing {
+            return self.position;
+        }
+        let position = (now - self.started_at).max(0.0);
+        match length {
+            // a lone track loops, in a playlist the server moves on to the next one when it ends
+            Some(length) if length > 0.0 && self.playlist.is_empty() => position % length,
+            _ => position,
+        }
+    }
+
+    fn next_track(&self) -> Option<String> {
+        if self.playlist.is_empty() {
+            return None;
+        }
+        let next = match self.playlist.iter().position(|track| *track == self.track) {
+            Some(current) => (current + 1) % self.playlist.len(),
+            None => 0,
+        };
+        Some(self.playlist[next].clone())
+    }
+}
+
+// "one.wav; two.wav"
+pub fn parse_playlist(playlist: &str) -> Vec<String> {
+    playlist.split(';').map(|track| track.trim()).filter(|track| !track.is_empty()).map(|track| track.to_string()).collect()
+}
+
+/// how long a track in base/snd is in seconds, only wavs are understood
+/// the server doesn't have an audio backend, so it reads the header itself
+pub fn track_length(game_data_path: &str, track: &str) -> Option<f64> {
+    if let Some(length) = TRACK_LENGTHS.lock().unwrap().get(track) {
+        return *length;
+    }
+    let path = format!("{}/snd/{}", game_data_path, track);
+    let length = wav_length(&path);
+    if length.is_none() {
+        warn!("couldn't work out how long {} is, jukeboxes can't move on from it", path);
+    }
+    TRACK_LENGTHS.lock().unwrap().insert(track.to_string(), length);
+    length
+}
+
+fn wav_length(path: &str) -> Option<f64> {
+    let mut file = File::open(path).ok()?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut byte_rate = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk).ok()?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut format = vec![0u8; size as usize];
+                file.read_exact(&mut format).ok()?;
+                if format.len() < 12 {
+                    return None;
+                }
+                byte_rate = Some(u32::from_le_bytes([format[8], format[9], format[10], format[11]]));
+            }
+            b"data" => {
+                let byte_rate = byte_rate.filter(|rate| *rate > 0)?;
+                return Some(size as f64 / byte_rate as f64);
+            }
+            _ => {
+                file.seek(SeekFrom::Current(size as i64)).ok()?;
+            }
+        }
+        // chunks are padded to an even size
+        if size % 2 == 1 {
+            file.seek(SeekFrom::Current(1)).ok()?;
+        }
+    }
+}
+
+impl WorldMachine {
+    /// seconds since the server loaded, clientside this is estimated from the server's replies to pings
+    pub fn server_time(&self) -> f64 {
+        self.clock_start.elapsed().as_secs_f64() + self.server_clock_offset.unwrap_or(0.0)
+    }
+
+    /// the server says it's `server_time`, and the question took `round_trip` seconds to answer
+    pub fn sync_server_time(&mut self, server_time: f64, round_trip: f64) {
+        let offset = server_time + round_trip / 2.0 - self.clock_start.elapsed().as_secs_f64();
+        if self.server_clock_offset.is_none() {
+            debug!("server clock is {:.3}s ahead of ours", offset);
+        }
+        self.server_clock_offset = Some(offset);
+    }
+
+    pub fn jukebox_state(&self, entity_id: EntityId) -> Option<JukeboxState> {
+        JukeboxState::from_component(self.get_entity(entity_id)?.get_component(COMPONENT_TYPE_JUKEBOX.clone())?)
+    }
+
+    /// changes a jukebox, serverside only. clients hear about it through the usual parameter updates
+    pub fn jukebox_command(&mut self, entity_id: EntityId, command: JukeboxCommand) -> Option<()> {
+        if !self.is_server {
+            warn!("jukebox_command: called on client");
+            return None;
+        }
+        let old = match self.jukebox_state(entity_id) {
+            Some(state) => state,
+            None => {
+                warn!("jukebox_command: entity {} has no jukebox", entity_id);
+                return None;
+            }
+        };
+        let now = self.server_time();
+        let length = track_length(&self.game_data_path, &old.track);
+        let mut new = old.clone();
+        let command = match command {
+            JukeboxCommand::Toggle if old.playing => JukeboxCommand::Stop,
+            JukeboxCommand::Toggle => JukeboxCommand::Play,
+            command => command,
+        };
+        match command {
+            JukeboxCommand::Play => {
+                if !old.playing {
+                    new.playing = true;
+                    new.started_at = now - old.position;
+                }
+            }
+            JukeboxCommand::Stop => {
+                if old.playing {
+                    new.playing = false;
+                    new.position = old.position_at(now, length);
+                }
+            }
+            JukeboxCommand::Toggle => unreachable!(),
+            JukeboxCommand::Seek(seconds) => {
+                let seconds = match length {
+                    Some(length) => seconds.clamp(0.0, length),
+                    None => seconds.max(0.0),
+                };
+                new.started_at = now - seconds;
+                new.position = seconds;
+            }
+            JukeboxCommand::Volume(volume) => {
+                new.volume = volume.max(0.0);
+            }
+            JukeboxCommand::Next => {
+                new.track = old.next_track()?;
+                new.started_at = now;
+                new.position = 0.0;
+            }
+            JukeboxCommand::Track(track) => {
+                new.track = track;
+                new.started_at = now;
+                new.position = 0.0;
+            }
+        }
+        self.set_jukebox_state(entity_id, &old, &new);
+        Some(())
+    }
+
+    // only sends what changed
+    fn set_jukebox_state(&mut self, entity_id: EntityId, old: &JukeboxState, new: &JukeboxState) {
+        let jukebox = COMPONENT_TYPE_JUKEBOX.clone();
+        if old.track != new.track {
+            self.set_component_parameter(entity_id, jukebox.clone(), "track", ParameterValue::String(new.track.clone()));
+        }
+        if old.started_at != new.started_at {
+            self.set_component_parameter(entity_id, jukebox.clone(), "started_at", ParameterValue::Float(new.started_at));
+        }
+        if old.position != new.position {
+            self.set_component_parameter(entity_id, jukebox.clone(), "position", ParameterValue::Float(new.position));
+        }
+        if old.volume != new.volume {
+            self.set_component_parameter(entity_id, jukebox.clone(), "volume", ParameterValue::Float(new.volume));
+        }
+        if old.playing != new.playing {
+            self.set_component_parameter(entity_id, jukebox, "playing", ParameterValue::Bool(new.playing));
+        }
+    }
+
+    /// moves playing jukeboxes on to the next track in their playlist when the current one ends, serverside only
+    pub fn tick_jukeboxes(&mut self) {
+        if !self.is_server {
+            return;
+        }
+        let now = self.server_time();
+        let jukeboxes = self.world.entities.with_component(COMPONENT_TYPE_JUKEBOX.clone())
+            .map(|(_, entity)| entity.uid)
+            .collect::<Vec<EntityId>>();
+        for entity_id in jukeboxes {
+            let old = match self.jukebox_state(entity_id) {
+                Some(state) if state.playing && !state.playlist.is_empty() => state,
+                _ => continue,
+            };
+            let length = match track_length(&self.game_data_path, &old.track) {
+                Some(length) if now - old.started_at >= length => length,
+                _ => continue,
+            };
+            let mut new = old.clone();
+            new.track = old.next_track().unwrap();
+            // started when the last one ended rather than now, so it doesn't drift
+            new.started_at = old.started_at + length;
+            debug!("jukebox {} moving on to {}", entity_id, new.track);
+            self.set_jukebox_state(entity_id, &old, &new);
+        }
+    }
+}
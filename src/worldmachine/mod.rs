@@ -1,7 +1,7 @@
 use std::any::Any;
 use std::borrow::{Borrow, BorrowMut};
 use halfbrown::HashMap;
-use std::collections::{VecDeque};
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
@@ -19,29 +19,54 @@ use crate::animgraph::{AnimGraph, AnimGraphNode};
 use crate::audio::AudioBackend;
 use crate::common_anim::move_anim::{Features, MoveAnim};
 use crate::helpers::{add_quaternion, from_q64, multiply_quaternion, rotate_vector_by_quaternion, to_q64};
-use crate::physics::{Materials, PhysicsSystem};
+use crate::maps::scripting::{self, MapScript, ScriptCommand};
+use crate::maps::triggers::{self, Trigger, TriggerAction, TriggerContext, TriggerType, WhoTriggered};
+use crate::physics::{ColliderShape, Materials, PhysicsColliderStatic, PhysicsSystem, PhysicsTriggerShape};
 use crate::server::{ConnectionClientside, ConnectionUUID, FastPacket, FastPacketData, NameRejectionReason, SteadyPacket, SteadyPacketData};
+use crate::server::demo::{DemoHeader, DemoPacket};
 use crate::server::server_player::{ServerPlayer, ServerPlayerContainer};
 use crate::ui_defs::chat;
-use crate::worldmachine::components::{COMPONENT_TYPE_BOX_COLLIDER, COMPONENT_TYPE_JUKEBOX, COMPONENT_TYPE_LIGHT, COMPONENT_TYPE_MESH_RENDERER, COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_TERRAIN, COMPONENT_TYPE_TRANSFORM, COMPONENT_TYPE_TRIGGER, Light, MeshRenderer, Terrain, Transform};
+use crate::worldmachine::components::{COMPONENT_TYPE_BOX_COLLIDER, COMPONENT_TYPE_CAPSULE_COLLIDER, COMPONENT_TYPE_CONVEX_COLLIDER, COMPONENT_TYPE_JUKEBOX, COMPONENT_TYPE_LIGHT, COMPONENT_TYPE_MESH_COLLIDER, COMPONENT_TYPE_MESH_RENDERER, COMPONENT_TYPE_NPC, COMPONENT_TYPE_PLATFORM, COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_RIGID_BODY, COMPONENT_TYPE_SPHERE_COLLIDER, COMPONENT_TYPE_TERRAIN, COMPONENT_TYPE_TRANSFORM, COMPONENT_TYPE_TRIGGER, Light, MeshRenderer, Terrain, Transform};
 use crate::worldmachine::ecs::*;
+use crate::worldmachine::entity_store::{EntityHandle, EntityStore};
 use crate::worldmachine::MapLoadError::FolderNotFound;
+use crate::worldmachine::jukebox::{JukeboxCommand, JukeboxState};
+use crate::worldmachine::movement::MovementSettings;
+use crate::worldmachine::navmesh::{NavAgent, NavMesh};
+use crate::worldmachine::npcs::NpcController;
 use crate::worldmachine::player::{MovementInfo, Player, PlayerContainer};
+use crate::worldmachine::platforms::PlatformPath;
+use crate::worldmachine::rigid_bodies::RigidBody;
+use crate::worldmachine::volumes::MovementVolume;
 use crate::worldmachine::snowballs::Snowball;
+use crate::worldmachine::systems::ScheduledSystem;
 
 pub mod ecs;
 pub mod components;
 pub mod entities;
+pub mod entity_store;
+pub mod hierarchy;
 pub mod helpers;
+pub mod jukebox;
+pub mod movement;
+pub mod navmesh;
+pub mod npcs;
+pub mod platforms;
 pub mod player;
 pub mod playermodel;
+pub mod rigid_bodies;
 pub mod snowballs;
+pub mod systems;
+pub mod volumes;
 
 pub type EntityId = u64;
 
+const NAVMESH_VIEW_DISTANCE: f32 = 20.0; // the navmesh debug view only draws nodes this close to the camera
+const SETTLE_TICKS: u64 = 10; // physics steps are at least 10ms apart, so an unreliable parameter has to sit still for 100ms before it's resent reliably
+
 #[derive(Deserialize, Serialize)]
 pub struct World {
-    pub entities: Vec<Entity>,
+    pub entities: EntityStore,
     pub systems: Vec<System>,
     eid_manager: EntityId,
     current_map: String,
@@ -60,6 +85,13 @@ pub enum WorldUpdate {
     SetRotation(EntityId, Quaternion),
     SetScale(EntityId, Vec3),
     MovePlayerEntity(EntityId, Vec3, Quaternion, Quaternion),
+    SetParent(EntityId, Option<EntityId>),
+    SetParameter(EntityId, ComponentType, String, ParameterValue, u64), // the last one is the tick it changed on
+    SettleParameter(EntityId, ComponentType, String, ParameterValue, u64), // an unreliable parameter's final value, always sent reliably
+    ChatMessage(String, String), // who it's from, message
+    TeleportPlayer(EntityId, Vec3),
+    SetSystems(Vec<System>),
+    SetMovementSettings(MovementSettings),
     EntityNoLongerExists(EntityId),
 }
 
@@ -72,19 +104,25 @@ pub enum ClientUpdate {
     IMoved(Vec3, Option<Vec3>, Quaternion, Quaternion, Option<MovementInfo>), // position, displacement vector, rotation, head rotation, extra movement info
     IJumped,
     IThrewSnowball,
+    IUsed,
+}
+
+/// physx objects that belong to an entity, dropping them removes them from the scene
+#[derive(Clone, Default)]
+pub struct EntityPhysics {
+    pub colliders: Vec<PhysicsColliderStatic>,
+    pub triggers: Vec<PhysicsTriggerShape>,
 }
 
 #[derive(Clone, Debug)]
 pub enum MapLoadError {
     FolderNotFound(String),
+    InvalidWorldDef(String),
 }
 
 impl Clone for World {
     fn clone(&self) -> Self {
-        let mut entities = Vec::new();
-        for entity in &self.entities {
-            entities.push(entity.deref().clone());
-        }
+        let entities = self.entities.clone();
         let mut systems = Vec::new();
         for system in &self.systems {
             systems.push(system.deref().clone());
@@ -102,11 +140,33 @@ pub struct WorldMachine {
     pub world: World,
     pub snowballs: Vec<Snowball>,
     pub physics: Arc<mutex_timeouts::std::MutexWithTimeout<Option<PhysicsSystem>>>,
-    pub last_physics_update: std::time::Instant,
+    pub last_physics_update: tokio::time::Instant, // serverside, tokio's so that tests can control time
+    clock_start: tokio::time::Instant, // what server_time counts from
+    server_clock_offset: Option<f64>, // clientside, how far ahead of our clock the server's is. None until it first tells us
     pub game_data_path: String,
     pub counter: f32,
-    pub entities_wanting_to_load_things: Vec<usize>,
-    // index
+    pub entities_wanting_to_load_things: Vec<EntityHandle>,
+    system_schedule: Vec<ScheduledSystem>,
+    entity_physics: HashMap<EntityId, EntityPhysics>,
+    triggers: HashMap<EntityId, Trigger>,
+    npcs: HashMap<EntityId, NpcController>, // serverside only, clients just see where the server puts them
+    rigid_bodies: HashMap<EntityId, RigidBody>,
+    platforms: HashMap<EntityId, PlatformPath>, // serverside only, clients follow their Transform
+    movement_volumes: HashMap<EntityId, Vec<MovementVolume>>, // ladders and water
+    movement_settings: MovementSettings, // the current map's, clientside whatever the server last sent
+    map_script: Option<MapScript>, // serverside only
+    prefab_instances: HashMap<EntityId, String>, // entities spawned from an edef, so they can be updated when it changes
+    map_finalised: bool, // clientside, entities arriving after this need their physics set up as they come in
+    navmesh: Option<Arc<NavMesh>>, // built with the map serverside, clientside only when it's being looked at
+    navmesh_attempted: bool, // clientside, so a map without a navmesh doesn't try every frame
+    // serverside, parameters changed since the last server tick. keyed by (entity, component, parameter) so repeated changes only send the latest value
+    dirty_parameters: BTreeMap<(EntityId, String, String), (ComponentType, ParameterValue)>,
+    // serverside, unreliable parameters that have been sent and the tick they last changed on. once one stops changing it's sent again reliably,
+    // otherwise losing the last fast packet would leave clients with an old value for good
+    settling_parameters: BTreeMap<(EntityId, String, String), (ComponentType, ParameterValue, u64)>,
+    settled_parameters: Vec<WorldUpdate>, // serverside, settled by the last physics steps and waiting for the next server tick to send them
+    parameter_tick: u64, // serverside, counts physics steps so clients can tell which parameter update is newest
+    parameter_ticks: HashMap<(EntityId, String, String), u64>, // clientside, the tick of the newest update applied to each parameter
     lights_changed: bool,
     is_server: bool,
     server_connection: Option<crate::server::ConnectionClientside>,
@@ -118,12 +178,14 @@ pub struct WorldMachine {
 
     // only used clientside
     last_ping: Instant,
+    ping_sent: Option<Instant>, // the ping we're waiting to hear back about
+    jukeboxes_playing: HashMap<String, (String, f64)>, // jukebox uuid -> the track and started_at we started it with
 }
 
 impl Default for WorldMachine {
     fn default() -> Self {
         let world = World {
-            entities: Vec::new(),
+            entities: EntityStore::new(),
             systems: Vec::new(),
             eid_manager: 0,
             current_map: "".to_string(),
@@ -132,10 +194,30 @@ impl Default for WorldMachine {
             world,
             snowballs: vec![],
             physics: Arc::new(mutex_timeouts::std::MutexWithTimeout::new(None)),
-            last_physics_update: std::time::Instant::now(),
+            last_physics_update: tokio::time::Instant::now(),
+            clock_start: tokio::time::Instant::now(),
+            server_clock_offset: None,
             game_data_path: String::from(""),
             counter: 0.0,
             entities_wanting_to_load_things: Vec::new(),
+            system_schedule: Vec::new(),
+            entity_physics: HashMap::new(),
+            triggers: HashMap::new(),
+            npcs: HashMap::new(),
+            rigid_bodies: HashMap::new(),
+            platforms: HashMap::new(),
+            movement_volumes: HashMap::new(),
+            movement_settings: MovementSettings::default(),
+            map_script: None,
+            prefab_instances: HashMap::new(),
+            map_finalised: false,
+            navmesh: None,
+            navmesh_attempted: false,
+            dirty_parameters: BTreeMap::new(),
+            settling_parameters: BTreeMap::new(),
+            settled_parameters: Vec::new(),
+            parameter_tick: 0,
+            parameter_ticks: HashMap::new(),
             lights_changed: true,
             is_server: false,
             server_connection: None,
@@ -145,6 +227,8 @@ impl Default for WorldMachine {
             ignore_this_entity: None,
             players: None,
             last_ping: Instant::now(),
+            ping_sent: None,
+            jukeboxes_playing: HashMap::new(),
         }
     }
 }
@@ -152,7 +236,10 @@ impl Default for WorldMachine {
 impl WorldMachine {
     pub fn initialise(&mut self, physics: PhysicsSystem, is_server: bool) {
         let _ = *components::COMPONENTS_INITIALISED;
+        let _ = *systems::SYSTEMS_INITIALISED;
+        let _ = *crate::maps::TRIGGER_HANDLERS_REGISTERED;
         self.game_data_path = String::from("base");
+        physics.load_materials(&self.game_data_path);
         self.physics = Arc::new(mutex_timeouts::std::MutexWithTimeout::new(Some(physics)));
         self.is_server = is_server;
 
@@ -170,38 +257,92 @@ impl WorldMachine {
             let mut eid_manager = ENTITY_ID_MANAGER.lock().unwrap();
             eid_manager.borrow_mut().id = 0;
         }
+        self.entity_physics.clear();
+        self.triggers.clear();
+        self.npcs.clear();
+        self.rigid_bodies.clear();
+        self.platforms.clear();
+        self.movement_volumes.clear();
+        self.map_script = None;
+        self.prefab_instances.clear();
+        self.map_finalised = false;
+        self.navmesh = None;
+        self.navmesh_attempted = false;
+        self.dirty_parameters.clear();
+        self.settling_parameters.clear();
+        self.settled_parameters.clear();
+        self.parameter_ticks.clear();
         self.world.entities.clear();
         self.world.systems.clear();
+        self.system_schedule.clear();
         self.counter = 0.0;
         self.lights_changed = true;
     }
 
     pub fn load_map(&mut self, map_name: &str) -> Result<(), MapLoadError> {
         self.blank_slate(self.is_server);
+        let world_def = self.read_world_def(map_name)?;
+        self.load_world_def(map_name, world_def);
+        Ok(())
+    }
+
+    /// reloads the current map in place, connected players and their entities are kept
+    /// everything else is removed (on clients too) and replaced with what the worlddef has now
+    pub fn reload_map(&mut self) -> Result<(), MapLoadError> {
+        let map_name = self.world.current_map.clone();
+        // read it first, if it's broken (or half saved) we keep the old map
+        let world_def = self.read_world_def(&map_name)?;
+        let players = self.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone()).map(|(_, entity)| entity.clone()).collect::<Vec<Entity>>();
+        let removed = self.world.entities.iter()
+            .filter(|(_, entity)| !entity.has_component(COMPONENT_TYPE_PLAYER.clone()))
+            .map(|(_, entity)| entity.uid)
+            .collect::<Vec<EntityId>>();
+        // players keep their ids, so new entities have to carry on counting from here
+        let next_id = ENTITY_ID_MANAGER.lock().unwrap().id;
+        self.blank_slate(self.is_server);
+        ENTITY_ID_MANAGER.lock().unwrap().id = next_id;
+        self.prefab_instances.clear();
+        if self.is_server {
+            self.queue_updates(removed.into_iter().map(WorldUpdate::EntityNoLongerExists).collect());
+        }
+        self.load_world_def(&map_name, world_def);
+        for player in players {
+            self.world.entities.insert(player);
+        }
+        if self.is_server {
+            self.queue_updates(vec![WorldUpdate::SetSystems(self.world.systems.clone())]);
+        }
+        info!("reloaded map {}", map_name);
+        Ok(())
+    }
+
+    fn read_world_def(&self, map_name: &str) -> Result<WorldDef, MapLoadError> {
         let map_dir = format!("{}/maps/{}", self.game_data_path, map_name);
         if !std::path::Path::new(&map_dir).exists() {
             return Err(FolderNotFound(map_dir));
         }
-        let mut deserializer = rmp_serde::Deserializer::new(std::fs::File::open(format!("{}/worlddef", map_dir)).unwrap());
-        let world_def: WorldDef = Deserialize::deserialize(&mut deserializer).unwrap();
+        let file = std::fs::File::open(format!("{}/worlddef", map_dir)).map_err(|e| MapLoadError::InvalidWorldDef(format!("{:?}", e)))?;
+        let mut deserializer = rmp_serde::Deserializer::new(file);
+        Deserialize::deserialize(&mut deserializer).map_err(|e| MapLoadError::InvalidWorldDef(format!("{:?}", e)))
+    }
 
+    fn load_world_def(&mut self, map_name: &str, world_def: WorldDef) {
         // load entities
-        for entity in world_def.world.entities {
-            let mut entity_new = unsafe {
-                Entity::new(entity.name.as_str())
-            };
-            for component in entity.components {
-                let component_type = ComponentType::get(component.get_type().name);
-                if component_type.is_none() {
-                    panic!("component type not found: {}", component.get_type().name);
+        // entities get new ids, so keep track of what the worlddef called them to fix up parents afterwards
+        let mut id_map = HashMap::new();
+        for (_, entity) in world_def.world.entities.iter() {
+            self.load_entity_from_def(entity.clone(), None, &mut id_map);
+        }
+        for (old_id, new_id) in id_map.clone() {
+            let entity = self.world.entities.get_by_id(new_id).unwrap();
+            if let Some(parent) = entity.parent {
+                if parent == old_id || !id_map.contains_key(&parent) {
+                    warn!("entity {} has an invalid parent {}, detaching it", new_id, parent);
+                    self.world.entities.set_parent(new_id, None);
+                } else {
+                    self.world.entities.set_parent(new_id, id_map.get(&parent).cloned());
                 }
-                let component_type = component_type.unwrap();
-                let mut component = component;
-                component.component_type = component_type.clone();
-
-                entity_new.add_component(component);
             }
-            self.world.entities.push(entity_new);
         }
 
         self.world.current_map = map_name.to_string();
@@ -212,7 +353,7 @@ impl WorldMachine {
         // if we're a server, queue entity init packets
         if self.is_server {
             let mut entity_init_packets = Vec::new();
-            for entity in &self.world.entities {
+            for (_, entity) in self.world.entities.iter() {
                 entity_init_packets.push(WorldUpdate::InitEntity(entity.uid, entity.clone()));
             }
             self.queue_updates(entity_init_packets);
@@ -222,100 +363,551 @@ impl WorldMachine {
         for system in world_def.world.systems {
             self.world.systems.push(system);
         }
+        self.system_schedule = systems::build_schedule(&self.world.systems);
 
-        Ok(())
+        // map logic is authoritative, so only the server runs the script
+        if self.is_server {
+            self.map_script = MapScript::load(&self.game_data_path, map_name);
+            self.set_movement_settings(MovementSettings::load(&self.game_data_path, map_name));
+            self.build_navmesh();
+        }
+    }
+
+    /// (re)builds the navmesh from the map's colliders and meshes
+    pub fn build_navmesh(&mut self) {
+        let start = Instant::now();
+        self.navmesh = NavMesh::build(&self.world.entities, &self.game_data_path, NavAgent::default()).map(Arc::new);
+        match &self.navmesh {
+            Some(navmesh) => info!("built navmesh for {} in {:?}, {} nodes", self.world.current_map, start.elapsed(), navmesh.nodes.len()),
+            None => warn!("no navmesh for {}", self.world.current_map),
+        }
+    }
+
+    pub fn navmesh(&self) -> Option<Arc<NavMesh>> {
+        self.navmesh.clone()
+    }
+
+    /// waypoints from one place to another along the navmesh, see NavMesh::find_path
+    pub fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<Vec3>> {
+        self.navmesh.as_ref()?.find_path(from, to)
+    }
+
+    /// reloads the current map's script, the old one keeps running if the new one doesn't compile
+    pub fn reload_map_script(&mut self) {
+        if !self.is_server {
+            return;
+        }
+        match MapScript::load(&self.game_data_path, &self.world.current_map) {
+            Some(script) => {
+                info!("reloaded map script for {}", self.world.current_map);
+                self.map_script = Some(script);
+            }
+            None => warn!("map script for {} didn't load, keeping the old one", self.world.current_map),
+        }
+    }
+
+    /// updates every entity spawned from base/entities/<prefab>.edef to match the file
+    /// instances keep their transform, every other component is replaced
+    pub fn reload_prefab(&mut self, prefab: &str) {
+        let instances = self.prefab_instances.iter().filter(|(_, name)| name.as_str() == prefab).map(|(id, _)| *id).collect::<Vec<EntityId>>();
+        if instances.is_empty() {
+            return;
+        }
+        let entity_def = match self.load_entity_def(prefab) {
+            Some(entity_def) => entity_def,
+            None => {
+                warn!("reload_prefab: {} didn't load, leaving its instances alone", prefab);
+                return;
+            }
+        };
+        let mut components = Vec::new();
+        for component in entity_def.components {
+            if component.get_type().name == COMPONENT_TYPE_TRANSFORM.name {
+                continue;
+            }
+            let mut component = component;
+            match ComponentType::get(component.get_type().name) {
+                Some(component_type) => component.component_type = component_type,
+                None => {
+                    warn!("reload_prefab: component type not found: {}", component.get_type().name);
+                    return;
+                }
+            }
+            components.push(component);
+        }
+        let mut updates = Vec::new();
+        for entity_id in instances {
+            let handle = match self.world.entities.handle_of(entity_id) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            let entity = self.world.entities.get_mut(handle).unwrap();
+            entity.components.retain(|component| component.component_type == *COMPONENT_TYPE_TRANSFORM);
+            entity.components.extend(components.iter().cloned());
+            updates.push(WorldUpdate::InitEntity(entity_id, entity.clone()));
+            self.world.entities.reindex(handle);
+            self.initialise_entity_physics(entity_id);
+            if !self.is_server {
+                self.entities_wanting_to_load_things.push(handle);
+            }
+        }
+        info!("reloaded {} instance(s) of {}", updates.len(), prefab);
+        if self.is_server {
+            self.queue_updates(updates);
+        }
+    }
+
+    pub fn current_map(&self) -> &str {
+        &self.world.current_map
+    }
+
+    // nested children in a worlddef are flattened into the store, the hierarchy is kept through their parent ids
+    fn load_entity_from_def(&mut self, entity: Entity, parent: Option<EntityId>, id_map: &mut HashMap<EntityId, EntityId>) {
+        let mut entity_new = Entity::new(entity.name.as_str());
+        for component in entity.components {
+            let component_type = ComponentType::get(component.get_type().name);
+            if component_type.is_none() {
+                panic!("component type not found: {}", component.get_type().name);
+            }
+            let component_type = component_type.unwrap();
+            let mut component = component;
+            component.component_type = component_type.clone();
+
+            entity_new.add_component(component);
+        }
+        // parent is an id from the worlddef at this point, load_map fixes it up once everything exists
+        entity_new.parent = parent.or(entity.parent);
+        id_map.insert(entity.uid, entity_new.uid);
+        self.world.entities.insert(entity_new);
+        for child in entity.children {
+            self.load_entity_from_def(child, Some(entity.uid), id_map);
+        }
     }
 
     /// this should only be called once per map load
     pub fn initialise_entities(&mut self) {
-        for entity in &mut self.world.entities {
-            if let Some(box_collider) = entity.get_component(COMPONENT_TYPE_BOX_COLLIDER.clone()) {
-                let box_collider = box_collider.borrow();
-                let position = box_collider.get_parameter("position").borrow().clone();
-                let mut position = match position.value {
-                    ParameterValue::Vec3(position) => position,
-                    _ => panic!("position is not a vec3"),
-                };
-                let scale = box_collider.get_parameter("scale").borrow().clone();
-                let mut scale = match scale.value {
-                    ParameterValue::Vec3(scale) => scale,
-                    _ => panic!("scale is not a vec3"),
-                };
-                if let Some(transform) = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()) {
-                    let transform = transform.borrow();
-                    let trans_position = transform.get_parameter("position").borrow().clone();
-                    let trans_position = match trans_position.value {
-                        ParameterValue::Vec3(position) => position,
-                        _ => panic!("position is not a vec3"),
-                    };
-                    let trans_scale = transform.get_parameter("scale").borrow().clone();
-                    let trans_scale = match trans_scale.value {
-                        ParameterValue::Vec3(scale) => scale,
-                        _ => panic!("scale is not a vec3"),
-                    };
-                    position += trans_position;
-                    scale *= trans_scale;
+        let entity_ids = self.world.entities.iter().map(|(_, entity)| entity.uid).collect::<Vec<EntityId>>();
+        for entity_id in entity_ids {
+            self.initialise_entity_physics(entity_id);
+        }
+        self.map_finalised = true;
+    }
+
+    /// creates the physics objects for an entity's colliders and triggers, replacing any it already had
+    pub fn initialise_entity_physics(&mut self, entity_id: EntityId) {
+        self.release_entity_physics(entity_id);
+        let entity = match self.world.entities.get_by_id(entity_id) {
+            Some(entity) => entity,
+            None => {
+                warn!("initialise_entity_physics: entity {} does not exist", entity_id);
+                return;
+            }
+        };
+        let physics = match self.physics.lock().unwrap().clone() {
+            Some(physics) => physics,
+            None => {
+                warn!("initialise_entity_physics: no physics system");
+                return;
+            }
+        };
+        let mut entity_physics = EntityPhysics::default();
+        let mut trigger_state = None;
+        // what a collider is made of, see base/materials.yaml
+        let surface = |component: &Component| match component.get_parameter("material").value {
+            ParameterValue::String(ref name) => Materials::surface(name),
+            _ => Materials::surface(""),
+        };
+        if let Some(box_collider) = entity.get_component(COMPONENT_TYPE_BOX_COLLIDER.clone()) {
+            let box_collider = box_collider.borrow();
+            let position = box_collider.get_parameter("position").borrow().clone();
+            let position = match position.value {
+                ParameterValue::Vec3(position) => position,
+                _ => panic!("position is not a vec3"),
+            };
+            let scale = box_collider.get_parameter("scale").borrow().clone();
+            let mut scale = match scale.value {
+                ParameterValue::Vec3(scale) => scale,
+                _ => panic!("scale is not a vec3"),
+            };
+            // the offset is relative to the entity, wherever it is in the hierarchy
+            let world_transform = self.world.entities.world_transform(entity.uid).unwrap_or_default();
+            position = world_transform.transform_point(position);
+            scale *= world_transform.scale;
+            let box_collider_physics = physics.create_collider_static(&self.game_data_path, &ColliderShape::Box(scale), position, world_transform.rotation, surface(box_collider)).unwrap();
+            entity_physics.colliders.push(box_collider_physics);
+        }
+        let world_transform = self.world.entities.world_transform(entity.uid).unwrap_or_default();
+        let float = |component: &Component, name: &str| match component.get_parameter(name).value {
+            ParameterValue::Float(v) => v as f32,
+            _ => 0.0,
+        };
+        let offset = |component: &Component| match component.get_parameter("position").value {
+            ParameterValue::Vec3(position) => world_transform.transform_point(position),
+            _ => world_transform.position,
+        };
+        if let Some(sphere_collider) = entity.get_component(COMPONENT_TYPE_SPHERE_COLLIDER.clone()) {
+            let scale = world_transform.scale;
+            let radius = float(sphere_collider, "radius") * scale.x.abs().max(scale.y.abs()).max(scale.z.abs());
+            match physics.create_collider_static(&self.game_data_path, &ColliderShape::Sphere(radius), offset(sphere_collider), world_transform.rotation, surface(sphere_collider)) {
+                Some(collider) => entity_physics.colliders.push(collider),
+                None => warn!("entity {} has a sphere collider with a bad radius", entity_id),
+            }
+        }
+        if let Some(capsule_collider) = entity.get_component(COMPONENT_TYPE_CAPSULE_COLLIDER.clone()) {
+            let scale = world_transform.scale;
+            let radius = float(capsule_collider, "radius") * scale.x.abs().max(scale.z.abs());
+            let height = float(capsule_collider, "height") * scale.y.abs();
+            match physics.create_collider_static(&self.game_data_path, &ColliderShape::Capsule(radius, height), offset(capsule_collider), world_transform.rotation, surface(capsule_collider)) {
+                Some(collider) => entity_physics.colliders.push(collider),
+                None => warn!("entity {} has a capsule collider with a bad radius or height", entity_id),
+            }
+        }
+        for (component_type, convex) in [(COMPONENT_TYPE_MESH_COLLIDER.clone(), false), (COMPONENT_TYPE_CONVEX_COLLIDER.clone(), true)] {
+            let collider = match entity.get_component(component_type) {
+                Some(collider) => collider,
+                None => continue,
+            };
+            let mesh = match collider.get_parameter("mesh").value {
+                ParameterValue::String(ref mesh) => mesh.clone(),
+                _ => continue,
+            };
+            let shape = if convex {
+                ColliderShape::Convex(mesh, world_transform.scale)
+            } else {
+                ColliderShape::Mesh(mesh, world_transform.scale)
+            };
+            if let Some(collider) = physics.create_collider_static(&self.game_data_path, &shape, world_transform.position, world_transform.rotation, surface(collider)) {
+                entity_physics.colliders.push(collider);
+            }
+        }
+        if let Some(trigger) = entity.get_component(COMPONENT_TYPE_TRIGGER.clone()) {
+            let trigger = trigger.borrow();
+            let position = trigger.get_parameter("position").borrow().clone();
+            let position = match position.value {
+                ParameterValue::Vec3(position) => position,
+                _ => panic!("position is not a vec3"),
+            };
+            let scale = trigger.get_parameter("size").borrow().clone();
+            let mut scale = match scale.value {
+                ParameterValue::Vec3(scale) => scale,
+                _ => panic!("scale is not a vec3"),
+            };
+            // the offset is relative to the entity, wherever it is in the hierarchy
+            let position = world_transform.transform_point(position);
+            scale *= world_transform.scale;
+            let trigger_physics = physics.create_trigger_shape(position, world_transform.rotation, scale, Materials::surface("")).unwrap();
+            trigger_physics.set_owner(entity_id);
+            debug!("added trigger to physics scene with position: {:?} and scale: {:?}", position, scale);
+            entity_physics.triggers.push(trigger_physics);
+            trigger_state = Some(Trigger::from_component(entity_id, trigger));
+        }
+        if let Some(trigger_state) = trigger_state {
+            self.triggers.insert(entity_id, trigger_state);
+        }
+        if let Some(npc) = entity.get_component(COMPONENT_TYPE_NPC.clone()) {
+            if self.is_server {
+                let position = self.world.entities.world_transform(entity_id).unwrap_or_default().position;
+                match NpcController::new(entity_id, npc, position, &physics) {
+                    Some(npc) => {
+                        self.npcs.insert(entity_id, npc);
+                    }
+                    None => warn!("failed to create a character controller for npc {}", entity_id),
+                }
+            }
+        }
+        let platform = entity.get_component(COMPONENT_TYPE_PLATFORM.clone());
+        if let Some(platform) = platform {
+            if entity.parent.is_some() {
+                warn!("entity {} is a platform but also has a parent, only top level entities can move along a path", entity_id);
+            } else if self.is_server {
+                if let Some(path) = PlatformPath::new(entity_id, platform, world_transform.position) {
+                    self.platforms.insert(entity_id, path);
+                }
+            }
+        }
+        if let Some(rigid_body) = entity.get_component(COMPONENT_TYPE_RIGID_BODY.clone()) {
+            if entity.parent.is_some() {
+                warn!("entity {} has a rigid body but also a parent, only top level entities can be simulated", entity_id);
+            } else {
+                // clients don't simulate anything themselves, their copy just follows the server's
+                // platforms are moved by their path rather than by physics
+                let kinematic = !self.is_server || platform.is_some();
+                match RigidBody::new(entity_id, rigid_body, &world_transform, kinematic, &self.game_data_path, &physics) {
+                    Some(rigid_body) => {
+                        self.rigid_bodies.insert(entity_id, rigid_body);
+                    }
+                    None => warn!("failed to create a rigid body for entity {}", entity_id),
                 }
-                let box_collider_physics = self.physics.lock().unwrap().as_ref().unwrap().create_box_collider_static(position, scale, Materials::Player).unwrap();
-                box_collider_physics.add_self_to_scene(self.physics.lock().unwrap().clone().unwrap());
-            }
-            if let Some(trigger) = entity.get_component(COMPONENT_TYPE_TRIGGER.clone()) {
-                let trigger = trigger.borrow();
-                let position = trigger.get_parameter("position").borrow().clone();
-                let mut position = match position.value {
-                    ParameterValue::Vec3(position) => position,
-                    _ => panic!("position is not a vec3"),
+            }
+        }
+        let volumes = MovementVolume::from_entity(entity, &world_transform);
+        if !volumes.is_empty() {
+            self.movement_volumes.insert(entity_id, volumes);
+        }
+        for collider in entity_physics.colliders.iter() {
+            collider.set_owner(entity_id);
+        }
+        if !entity_physics.colliders.is_empty() || !entity_physics.triggers.is_empty() {
+            self.entity_physics.insert(entity_id, entity_physics);
+        }
+    }
+
+    pub fn release_entity_physics(&mut self, entity_id: EntityId) {
+        self.triggers.remove(&entity_id);
+        // dropping the controller and colliders takes them out of the scene
+        self.npcs.remove(&entity_id);
+        self.rigid_bodies.remove(&entity_id);
+        self.platforms.remove(&entity_id);
+        self.movement_volumes.remove(&entity_id);
+        self.entity_physics.remove(&entity_id);
+    }
+
+    /// hands trigger enters and exits from the last physics tick to the handlers named by their Trigger components
+    pub fn process_trigger_events(&mut self) {
+        let events = match self.physics.lock().unwrap().as_ref() {
+            Some(physics) => physics.drain_trigger_events(),
+            None => return,
+        };
+        for event in events {
+            let who = match WhoTriggered::from_owner(event.other) {
+                Some(who) => who,
+                None => continue,
+            };
+            let trigger = match self.triggers.get_mut(&event.trigger) {
+                Some(trigger) => trigger,
+                None => continue,
+            };
+            if !trigger.report.accepts(&who, self.is_server) {
+                continue;
+            }
+            let action = if event.entered {
+                if trigger.occupants.contains(&who) {
+                    continue;
+                }
+                trigger.occupants.push(who);
+                TriggerAction::Enter
+            } else {
+                trigger.occupants.retain(|occupant| *occupant != who);
+                TriggerAction::Exit
+            };
+            if trigger.trigger_type == TriggerType::OnEnter {
+                let handler = trigger.handler.clone();
+                self.run_trigger_handler(&handler, TriggerContext {
+                    trigger: event.trigger,
+                    who,
+                    action,
+                });
+            }
+        }
+    }
+
+    /// runs the handlers of every use trigger `who` is currently standing in
+    pub fn use_triggers(&mut self, who: WhoTriggered) {
+        let used = self.triggers.values()
+            .filter(|trigger| trigger.trigger_type == TriggerType::OnUse && trigger.occupants.contains(&who))
+            .map(|trigger| (trigger.entity, trigger.handler.clone()))
+            .collect::<Vec<_>>();
+        for (trigger, handler) in used {
+            self.run_trigger_handler(&handler, TriggerContext {
+                trigger,
+                who,
+                action: TriggerAction::Use,
+            });
+        }
+    }
+
+    fn run_trigger_handler(&mut self, handler: &str, context: TriggerContext) {
+        let trigger = context.trigger as i64;
+        let who = context.who.entity_id().map(|id| id as i64).unwrap_or(-1);
+        let (hook, action) = match context.action {
+            TriggerAction::Enter => ("on_trigger_enter", "enter"),
+            TriggerAction::Exit => ("on_trigger_exit", "exit"),
+            TriggerAction::Use => ("on_trigger_use", "use"),
+        };
+        self.run_script_hook(hook, (trigger, who));
+        if handler.is_empty() {
+            return;
+        }
+        if let Some(handler) = triggers::get_handler(handler) {
+            handler(self, &context);
+        } else if self.map_script.as_ref().map(|script| script.has_function(handler)).unwrap_or(false) {
+            self.run_script_hook(handler, (trigger, who, action.to_string()));
+        } else {
+            warn!("trigger {} names unknown handler {}", context.trigger, handler);
+        }
+    }
+
+    /// calls a function in the map's script (if there is one and it defines it), then applies what the script asked for
+    pub fn run_script_hook(&mut self, name: &str, args: impl rhai::FuncArgs) {
+        let commands = match self.map_script.as_mut() {
+            Some(script) => script.call(&self.world.entities, name, args),
+            None => return,
+        };
+        for command in commands {
+            self.apply_script_command(command);
+        }
+    }
+
+    fn apply_script_command(&mut self, command: ScriptCommand) {
+        match command {
+            ScriptCommand::SetParameter(entity_id, component, parameter, value) => {
+                let component_type = match ComponentType::get(component.clone()) {
+                    Some(component_type) => component_type,
+                    None => {
+                        warn!("map script: unknown component {}", component);
+                        return;
+                    }
                 };
-                let scale = trigger.get_parameter("size").borrow().clone();
-                let mut scale = match scale.value {
-                    ParameterValue::Vec3(scale) => scale,
-                    _ => panic!("scale is not a vec3"),
+                let current = self.get_entity(entity_id)
+                    .and_then(|entity| entity.get_component(component_type.clone()))
+                    .and_then(|component| component.get_parameters().get(&parameter))
+                    .map(|parameter| parameter.value.clone());
+                let current = match current {
+                    Some(current) => current,
+                    None => {
+                        warn!("map script: entity {} has no parameter {}.{}", entity_id, component, parameter);
+                        return;
+                    }
                 };
-                if let Some(transform) = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()) {
-                    let transform = transform.borrow();
-                    let trans_position = transform.get_parameter("position").borrow().clone();
-                    let trans_position = match trans_position.value {
-                        ParameterValue::Vec3(position) => position,
-                        _ => panic!("position is not a vec3"),
-                    };
-                    let trans_scale = transform.get_parameter("scale").borrow().clone();
-                    let trans_scale = match trans_scale.value {
-                        ParameterValue::Vec3(scale) => scale,
-                        _ => panic!("scale is not a vec3"),
-                    };
-                    position += trans_position;
-                    scale *= trans_scale;
+                match scripting::to_parameter_value(&value, &current) {
+                    Some(value) => {
+                        self.set_component_parameter(entity_id, component_type, &parameter, value);
+                    }
+                    None => warn!("map script: {} is the wrong type for {}.{}", value.type_name(), component, parameter),
+                }
+            }
+            ScriptCommand::Spawn(prefab, entity) => {
+                if let Some(entity_id) = self.spawn(entity, None) {
+                    self.prefab_instances.insert(entity_id, prefab);
+                }
+            }
+            ScriptCommand::Despawn(entity_id) => {
+                self.despawn(entity_id);
+            }
+            ScriptCommand::Chat(message) => {
+                self.queue_updates(vec![WorldUpdate::ChatMessage("server".to_string(), message)]);
+            }
+            ScriptCommand::Teleport(entity_id, position) => {
+                self.queue_updates(vec![WorldUpdate::TeleportPlayer(entity_id, position)]);
+            }
+            ScriptCommand::Jukebox(entity_id, command) => {
+                self.jukebox_command(entity_id, command);
+            }
+            ScriptCommand::Movement(setting, value) => {
+                let mut settings = self.movement_settings;
+                if settings.set(&setting, value) {
+                    self.set_movement_settings(settings);
+                } else {
+                    warn!("map script: there's no movement setting called {}", setting);
                 }
-                let trigger_physics = self.physics.lock().unwrap().as_ref().unwrap().create_trigger_shape(position, scale, Materials::Player).unwrap();
-                trigger_physics.add_self_to_scene(self.physics.lock().unwrap().clone().unwrap());
-                debug!("added trigger to physics scene with position: {:?} and scale: {:?}", position, scale);
             }
         }
     }
 
-    #[allow(clippy::borrowed_box)]
-    pub fn get_entity(&self, entity_id: EntityId) -> Option<Arc<Mutex<&Entity>>> {
-        for entity in self.world.entities.iter() {
-            if entity.get_id() == entity_id {
-                return Some(Arc::new(Mutex::new(entity)));
+    /// reads an entity definition from base/entities/<name>.edef
+    pub fn load_entity_def(&self, name: &str) -> Option<EntityDef> {
+        entities::load_entity_def(&self.game_data_path, name)
+    }
+
+    /// adds an entity to the world at runtime and sets up its physics
+    /// on the server the entity (and any children it carries) are replicated to clients
+    pub fn spawn(&mut self, entity: Entity, parent: Option<EntityId>) -> Option<EntityId> {
+        if self.world.entities.contains_id(entity.uid) {
+            warn!("spawn: entity {} already exists", entity.uid);
+            return None;
+        }
+        if let Some(parent) = parent {
+            if !self.world.entities.contains_id(parent) {
+                warn!("spawn: parent {} does not exist", parent);
+                return None;
+            }
+        }
+        let mut entity = entity;
+        for component in entity.components.iter_mut() {
+            match ComponentType::get(component.get_type().name) {
+                Some(component_type) => component.component_type = component_type,
+                None => {
+                    warn!("spawn: component type not found: {}", component.get_type().name);
+                    return None;
+                }
             }
         }
-        None
+        // children are stored flat, same as on map load
+        let children = std::mem::take(&mut entity.children);
+        entity.parent = parent;
+        let entity_id = entity.uid;
+        let init = WorldUpdate::InitEntity(entity_id, entity.clone());
+        self.world.entities.insert(entity);
+        self.initialise_entity_physics(entity_id);
+        if self.is_server {
+            self.queue_updates(vec![init]);
+        }
+        for child in children {
+            // children of a spawned entity get new ids, the ones they had only meant something in the file they came from
+            let mut new_child = Entity::new(child.name.as_str());
+            new_child.copy_data_from_other_entity(&child);
+            self.spawn(new_child, Some(entity_id));
+        }
+        Some(entity_id)
     }
 
-    pub fn get_entity_index(&self, entity_id: EntityId) -> Option<usize> {
-        for (index, entity) in self.world.entities.iter().enumerate() {
-            if entity.get_id() == entity_id {
-                return Some(index);
+    /// spawns an entity from base/entities/<prefab>.edef, optionally moving it somewhere first
+    pub fn spawn_prefab(&mut self, prefab: &str, position: Option<Vec3>, parent: Option<EntityId>) -> Option<EntityId> {
+        let entity_def = self.load_entity_def(prefab)?;
+        let mut entity = Entity::from_entity_def(&entity_def);
+        if let Some(position) = position {
+            if entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "position", ParameterValue::Vec3(position)).is_none() {
+                warn!("spawn_prefab: {} has no transform, ignoring position", prefab);
             }
         }
-        None
+        let entity_id = self.spawn(entity, parent)?;
+        self.prefab_instances.insert(entity_id, prefab.to_string());
+        Some(entity_id)
+    }
+
+    /// removes an entity and everything parented to it, releasing their physics
+    /// on the server the removal is replicated to clients
+    pub fn despawn(&mut self, entity_id: EntityId) -> bool {
+        match self.world.entities.get_by_id(entity_id) {
+            Some(entity) => {
+                if entity.has_component(COMPONENT_TYPE_PLAYER.clone()) {
+                    warn!("despawn: entity {} is a player, disconnect them instead", entity_id);
+                    return false;
+                }
+            }
+            None => {
+                warn!("despawn: entity {} does not exist", entity_id);
+                return false;
+            }
+        }
+        let mut updates = Vec::new();
+        // children first, so that clients never see an entity whose parent is already gone
+        for id in self.world.entities.descendants_of(entity_id).into_iter().rev() {
+            if self.remove_entity(id).is_some() {
+                updates.push(WorldUpdate::EntityNoLongerExists(id));
+            }
+        }
+        if self.is_server {
+            self.queue_updates(updates);
+        }
+        true
+    }
+
+    pub fn get_entity(&self, entity_id: EntityId) -> Option<&Entity> {
+        self.world.entities.get_by_id(entity_id)
+    }
+
+    pub fn get_entity_mut(&mut self, entity_id: EntityId) -> Option<&mut Entity> {
+        self.world.entities.get_by_id_mut(entity_id)
+    }
+
+    pub fn get_entity_handle(&self, entity_id: EntityId) -> Option<EntityHandle> {
+        self.world.entities.handle_of(entity_id)
     }
 
     /*
     pub fn set_entity_position(&mut self, entity_id: EntityId, position: Vec3) {
-        let entity_index = self.get_entity_index(entity_id).unwrap();
-        let entity = self.world.entities[entity_index].borrow_mut();
+        let entity = self.get_entity_mut(entity_id).unwrap();
         let res = entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "position", ParameterValue::Vec3(position));
         if res.is_none() {
             warn!("attempted to set entity position on an entity that has no transform component");
@@ -323,8 +915,170 @@ impl WorldMachine {
     }
      */
 
-    pub fn remove_entity_at_index(&mut self, index: usize) {
-        self.world.entities.remove(index);
+    /// attaches an entity to a parent (or detaches it with None), keeping its transform as a local offset
+    /// on the server the change is replicated to clients
+    pub fn set_parent(&mut self, entity_id: EntityId, parent: Option<EntityId>) -> bool {
+        if let Some(parent) = parent {
+            if parent == entity_id || !self.world.entities.contains_id(parent) {
+                warn!("set_parent: invalid parent {} for entity {}", parent, entity_id);
+                return false;
+            }
+            if self.world.entities.would_create_cycle(entity_id, parent) {
+                warn!("set_parent: parenting {} to {} would create a cycle", entity_id, parent);
+                return false;
+            }
+        }
+        if !self.world.entities.set_parent(entity_id, parent) {
+            warn!("set_parent: entity {} does not exist", entity_id);
+            return false;
+        }
+        if self.is_server {
+            self.queue_updates(vec![WorldUpdate::SetParent(entity_id, parent)]);
+        }
+        let moved = self.world.entities.descendants_of(entity_id);
+        self.refresh_physics(moved);
+        true
+    }
+
+    // colliders, triggers and movement volumes are put in the world where their entity was when they were made,
+    // so whatever is under an entity that moved has to have them made again to keep up with it
+    fn refresh_physics(&mut self, entity_ids: Vec<EntityId>) {
+        if !self.map_finalised {
+            return;
+        }
+        for entity_id in entity_ids {
+            // a new trigger shape reports everyone inside it again, they haven't actually just entered
+            let occupants = self.triggers.get(&entity_id).map(|trigger| trigger.occupants.clone());
+            self.initialise_entity_physics(entity_id);
+            if let (Some(occupants), Some(trigger)) = (occupants, self.triggers.get_mut(&entity_id)) {
+                trigger.occupants = occupants;
+            }
+        }
+    }
+
+    // an entity's transform changed, anything under it with physics needs to follow
+    fn transform_changed(&mut self, entity_id: EntityId) {
+        let children = self.world.entities.descendants_of(entity_id).into_iter()
+            .skip(1)
+            .filter(|id| self.entity_physics.contains_key(id) || self.movement_volumes.contains_key(id) || self.npcs.contains_key(id))
+            .collect::<Vec<_>>();
+        if !children.is_empty() {
+            self.refresh_physics(children);
+        }
+    }
+
+    /// sets a component parameter on an entity
+    /// on the server the change is sent to clients on the next server tick, reliably or not depending on the parameter
+    pub fn set_component_parameter(&mut self, entity_id: EntityId, component_type: ComponentType, parameter_name: &str, value: ParameterValue) -> Option<()> {
+        let entity = match self.world.entities.get_by_id_mut(entity_id) {
+            Some(entity) => entity,
+            None => {
+                warn!("set_component_parameter: entity {} does not exist", entity_id);
+                return None;
+            }
+        };
+        entity.set_component_parameter(component_type.clone(), parameter_name, value.clone())?;
+        if component_type == *COMPONENT_TYPE_LIGHT {
+            self.lights_changed = true;
+        } else if component_type == *COMPONENT_TYPE_TRANSFORM {
+            self.transform_changed(entity_id);
+        }
+        if self.is_server {
+            self.dirty_parameters.insert((entity_id, component_type.name.clone(), parameter_name.to_string()), (component_type, value));
+        }
+        Some(())
+    }
+
+    /// applies a parameter change that came from the server
+    /// anything older than what we already have for that parameter is dropped, fast packets can arrive out of order
+    fn apply_parameter_update(&mut self, entity_id: EntityId, component_type: ComponentType, parameter_name: &str, value: ParameterValue, tick: u64) {
+        if let Some(ignore) = self.ignore_this_entity {
+            if entity_id == ignore {
+                return;
+            }
+        }
+        let newest = self.parameter_ticks.entry((entity_id, component_type.name.clone(), parameter_name.to_string())).or_insert(0);
+        if tick < *newest {
+            return;
+        }
+        *newest = tick;
+        if let Some(entity) = self.world.entities.get_by_id_mut(entity_id) {
+            if entity.set_component_parameter(component_type.clone(), parameter_name, value).is_none() {
+                warn!("apply_parameter_update: entity {} has no parameter {}.{}", entity_id, component_type.name, parameter_name);
+            } else if component_type == *COMPONENT_TYPE_LIGHT {
+                self.lights_changed = true;
+            } else if component_type == *COMPONENT_TYPE_TRANSFORM {
+                self.transform_changed(entity_id);
+            }
+        }
+    }
+
+    /// serverside, called after every physics step
+    /// moves the parameter tick on, and settles unreliable parameters that haven't changed for SETTLE_TICKS steps
+    pub fn settle_parameters(&mut self) {
+        self.parameter_tick += 1;
+        let tick = self.parameter_tick;
+        let settled = self.settling_parameters.iter()
+            .filter(|(key, (_, _, changed))| tick - changed >= SETTLE_TICKS && !self.dirty_parameters.contains_key(*key))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in settled {
+            if let Some((component_type, value, changed)) = self.settling_parameters.remove(&key) {
+                let (entity_id, _, parameter_name) = key;
+                self.settled_parameters.push(WorldUpdate::SettleParameter(entity_id, component_type, parameter_name, value, changed));
+            }
+        }
+    }
+
+    fn take_dirty_parameters(&mut self) -> Vec<WorldUpdate> {
+        let tick = self.parameter_tick;
+        let mut updates = std::mem::take(&mut self.settled_parameters);
+        for ((entity_id, component_name, parameter_name), (component_type, value)) in std::mem::take(&mut self.dirty_parameters) {
+            if component_type.replication(&parameter_name) == Replication::Unreliable {
+                self.settling_parameters.insert((entity_id, component_name, parameter_name.clone()), (component_type.clone(), value.clone(), tick));
+            }
+            updates.push(WorldUpdate::SetParameter(entity_id, component_type, parameter_name, value, tick));
+        }
+        updates
+    }
+
+    pub fn remove_entity(&mut self, entity_id: EntityId) -> Option<Entity> {
+        self.release_entity_physics(entity_id);
+        self.dirty_parameters.retain(|(id, _, _), _| *id != entity_id);
+        self.settling_parameters.retain(|(id, _, _), _| *id != entity_id);
+        self.settled_parameters.retain(|update| !matches!(update, WorldUpdate::SettleParameter(id, ..) if *id == entity_id));
+        self.parameter_ticks.retain(|(id, _, _), _| *id != entity_id);
+        self.prefab_instances.remove(&entity_id);
+        for trigger in self.triggers.values_mut() {
+            trigger.occupants.retain(|occupant| occupant.entity_id() != Some(entity_id));
+        }
+        self.world.entities.remove_by_id(entity_id)
+    }
+
+    /// runs every scheduled system that is allowed to run on this side, in schedule order
+    /// what each system changes is applied before the next one runs, so later systems see it
+    pub fn run_systems(&mut self, delta: f32) {
+        for i in 0..self.system_schedule.len() {
+            let scheduled = &self.system_schedule[i];
+            let definition = &scheduled.definition;
+            if !definition.runs_on(self.is_server) {
+                continue;
+            }
+            let mut context = SystemContext {
+                entities: &self.world.entities,
+                affected_entities: &scheduled.affected_entities,
+                reads: &definition.reads,
+                writes: &definition.writes,
+                delta,
+                is_server: self.is_server,
+                changes: Vec::new(),
+            };
+            (definition.run)(&mut context);
+            let changes = context.changes;
+            for (entity_id, component_type, parameter_name, value) in changes {
+                self.set_component_parameter(entity_id, component_type, &parameter_name, value);
+            }
+        }
     }
 
     pub fn send_lights_to_renderer(&mut self) -> Option<Vec<crate::light::Light>> {
@@ -332,22 +1086,14 @@ impl WorldMachine {
         //    return Option::None;
         //}
         let mut lights = Vec::new();
-        for entity in &self.world.entities {
-            let components = entity.get_components();
-            let mut light_component = None;
-            let mut transform_component = None; // if we have a transform component, this will be added to the light's position
-            for component in components {
-                if component.get_type() == COMPONENT_TYPE_LIGHT.clone() {
-                    light_component = Some(component);
-                }
-                if component.get_type() == COMPONENT_TYPE_TRANSFORM.clone() {
-                    transform_component = Some(component);
-                }
-            }
+        for (_, entity) in self.world.entities.with_component(COMPONENT_TYPE_LIGHT.clone()) {
+            let light_component = entity.get_component(COMPONENT_TYPE_LIGHT.clone());
+            // the light's position is an offset from wherever the entity is in the world
+            let world_transform = self.world.entities.world_transform(entity.uid).unwrap_or_default();
             if let Some(light) = light_component {
                 let light = light.clone();
                 let position = light.get_parameter("position");
-                let mut position = match position.value {
+                let position = match position.value {
                     ParameterValue::Vec3(v) => v,
                     _ => {
                         error!("send_lights_to_renderer: light position is not a vec3");
@@ -387,18 +1133,7 @@ impl WorldMachine {
                     }
                 };
 
-                if let Some(transform) = transform_component {
-                    let transform = transform.clone();
-                    let trans_position = transform.get_parameter("position");
-                    let trans_position = match trans_position.value {
-                        ParameterValue::Vec3(v) => v,
-                        _ => {
-                            error!("send_lights_to_renderer: transform position is not a vec3");
-                            Vec3::new(0.0, 0.0, 0.0)
-                        }
-                    };
-                    position += trans_position;
-                }
+                let position = world_transform.transform_point(position);
                 lights.push(crate::light::Light {
                     position,
                     color,
@@ -429,6 +1164,7 @@ impl WorldMachine {
                 ConnectionClientside::Lan(connection) => {
                     let attempt = connection.send_fast_and_serialise(message).await;
                 }
+                ConnectionClientside::Demo(_) => {} // nobody to send to
             }
         }
     }
@@ -453,6 +1189,7 @@ impl WorldMachine {
                     }
                     true
                 }
+                ConnectionClientside::Demo(_) => true,
             }
         } else {
             false
@@ -469,11 +1206,6 @@ impl WorldMachine {
         }
     }
 
-    async fn remove_entity(&mut self, packet: SteadyPacket) {
-        if let SteadyPacket::RemoveEntity(entity_id) = packet {
-        }
-    }
-
     pub async fn set_name(&mut self, name: String) {
         self.send_steady_message(SteadyPacketData {
             packet: SteadyPacket::SetName(String::new(), name),
@@ -509,19 +1241,23 @@ impl WorldMachine {
                 }
 
                 // check if we already have this entity
-                if self.get_entity(entity_id).is_none() {
+                if let Some(handle) = self.get_entity_handle(entity_id) {
+                    // we already have this entity, so we need to update it
+                    let entity = self.world.entities.get_mut(handle).unwrap();
+                    entity.copy_data_from_other_entity(&entity_data);
+                    self.world.entities.reindex(handle);
+                    self.entities_wanting_to_load_things.push(handle);
+                } else {
                     let mut entity = unsafe {
                         Entity::new_with_id(entity_data.name.as_str(), entity_id)
                     };
                     entity.copy_data_from_other_entity(&entity_data);
-                    self.world.entities.push(entity);
-                    self.entities_wanting_to_load_things.push(self.world.entities.len() - 1);
-                } else {
-                    // we already have this entity, so we need to update it
-                    let entity_index = self.get_entity_index(entity_id).unwrap();
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
-                    entity.copy_data_from_other_entity(&entity_data);
-                    self.entities_wanting_to_load_things.push(entity_index);
+                    let handle = self.world.entities.insert(entity);
+                    self.entities_wanting_to_load_things.push(handle);
+                }
+                // anything that arrives before FinaliseMapLoad is set up by initialise_entities
+                if self.map_finalised {
+                    self.initialise_entity_physics(entity_id);
                 }
                 debug!("initialise entity message received");
             }
@@ -531,6 +1267,7 @@ impl WorldMachine {
             SteadyPacket::InitialisePlayer(uuid, id, name, position, rotation, scale) => {
                 debug!("initialise player message received");
                 let mut player = Player::default();
+                player.set_movement_settings(self.movement_settings);
                 player.init(self.physics.lock().unwrap().clone().unwrap(), uuid, name.clone(), position, rotation, scale);
                 chat::CHAT_BUFFER.lock().unwrap().my_name = name;
                 self.ignore_this_entity = Some(id);
@@ -539,18 +1276,26 @@ impl WorldMachine {
                     entity_id: None
                 });
             }
+            SteadyPacket::InitialiseSystems(system_list) => {
+                self.world.systems = system_list;
+                self.system_schedule = systems::build_schedule(&self.world.systems);
+            }
             SteadyPacket::FinaliseMapLoad => {
                 self.initialise_entities();
             }
+            SteadyPacket::SetParent(entity_id, parent) => {
+                self.set_parent(entity_id, parent);
+            }
+            SteadyPacket::EntitySetParameter(entity_id, component_type, parameter_name, parameter_value, tick) => {
+                self.apply_parameter_update(entity_id, component_type, parameter_name.as_str(), parameter_value, tick);
+            }
             SteadyPacket::RemoveEntity(entity_id) => {
                 if let Some(ignore) = self.ignore_this_entity {
                     if entity_id == ignore {
                         return;
                     }
                 }
-                let entity_index = self.get_entity_index(entity_id);
-                if let Some(entity_index) = entity_index {
-                    self.world.entities.remove(entity_index);
+                if self.remove_entity(entity_id).is_some() {
                     debug!("remove entity message received");
                     debug!("world entities: {:?}", self.world.entities);
                 }
@@ -563,10 +1308,9 @@ impl WorldMachine {
                     }
                 }
                 if !dont_show {
-                    let players = self.world.entities.iter().filter(|e| e.has_component(COMPONENT_TYPE_PLAYER.clone())).collect::<Vec<&Entity>>();
                     let name = {
                         let mut namebuf = None;
-                        for player in players {
+                        for (_, player) in self.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone()) {
                             if let Some(player_component) = player.get_component(COMPONENT_TYPE_PLAYER.clone()) {
                                 let uuid = player_component.get_parameter("uuid");
                                 let uuid = match &uuid.value {
@@ -595,10 +1339,14 @@ impl WorldMachine {
                 }
             }
             SteadyPacket::SetName(who_sent, new_name) => {
-                let players = self.world.entities.iter_mut().filter(|e| e.has_component(COMPONENT_TYPE_PLAYER.clone())).collect::<Vec<&mut Entity>>();
+                let players = self.world.entities.handles_with_component(COMPONENT_TYPE_PLAYER.clone());
                 let name = {
                     let mut namebuf = None;
-                    for player in players {
+                    for handle in players {
+                        let player = match self.world.entities.get_mut(handle) {
+                            Some(player) => player,
+                            None => continue,
+                        };
                         if let Some(player_component) = player.get_component(COMPONENT_TYPE_PLAYER.clone()).cloned() {
                             let uuid = player_component.get_parameter("uuid");
                             let uuid = match &uuid.value {
@@ -650,12 +1398,21 @@ impl WorldMachine {
                     self.snowballs.push(snowball);
                 }
             }
+            SteadyPacket::Use(_) => {}
             SteadyPacket::Respawn(position) => {
                 if let Some(player) = &mut self.player {
                     info!("respawning player");
                     player.player.set_position(position);
                 }
             }
+            SteadyPacket::MovementSettings(settings) => {
+                self.set_movement_settings(settings);
+            }
+            SteadyPacket::ServerTime(server_time) => {
+                // the server answers pings with this straight away, but it also sends one unasked when we join
+                let round_trip = self.ping_sent.take().map(|sent| sent.elapsed().as_secs_f64()).unwrap_or(0.0);
+                self.sync_server_time(server_time, round_trip);
+            }
             SteadyPacket::Ping => {}
         }
     }
@@ -683,6 +1440,7 @@ impl WorldMachine {
                         self.handle_steady_message(message.clone().packet).await;
                     }
                 }
+                ConnectionClientside::Demo(_) => {} // see tick_demo
             }
         }
     }
@@ -695,13 +1453,13 @@ impl WorldMachine {
                         return;
                     }
                 }
-                if let Some(entity_index) = self.get_entity_index(entity_id) {
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
+                if let Some(entity) = self.world.entities.get_by_id_mut(entity_id) {
                     let transform = entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "position", ParameterValue::Vec3(vec3));
                     if transform.is_none() {
                         warn!("process_fast_messages: failed to set transform rotation");
                     }
                 }
+                self.transform_changed(entity_id);
             }
             FastPacket::ChangeRotation(entity_id, quat) => {
                 if let Some(ignore) = self.ignore_this_entity {
@@ -709,13 +1467,13 @@ impl WorldMachine {
                         return;
                     }
                 }
-                if let Some(entity_index) = self.get_entity_index(entity_id) {
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
+                if let Some(entity) = self.world.entities.get_by_id_mut(entity_id) {
                     let transform = entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "rotation", ParameterValue::Quaternion(quat));
                     if transform.is_none() {
                         warn!("process_fast_messages: failed to set transform rotation");
                     }
                 }
+                self.transform_changed(entity_id);
             }
             FastPacket::ChangeScale(entity_id, vec3) => {
                 if let Some(ignore) = self.ignore_this_entity {
@@ -723,13 +1481,13 @@ impl WorldMachine {
                         return;
                     }
                 }
-                if let Some(entity_index) = self.get_entity_index(entity_id) {
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
+                if let Some(entity) = self.world.entities.get_by_id_mut(entity_id) {
                     let transform = entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "scale", ParameterValue::Vec3(vec3));
                     if transform.is_none() {
                         warn!("process_fast_messages: failed to set transform scale");
                     }
                 }
+                self.transform_changed(entity_id);
             }
             FastPacket::PlayerMoved(entity_id, new_position, new_rotation, new_head_rotation) => {
                 if let Some(ignore) = self.ignore_this_entity {
@@ -737,8 +1495,7 @@ impl WorldMachine {
                         return;
                     }
                 }
-                if let Some(entity_index) = self.get_entity_index(entity_id) {
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
+                if let Some(entity) = self.world.entities.get_by_id_mut(entity_id) {
                     let prev_transform = entity.get_component(COMPONENT_TYPE_PLAYER.clone());
                     if let Some(prev_transform) = prev_transform {
                         let prev_position = prev_transform.get_parameter("position");
@@ -785,19 +1542,8 @@ impl WorldMachine {
 
                 }
             }
-            FastPacket::EntitySetParameter(entity_id, component_type, parameter_name, parameter_value) => {
-                if let Some(ignore) = self.ignore_this_entity {
-                    if entity_id == ignore {
-                        return;
-                    }
-                }
-                if let Some(entity_index) = self.get_entity_index(entity_id) {
-                    let entity = self.world.entities.get_mut(entity_index).unwrap();
-                    let component = entity.set_component_parameter(component_type, parameter_name.as_str(), parameter_value);
-                    if component.is_none() {
-                        warn!("process_fast_messages: failed to set component parameter");
-                    }
-                }
+            FastPacket::EntitySetParameter(entity_id, component_type, parameter_name, parameter_value, tick) => {
+                self.apply_parameter_update(entity_id, component_type, parameter_name.as_str(), parameter_value, tick);
             }
             FastPacket::PlayerFuckYouMoveHere(new_position) => {
                 if let Some(player) = self.player.as_mut() {
@@ -841,6 +1587,7 @@ impl WorldMachine {
                         self.handle_message_fast(message.clone().packet.unwrap()).await;
                     }
                 }
+                ConnectionClientside::Demo(_) => {} // see tick_demo
             }
         }
     }
@@ -917,6 +1664,14 @@ impl WorldMachine {
                 ClientUpdate::IThrewSnowball => {
                     self.throw_snowball().await;
                 }
+                ClientUpdate::IUsed => {
+                    // client triggers are ours to handle, the server takes care of the rest
+                    self.use_triggers(WhoTriggered::Client);
+                    self.send_steady_message(SteadyPacketData {
+                        packet: SteadyPacket::Use(String::new()),
+                        uuid: server::generate_uuid(),
+                    }).await;
+                }
             }
         }
     }
@@ -931,6 +1686,7 @@ impl WorldMachine {
                 crate::ui::DISCONNECTED.store(true, Ordering::Relaxed);
             }
             self.last_ping = Instant::now();
+            self.ping_sent = Some(self.last_ping);
         }
     }
 
@@ -941,6 +1697,41 @@ impl WorldMachine {
         self.ping_if_needed().await;
     }
 
+    /// plays the demo we're connected to forward by delta, applying its packets as if a server had sent them
+    pub async fn tick_demo(&mut self, delta: f32) {
+        let demo = match &self.server_connection {
+            Some(ConnectionClientside::Demo(demo)) => demo.clone(),
+            _ => return,
+        };
+        let mut demo = demo.lock().await;
+        demo.handle_input();
+        demo.advance(delta);
+        if demo.take_restart() {
+            self.load_demo_header(&demo.header);
+        }
+        let packets = demo.due_packets();
+        drop(demo);
+        for packet in packets {
+            match packet {
+                DemoPacket::Steady(packet) => self.handle_steady_message(packet).await,
+                DemoPacket::Fast(packet) => self.handle_message_fast(packet).await,
+                DemoPacket::SteadyTo(_, _) | DemoPacket::FastTo(_, _) => {}
+            }
+        }
+    }
+
+    // puts the world back to how it was when the demo started recording
+    fn load_demo_header(&mut self, header: &DemoHeader) {
+        self.blank_slate(false);
+        self.world.current_map = header.map.clone();
+        for entity in header.entities.iter() {
+            self.world.entities.insert(entity.clone());
+        }
+        self.world.systems = header.systems.clone();
+        self.system_schedule = systems::build_schedule(&self.world.systems);
+        self.initialise_entities();
+    }
+
     pub async fn server_tick(&mut self) -> Option<Vec<WorldUpdate>> {
         let mut updates = Vec::new();
 
@@ -949,6 +1740,7 @@ impl WorldMachine {
             updates.push(update);
         });
         drop(world_updates);
+        updates.append(&mut self.take_dirty_parameters());
 
         if !updates.is_empty() {
             Some(updates)
@@ -988,25 +1780,21 @@ impl WorldMachine {
 
         let mut updates = Vec::new();
 
+        let position = self.player.as_mut().map(|player_container| player_container.player.get_position());
+        let mode = position.map(|position| self.movement_mode_at(position)).unwrap_or_default();
         if let Some(player_container) = self.player.as_mut() {
             let player = &mut player_container.player;
-            let player_updates = player.handle_input(renderer, delta_time);
+            let player_updates = player.handle_input(renderer, mode, delta_time);
             if let Some(mut player_updates) = player_updates {
                 updates.append(&mut player_updates);
             }
         }
 
-        let mut snowballs_to_remove = Vec::new();
-        for (i, snowball) in self.snowballs.iter_mut().enumerate() {
-            snowball.time_to_live -= delta_time;
-            if snowball.time_to_live <= 0.0 {
-                snowballs_to_remove.push(i);
-            }
-        }
-
-        for (i, snowball) in snowballs_to_remove.iter().enumerate() {
-            self.snowballs.remove(*snowball - i);
-        }
+        self.run_systems(delta_time);
+        self.tick_platforms(delta_time);
+        self.tick_rigid_bodies(delta_time);
+        self.process_trigger_events();
+        self.tick_snowballs(delta_time);
 
         if self.last_ping.elapsed().as_secs_f32() >= 10.0 {
             crate::ui::UNSTABLE_CONNECTION.store(true, Ordering::Relaxed);
@@ -1030,6 +1818,34 @@ impl WorldMachine {
         }
     }
 
+    // debug view, a little ball on every navmesh node near the camera
+    fn render_navmesh(&mut self, renderer: &mut ht_renderer) {
+        if !self.navmesh_attempted && self.map_finalised {
+            self.navmesh_attempted = true;
+            self.build_navmesh();
+        }
+        let navmesh = match &self.navmesh {
+            Some(navmesh) => navmesh.clone(),
+            None => return,
+        };
+        let mut mesh = match renderer.meshes.get("snowball").cloned() {
+            Some(mesh) => mesh,
+            None => return,
+        };
+        let texture = renderer.textures.get("default").cloned().unwrap();
+        let camera_position = renderer.camera.get_position();
+        for node in navmesh.nodes.iter() {
+            if crate::helpers::distance(node.position, camera_position) > NAVMESH_VIEW_DISTANCE {
+                continue;
+            }
+            mesh.position = node.position;
+            mesh.rotation = Quaternion::default();
+            // nodes with every neighbour are smaller, so the edges of the navmesh stand out
+            mesh.scale = if node.neighbours.len() == 8 { Vec3::new(0.05, 0.05, 0.05) } else { Vec3::new(0.12, 0.12, 0.12) };
+            mesh.render(renderer, Some(&texture), None, None);
+        }
+    }
+
     pub fn render(&mut self, renderer: &mut ht_renderer, shadow_pass: Option<(u8, usize)>) {
         // todo! actual good player rendering
         if let Some(player) = &mut self.player {
@@ -1045,7 +1861,8 @@ impl WorldMachine {
                 mesh.rotation = rotation;
                 mesh.scale = Vec3::new(1.0, 1.0, 1.0);
 
-                let move_anim = MoveAnim::from_values(player.player.speed, player.player.strafe);
+                let mut move_anim = MoveAnim::from_values(player.player.speed, player.player.strafe);
+                move_anim.set_crouch(player.player.is_crouching());
 
                 mesh.render(renderer, Some(&texture), Some(move_anim.weights()), shadow_pass);
             }
@@ -1067,13 +1884,24 @@ impl WorldMachine {
             }
         }
 
+        if shadow_pass.is_none() && crate::ui::SHOW_NAVMESH.load(Ordering::Relaxed) {
+            self.render_navmesh(renderer);
+        }
+
         let lights = self.send_lights_to_renderer();
         if let Some(..) = lights {
             renderer.set_lights(lights.unwrap());
         }
-        let mut indices_to_remove = Vec::new();
-        for index in self.entities_wanting_to_load_things.clone() {
-            let entity = &self.world.entities[index];
+        let mut handles_to_remove = Vec::new();
+        for handle in self.entities_wanting_to_load_things.clone() {
+            let entity = match self.world.entities.get(handle) {
+                Some(entity) => entity,
+                None => {
+                    // entity was removed before it finished loading
+                    handles_to_remove.push(handle);
+                    continue;
+                }
+            };
             let components = entity.get_components();
             let mut finished_loading = components.len();
             for component in components {
@@ -1141,12 +1969,12 @@ impl WorldMachine {
                 }
             }
             if finished_loading == 0 {
-                indices_to_remove.push(index);
+                handles_to_remove.push(handle);
             }
         }
-        self.entities_wanting_to_load_things.retain(|x| !indices_to_remove.contains(x));
-        for (i, entity) in self.world.entities.iter_mut().enumerate() {
-            if self.entities_wanting_to_load_things.contains(&i) {
+        self.entities_wanting_to_load_things.retain(|x| !handles_to_remove.contains(x));
+        for (handle, entity) in self.world.entities.with_component(COMPONENT_TYPE_MESH_RENDERER.clone()) {
+            if self.entities_wanting_to_load_things.contains(&handle) {
                 continue;
             }
             if let Some(mesh_renderer) = entity.get_component(COMPONENT_TYPE_MESH_RENDERER.clone()) {
@@ -1206,38 +2034,14 @@ impl WorldMachine {
                     let old_rotation = mesh.rotation;
                     let old_scale = mesh.scale;
 
-                    // if this entity has a transform, apply it
-                    if let Some(transform) = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()) {
-                        let position = match transform.get_parameter("position").value {
-                            ParameterValue::Vec3(v) => v,
-                            _ => {
-                                error!("render: transform position is not a vec3");
-                                continue;
-                            }
-                        };
-                        mesh.position += position;
-                        let rotation = match transform.get_parameter("rotation").value {
-                            ParameterValue::Quaternion(v) => v,
-                            _ => {
-                                error!("render: transform rotation is not a quaternion");
-                                continue;
-                            }
-                        };
-                        // add a bit of rotation to the transform to make things more interesting
-                        mesh.rotation = rotation;
-                        let scale = match transform.get_parameter("scale").value {
-                            ParameterValue::Vec3(v) => v,
-                            _ => {
-                                error!("render: transform scale is not a vec3");
-                                continue;
-                            }
-                        };
-                        mesh.scale *= scale;
+                    // if this entity has a transform (or a parent that does), apply it
+                    if entity.has_component(COMPONENT_TYPE_TRANSFORM.clone()) || entity.parent.is_some() {
+                        let world_transform = self.world.entities.world_transform(entity.uid).unwrap_or_default();
+                        mesh.position = world_transform.transform_point(mesh.position);
+                        mesh.rotation = world_transform.rotation;
+                        mesh.scale *= world_transform.scale;
                     }
 
-                    // add a bit of rotation to the transform to make things more interesting
-                    //entity.set_component_parameter(COMPONENT_TYPE_TRANSFORM.clone(), "rotation", Box::new(Quaternion::from_euler_angles_zyx(&Vec3::new(0.0, self.counter, 0.0))));
-
                     let mut anim_weights = None;
                     if mesh_name == "player" {
                         let move_anim = MoveAnim::from_values(0.0, 0.0);
@@ -1251,9 +2055,10 @@ impl WorldMachine {
                     *renderer.meshes.get_mut(&*mesh_name).unwrap() = mesh;
                 } else {
                     // if not, add it to the list of things to load
-                    self.entities_wanting_to_load_things.push(i);
+                    self.entities_wanting_to_load_things.push(handle);
                 }
             }
+        }
             /*if let Some(terrain) = entity.get_component(COMPONENT_TYPE_TERRAIN.clone()) {
                 if let Some(name) = terrain.get_parameter("name") {
                     // get the string value of the mesh
@@ -1303,6 +2108,7 @@ impl WorldMachine {
                 }
             }
              */
+        for (_, entity) in self.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone()) {
             if let Some(player_component) = entity.get_component(COMPONENT_TYPE_PLAYER.clone()) {
                 if let Some(ignore) = self.ignore_this_entity {
                     if ignore == entity.uid {
@@ -1341,6 +2147,7 @@ impl WorldMachine {
                         continue;
                     }
                 };
+                let crouching = matches!(player_component.get_parameter("crouching").value, ParameterValue::Bool(true));
                 if let Some(mesh) = renderer.meshes.get("player").cloned() {
                     renderer.meshes.get_mut("player").unwrap().updated_animations_this_frame = false;
                     if let Some(shadow_mesh) = &renderer.meshes.get_mut("player").unwrap().shadow_mesh {
@@ -1354,7 +2161,8 @@ impl WorldMachine {
                     mesh.rotation = rotation;
                     mesh.scale = Vec3::new(1.0, 1.0, 1.0);
 
-                    let move_anim = MoveAnim::from_values(speed, strafe);
+                    let mut move_anim = MoveAnim::from_values(speed, strafe);
+                    move_anim.set_crouch(crouching);
 
                     mesh.render(renderer, Some(&texture), Some(move_anim.weights()), shadow_pass);
 
@@ -1364,13 +2172,62 @@ impl WorldMachine {
                 }
             }
         }
+
+        // npcs are animated the same way as players, from the speed the server says they're going at
+        for (_, entity) in self.world.entities.with_component(COMPONENT_TYPE_NPC.clone()) {
+            let npc = entity.get_component(COMPONENT_TYPE_NPC.clone()).unwrap();
+            let (mesh_name, texture_name) = match (&npc.get_parameter("mesh").value, &npc.get_parameter("texture").value) {
+                (ParameterValue::String(mesh), ParameterValue::String(texture)) => (mesh.clone(), texture.clone()),
+                _ => {
+                    error!("render: npc mesh or texture is not a string");
+                    continue;
+                }
+            };
+            let speed = match npc.get_parameter("speed").value {
+                ParameterValue::Float(v) => v,
+                _ => 0.0,
+            };
+            let strafe = match npc.get_parameter("strafe").value {
+                ParameterValue::Float(v) => v,
+                _ => 0.0,
+            };
+            if !matches!(renderer.load_mesh_if_not_already_loaded(&mesh_name), Ok(true)) || !matches!(renderer.load_texture_if_not_already_loaded(&texture_name), Ok(true)) {
+                continue;
+            }
+            let (mut mesh, texture) = match (renderer.meshes.get(&*mesh_name).cloned(), renderer.textures.get(&*texture_name).cloned()) {
+                (Some(mesh), Some(texture)) => (mesh, texture),
+                _ => continue,
+            };
+            renderer.meshes.get_mut(&*mesh_name).unwrap().updated_animations_this_frame = false;
+            if let Some(shadow_mesh) = &renderer.meshes.get_mut(&*mesh_name).unwrap().shadow_mesh {
+                shadow_mesh.lock().unwrap().updated_animations_this_frame = false;
+            }
+            let world_transform = self.world.entities.world_transform(entity.uid).unwrap_or_default();
+            let old_position = mesh.position;
+            let old_rotation = mesh.rotation;
+            mesh.position = world_transform.position + Vec3::new(0.0, -0.1, 0.0);
+            mesh.rotation = world_transform.rotation;
+            mesh.scale = world_transform.scale;
+
+            let move_anim = MoveAnim::from_values(speed, strafe);
+
+            mesh.render(renderer, Some(&texture), Some(move_anim.weights()), shadow_pass);
+
+            // keep the animation state, but not where this npc was
+            mesh.position = old_position;
+            mesh.rotation = old_rotation;
+            *renderer.meshes.get_mut(&*mesh_name).unwrap() = mesh;
+        }
     }
 
     pub fn handle_audio(&mut self, renderer: &ht_renderer, audio: &AudioBackend, scontext: &SoundContext) {
         audio.update(renderer.camera.get_position(), -renderer.camera.get_front(), renderer.camera.get_up(), scontext);
 
-        for index in self.entities_wanting_to_load_things.clone() {
-            let entity = &self.world.entities[index];
+        for handle in self.entities_wanting_to_load_things.clone() {
+            let entity = match self.world.entities.get(handle) {
+                Some(entity) => entity,
+                None => continue,
+            };
             let components = entity.get_components();
             for component in components {
                 match component.get_type() {
@@ -1395,67 +2252,46 @@ impl WorldMachine {
         // don't clear here because that's done later in rendering
 
 
-        for (i, entity) in self.world.entities.iter_mut().enumerate() {
+        // until we know what time the server thinks it is, we don't know where in the track to start
+        let now = match (self.is_server, self.server_clock_offset) {
+            (false, None) => return,
+            _ => self.server_time(),
+        };
+        for (handle, entity) in self.world.entities.with_component(COMPONENT_TYPE_JUKEBOX.clone()) {
             if let Some(jukebox) = entity.get_component(COMPONENT_TYPE_JUKEBOX.clone()) {
-                let track = jukebox.get_parameter("track");
-                let track = match track.value {
-                    ParameterValue::String(ref s) => s.clone(),
-                    _ => {
-                        error!("audio: jukebox track is not a string");
-                        continue;
-                    }
-                };
-                let volume = jukebox.get_parameter("volume");
-                let volume = match volume.value {
-                    ParameterValue::Float(v) => v,
-                    _ => {
-                        error!("audio: jukebox volume is not a float");
-                        continue;
-                    }
-                };
-                let playing = jukebox.get_parameter("playing");
-                let playing = match playing.value {
-                    ParameterValue::Bool(ref s) => s.clone(),
-                    _ => {
-                        error!("audio: jukebox playing is not a string");
-                        continue;
-                    }
-                };
-                let uuid = jukebox.get_parameter("uuid");
-                let uuid = match uuid.value {
-                    ParameterValue::String(ref s) => s.clone(),
-                    _ => {
-                        error!("audio: jukebox uuid is not a string");
+                let jukebox = match JukeboxState::from_component(jukebox) {
+                    Some(jukebox) => jukebox,
+                    None => {
+                        error!("audio: jukebox {} has a parameter of the wrong type", entity.uid);
                         continue;
                     }
                 };
-
-                let position = if let Some(transform) = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()) {
-                    let position = transform.get_parameter("position");
-                    let position = match position.value {
-                        ParameterValue::Vec3(v) => v,
-                        _ => {
-                            error!("audio: transform position is not a vec3");
-                            continue;
-                        }
-                    };
-                    position
-                } else {
-                    Vec3::new(0.0, 0.0, 0.0)
-                };
-
-                if audio.is_sound_loaded(&track) {
-                    if playing && !audio.is_sound_playing(&uuid) {
-                        audio.play_sound_with_uuid(&uuid, &track, scontext);
-                    } else if !playing && audio.is_sound_playing(&uuid) {
+                let uuid = jukebox.uuid.clone();
+                let position = self.world.entities.world_transform(entity.uid).unwrap_or_default().position;
+
+                if audio.is_sound_loaded(&jukebox.track) {
+                    // a different track or a seek means starting again from wherever it should be now
+                    let started = (jukebox.track.clone(), jukebox.started_at);
+                    let stale = self.jukeboxes_playing.get(&uuid).map_or(false, |playing| *playing != started);
+                    if audio.is_sound_playing(&uuid) && (!jukebox.playing || stale) {
                         audio.stop_sound_with_uuid(&uuid, scontext);
+                        self.jukeboxes_playing.remove(&uuid);
                     }
-                    if playing {
+                    if jukebox.playing && !audio.is_sound_playing(&uuid) {
+                        let length = jukebox::track_length(&self.game_data_path, &jukebox.track);
+                        let offset = jukebox.position_at(now, length);
+                        audio.play_sound_with_uuid(&uuid, &jukebox.track, offset, jukebox.playlist.is_empty(), scontext);
+                        self.jukeboxes_playing.insert(uuid.clone(), started);
+                    }
+                    if jukebox.playing {
                         audio.set_sound_position(&uuid, position, scontext);
+                        audio.set_sound_gain(&uuid, jukebox.volume as f32, scontext);
                     }
                 } else {
                     // if not, add it to the list of things to load
-                    self.entities_wanting_to_load_things.push(i);
+                    if !self.entities_wanting_to_load_things.contains(&handle) {
+                        self.entities_wanting_to_load_things.push(handle);
+                    }
                 }
             }
         }
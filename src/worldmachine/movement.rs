@@ -0,0 +1,265 @@
+use std::ops::Deref;
+use gfx_maths::Vec3;
+use serde::{Deserialize, Serialize};
+use crate::helpers;
+use crate::physics::{MovementMode, PhysicsCharacterController, SurfaceMaterial, PLAYER_GRAVITY, PLAYER_JUMP_VELOCITY, PLAYER_TERMINAL_VELOCITY};
+use crate::server::server_player::{DEFAULT_CLIMBSPEED, DEFAULT_CROUCH_HEIGHT, DEFAULT_CROUCHSPEED, DEFAULT_HEIGHT, DEFAULT_MOVESPEED, DEFAULT_RADIUS, DEFAULT_SPRINTSPEED, DEFAULT_STEPHEIGHT, DEFAULT_SWIMSPEED};
+use crate::worldmachine::{WorldMachine, WorldUpdate};
+
+/// how players move on the current map, read from base/maps/<map>/movement.yaml
+/// the server sends it to clients when they join and whenever it changes, so their prediction matches it
+/// anything left out of the file keeps its default
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovementSettings {
+    pub gravity: f32,
+    pub jump_velocity: f32,
+    pub terminal_velocity: f32,
+    pub move_speed: f32,
+    pub sprint_speed: f32,
+    pub crouch_speed: f32,
+    pub climb_speed: f32,
+    pub swim_speed: f32,
+    pub ground_acceleration: f32, // how quickly it gets up to speed, in multiples of that speed per second
+    pub air_acceleration: f32,
+    pub air_speed: f32, // the most it can speed itself up in the direction it's already going while in the air
+    pub friction: f32,
+    pub stop_speed: f32, // friction slows it down at least as hard as if it was going this fast, so it doesn't creep to a stop
+    pub radius: f32,
+    pub height: f32,
+    pub crouch_height: f32,
+    pub step_height: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            gravity: PLAYER_GRAVITY,
+            jump_velocity: PLAYER_JUMP_VELOCITY,
+            terminal_velocity: PLAYER_TERMINAL_VELOCITY,
+            move_speed: DEFAULT_MOVESPEED,
+            sprint_speed: DEFAULT_SPRINTSPEED,
+            crouch_speed: DEFAULT_CROUCHSPEED,
+            climb_speed: DEFAULT_CLIMBSPEED,
+            swim_speed: DEFAULT_SWIMSPEED,
+            ground_acceleration: 12.0,
+            air_acceleration: 10.0,
+            air_speed: 1.0,
+            friction: 6.0,
+            stop_speed: 2.5,
+            radius: DEFAULT_RADIUS,
+            height: DEFAULT_HEIGHT,
+            crouch_height: DEFAULT_CROUCH_HEIGHT,
+            step_height: DEFAULT_STEPHEIGHT,
+        }
+    }
+}
+
+impl MovementSettings {
+    /// the settings for a map, the defaults if it doesn't have a movement.yaml
+    pub fn load(game_data_path: &str, map_name: &str) -> Self {
+        let path = format!("{}/maps/{}/movement.yaml", game_data_path, map_name);
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(_) => return Self::default(),
+        };
+        match serde_yaml::from_reader::<_, Self>(file) {
+            Ok(settings) => {
+                debug!("loaded movement settings for {}: {:?}", map_name, settings);
+                settings.validated()
+            }
+            Err(e) => {
+                warn!("failed to parse {}: {:?}, using the default movement settings", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// changes one setting by name, returns false if there's no such setting
+    pub fn set(&mut self, name: &str, value: f32) -> bool {
+        let setting = match name {
+            "gravity" => &mut self.gravity,
+            "jump_velocity" => &mut self.jump_velocity,
+            "terminal_velocity" => &mut self.terminal_velocity,
+            "move_speed" => &mut self.move_speed,
+            "sprint_speed" => &mut self.sprint_speed,
+            "crouch_speed" => &mut self.crouch_speed,
+            "climb_speed" => &mut self.climb_speed,
+            "swim_speed" => &mut self.swim_speed,
+            "ground_acceleration" => &mut self.ground_acceleration,
+            "air_acceleration" => &mut self.air_acceleration,
+            "air_speed" => &mut self.air_speed,
+            "friction" => &mut self.friction,
+            "stop_speed" => &mut self.stop_speed,
+            "radius" => &mut self.radius,
+            "height" => &mut self.height,
+            "crouch_height" => &mut self.crouch_height,
+            "step_height" => &mut self.step_height,
+            _ => return false,
+        };
+        *setting = value;
+        *self = self.validated();
+        true
+    }
+
+    // physx won't make a capsule with no size, and a crouch has to be shorter than standing
+    fn validated(mut self) -> Self {
+        let defaults = Self::default();
+        if self.radius <= 0.0 || self.height <= 0.0 {
+            warn!("movement settings: the player capsule needs a size, using the default one");
+            self.radius = defaults.radius;
+            self.height = defaults.height;
+        }
+        if self.crouch_height <= 0.0 || self.crouch_height > self.height {
+            self.crouch_height = self.height;
+        }
+        self.step_height = self.step_height.max(0.0);
+        self.friction = self.friction.max(0.0);
+        self.stop_speed = self.stop_speed.max(0.0);
+        self.terminal_velocity = self.terminal_velocity.abs();
+        self
+    }
+
+    /// how fast a player can go moving like this
+    pub fn speed(&self, mode: MovementMode, crouching: bool, sprinting: bool) -> f32 {
+        match mode {
+            MovementMode::Climb => self.climb_speed,
+            MovementMode::Swim(_) => self.swim_speed,
+            MovementMode::Walk if crouching => self.crouch_speed,
+            MovementMode::Walk if sprinting => self.sprint_speed,
+            MovementMode::Walk => self.move_speed,
+        }
+    }
+
+    /// a player's horizontal velocity after `delta` seconds of wanting to go in `wish_direction` (up to 1 long)
+    /// the client predicts with this and the server checks with it, so they agree on where momentum takes a player
+    /// on the ground friction slows it down and it speeds up towards wherever it wants to go, how grippy the surface is scales both.
+    /// in the air there's no friction and it can only add a little speed in the direction it's already going,
+    /// so turning while strafing is what builds speed up. ladders and water count as ground
+    pub fn next_velocity(&self, velocity: Vec3, wish_direction: Vec3, mode: MovementMode, crouching: bool, sprinting: bool, controller: &PhysicsCharacterController, delta: f32) -> Vec3 {
+        let velocity = Vec3::new(velocity.x, 0.0, velocity.z);
+        let wish = helpers::clamp_magnitude(Vec3::new(wish_direction.x, 0.0, wish_direction.z), 1.0);
+        let wish_length = wish.magnitude();
+        let direction = if wish_length > 0.0 { *wish.normalize().deref() } else { Vec3::zero() };
+
+        let surface = controller.ground_material();
+        let speed_multiplier = surface.as_ref().map_or(1.0, |surface| surface.speed);
+        // ice is slippery compared to the default surface, mud is grippy
+        let traction = surface.as_ref().map_or(1.0, |surface| surface.dynamic_friction / SurfaceMaterial::default().dynamic_friction);
+        let wish_speed = self.speed(mode, crouching, sprinting) * speed_multiplier * wish_length;
+
+        if mode != MovementMode::Walk || controller.is_on_ground() {
+            let speed = velocity.magnitude();
+            let velocity = if speed > 0.0 {
+                let drop = speed.max(self.stop_speed) * self.friction * traction * delta;
+                velocity * ((speed - drop).max(0.0) / speed)
+            } else {
+                velocity
+            };
+            accelerate(velocity, direction, wish_speed, wish_speed, self.ground_acceleration * traction, delta)
+        } else {
+            accelerate(velocity, direction, wish_speed, wish_speed.min(self.air_speed), self.air_acceleration, delta)
+        }
+    }
+
+    /// makes a player's controller fall, jump and fit the way these say
+    pub fn apply(&self, controller: &PhysicsCharacterController) {
+        controller.set_gravity(self.gravity, self.jump_velocity, self.terminal_velocity);
+        controller.set_dimensions(self.radius, self.height, self.step_height);
+    }
+}
+
+// speeds up towards direction, but not past `limit` along it
+fn accelerate(velocity: Vec3, direction: Vec3, wish_speed: f32, limit: f32, acceleration: f32, delta: f32) -> Vec3 {
+    let add = limit - velocity.dot(direction);
+    if add <= 0.0 {
+        return velocity;
+    }
+    velocity + direction * (acceleration * wish_speed * delta).min(add)
+}
+
+impl WorldMachine {
+    pub fn movement_settings(&self) -> MovementSettings {
+        self.movement_settings
+    }
+
+    /// serverside this sends the new settings to every client, clientside it's what the server told us
+    pub fn set_movement_settings(&mut self, settings: MovementSettings) {
+        if settings == self.movement_settings {
+            return;
+        }
+        self.movement_settings = settings;
+        if self.is_server {
+            self.queue_updates(vec![WorldUpdate::SetMovementSettings(settings)]);
+        } else if let Some(player) = self.player.as_mut() {
+            player.player.set_movement_settings(settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::testing::{controller, controller_on_floor, idle, scene, STEP};
+
+    #[test]
+    fn settings_are_set_by_name_and_change_how_players_fall() {
+        let mut low_gravity = MovementSettings::default();
+        assert!(low_gravity.set("gravity", -4.0));
+        assert!(low_gravity.set("move_speed", 20.0));
+        assert!(!low_gravity.set("flying", 1.0));
+        assert_eq!(low_gravity.move_speed, 20.0);
+
+        // half a second of falling, about 3 normally but only 0.5 with a quarter of the gravity
+        let physics = scene();
+        let start = Vec3::new(0.0, 100.0, 0.0);
+        let mut controller = controller(&physics, start);
+        low_gravity.apply(&controller);
+        idle(&mut controller, 0.5);
+        let fallen = start.y - controller.get_foot_position().y;
+        assert!(fallen > 0.2 && fallen < 1.0, "fell {} in half a second", fallen);
+    }
+
+    #[test]
+    fn players_speed_up_slow_down_and_slide() {
+        let settings = MovementSettings::default();
+        // moves it like a client would for a while, returning the velocity it ends up with
+        let walk = |controller: &mut PhysicsCharacterController, velocity: Vec3, wish: Vec3, seconds: f32| -> Vec3 {
+            let mut velocity = velocity;
+            for _ in 0..(seconds / STEP).round() as usize {
+                velocity = settings.next_velocity(velocity, wish, MovementMode::Walk, false, false, controller, STEP);
+                controller.move_by(velocity * STEP, false, None, false, STEP, STEP);
+            }
+            velocity
+        };
+        let forwards = Vec3::new(1.0, 0.0, 0.0);
+
+        let physics = scene();
+        let (mut controller, _floor) = controller_on_floor(&physics, "");
+        let started = walk(&mut controller, Vec3::zero(), forwards, STEP);
+        assert!(started.x > 0.0 && started.x < settings.move_speed * 0.9, "got to {:?} straight away", started);
+        let running = walk(&mut controller, started, forwards, 1.0);
+        assert!((running.magnitude() - settings.move_speed).abs() < 0.1, "ran at {:?}", running);
+        let stopping = walk(&mut controller, running, Vec3::zero(), 0.1);
+        assert!(stopping.x > 0.0 && stopping.x < running.x, "stopped from {:?} to {:?}", running, stopping);
+        assert_eq!(walk(&mut controller, stopping, Vec3::zero(), 1.0), Vec3::zero());
+
+        // ice takes longer to get going on, and keeps it going once it is
+        let icy = scene();
+        let (mut skater, _ice) = controller_on_floor(&icy, "ice");
+        let skating = walk(&mut skater, Vec3::zero(), forwards, 1.0);
+        assert!(skating.magnitude() < running.magnitude() * 0.8, "got going on ice at {:?}", skating);
+        let sliding = walk(&mut skater, skating, Vec3::zero(), 0.5);
+        assert!(sliding.magnitude() > skating.magnitude() * 0.5, "stopped on ice from {:?} to {:?}", skating, sliding);
+
+        // in the air it can't speed up the way it's already going, but turning sideways adds a little
+        controller.set_foot_position(Vec3::new(0.0, 100.0, 0.0));
+        controller.move_by(Vec3::zero(), false, None, false, STEP, STEP);
+        assert!(!controller.is_on_ground());
+        let flying = Vec3::new(running.magnitude(), 0.0, 0.0);
+        assert_eq!(settings.next_velocity(flying, forwards, MovementMode::Walk, false, false, &controller, STEP), flying);
+        let strafed = settings.next_velocity(flying, Vec3::new(0.0, 0.0, 1.0), MovementMode::Walk, false, false, &controller, STEP);
+        assert_eq!(strafed.x, flying.x);
+        assert!(strafed.magnitude() > flying.magnitude(), "strafing in the air went from {:?} to {:?}", flying, strafed);
+    }
+}
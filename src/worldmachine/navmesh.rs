@@ -0,0 +1,508 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use gfx_maths::Vec3;
+use crate::helpers;
+use crate::server::server_player::{DEFAULT_HEIGHT, DEFAULT_RADIUS, DEFAULT_STEPHEIGHT};
+use crate::worldmachine::components::{COMPONENT_TYPE_BOX_COLLIDER, COMPONENT_TYPE_CAPSULE_COLLIDER, COMPONENT_TYPE_CONVEX_COLLIDER, COMPONENT_TYPE_MESH_COLLIDER, COMPONENT_TYPE_MESH_RENDERER, COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_SPHERE_COLLIDER};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::entity_store::EntityStore;
+
+const CELL_SIZE: f32 = 0.5;
+const MAX_SLOPE: f32 = 45.0; // degrees, anything steeper is a wall
+const MAX_CELLS: usize = 1024 * 1024; // maps bigger than this (about 500m across) don't get a navmesh
+const MAX_SNAP_CELLS: i32 = 8; // how far away from the navmesh a path query can start or end
+
+/// what the navmesh is built for, defaults to the player's character controller
+#[derive(Clone, Copy, Debug)]
+pub struct NavAgent {
+    pub radius: f32,
+    pub height: f32,
+    pub step_height: f32,
+}
+
+impl Default for NavAgent {
+    fn default() -> Self {
+        Self {
+            radius: DEFAULT_RADIUS,
+            height: DEFAULT_HEIGHT,
+            step_height: DEFAULT_STEPHEIGHT,
+        }
+    }
+}
+
+impl NavAgent {
+    // the controller is a capsule, so the cylinder height plus both caps
+    fn total_height(&self) -> f32 {
+        self.height + self.radius * 2.0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NavNode {
+    pub position: Vec3, // where the agent's feet would be
+    pub neighbours: Vec<usize>,
+}
+
+/// a walkable grid over the static geometry of a map, one node per cell per floor
+/// built from box colliders and the meshes of mesh renderers, players and snowballs aren't part of it
+#[derive(Clone, Debug)]
+pub struct NavMesh {
+    pub agent: NavAgent,
+    origin: Vec3, // the corner of cell (0, 0)
+    width: usize,
+    depth: usize,
+    pub nodes: Vec<NavNode>,
+    columns: Vec<Vec<usize>>, // node indices in each cell, x + z * width
+}
+
+// everything the navmesh is built from, in world space
+#[derive(Default)]
+struct Geometry {
+    triangles: Vec<[Vec3; 3]>,
+    boxes: Vec<(Vec3, Vec3)>, // min, max
+}
+
+// what's in a grid cell before it becomes nodes
+#[derive(Clone, Default)]
+struct Cell {
+    surfaces: Vec<f32>, // heights something could stand at
+    solids: Vec<(f32, f32)>, // height ranges something is in the way
+}
+
+#[derive(PartialEq)]
+struct OpenNode {
+    cost: f32,
+    node: usize,
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // backwards so that the binary heap pops the cheapest first
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Geometry {
+    fn collect(entities: &EntityStore, game_data_path: &str) -> Self {
+        let mut geometry = Geometry::default();
+        let mut meshes: HashMap<String, Option<Vec<[Vec3; 3]>>> = HashMap::new();
+        for (_, entity) in entities.iter() {
+            if entity.has_component(COMPONENT_TYPE_PLAYER.clone()) {
+                continue;
+            }
+            let world_transform = entities.world_transform(entity.uid).unwrap_or_default();
+            if let Some(box_collider) = entity.get_component(COMPONENT_TYPE_BOX_COLLIDER.clone()) {
+                // same as the physics, the position is the centre and the scale is the full size
+                if let (ParameterValue::Vec3(position), ParameterValue::Vec3(scale)) = (&box_collider.get_parameter("position").value, &box_collider.get_parameter("scale").value) {
+                    let centre = *position + world_transform.position;
+                    let half = *scale * world_transform.scale * 0.5;
+                    let half = Vec3::new(half.x.abs(), half.y.abs(), half.z.abs());
+                    geometry.boxes.push((centre - half, centre + half));
+                }
+            }
+            // round colliders are close enough to their bounding boxes for walking around
+            for (component_type, height) in [(COMPONENT_TYPE_SPHERE_COLLIDER.clone(), false), (COMPONENT_TYPE_CAPSULE_COLLIDER.clone(), true)] {
+                let collider = match entity.get_component(component_type) {
+                    Some(collider) => collider,
+                    None => continue,
+                };
+                if let (ParameterValue::Vec3(position), ParameterValue::Float(radius)) = (&collider.get_parameter("position").value, &collider.get_parameter("radius").value) {
+                    let centre = *position + world_transform.position;
+                    let scale = world_transform.scale;
+                    let radius = *radius as f32 * scale.x.abs().max(scale.z.abs());
+                    let half_height = match (height, &collider.get_parameter("height").value) {
+                        (true, ParameterValue::Float(height)) => radius + *height as f32 * scale.y.abs() / 2.0,
+                        _ => radius,
+                    };
+                    let half = Vec3::new(radius, half_height, radius);
+                    geometry.boxes.push((centre - half, centre + half));
+                }
+            }
+            // meshes that are drawn or collided with, an entity with both only needs them once
+            let mut mesh_names = Vec::new();
+            for component_type in [COMPONENT_TYPE_MESH_RENDERER.clone(), COMPONENT_TYPE_MESH_COLLIDER.clone(), COMPONENT_TYPE_CONVEX_COLLIDER.clone()] {
+                if let Some(ParameterValue::String(mesh_name)) = entity.get_component(component_type).map(|c| c.get_parameter("mesh").value.clone()) {
+                    if !mesh_names.contains(&mesh_name) {
+                        mesh_names.push(mesh_name);
+                    }
+                }
+            }
+            for mesh_name in mesh_names {
+                let triangles = meshes.entry(mesh_name.clone()).or_insert_with(|| load_triangles(game_data_path, &mesh_name));
+                if let Some(triangles) = triangles {
+                    for triangle in triangles.iter() {
+                        geometry.triangles.push(triangle.map(|vertex| world_transform.transform_point(vertex)));
+                    }
+                }
+            }
+        }
+        geometry
+    }
+
+    // min and max x/z of everything
+    fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let points = self.triangles.iter().flat_map(|triangle| triangle.iter().cloned())
+            .chain(self.boxes.iter().flat_map(|(min, max)| [*min, *max]));
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for point in points {
+            bounds = Some(match bounds {
+                None => (point, point),
+                Some((min, max)) => (
+                    Vec3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                    Vec3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+                ),
+            });
+        }
+        bounds
+    }
+}
+
+fn load_triangles(game_data_path: &str, mesh_name: &str) -> Option<Vec<[Vec3; 3]>> {
+    let (vertices, indices) = helpers::load_gltf_mesh(game_data_path, mesh_name)?;
+    Some(indices.chunks_exact(3).map(|t| [vertices[t[0] as usize], vertices[t[1] as usize], vertices[t[2] as usize]]).collect())
+}
+
+impl NavMesh {
+    /// builds a navmesh from every collider and mesh renderer in the world
+    pub fn build(entities: &EntityStore, game_data_path: &str, agent: NavAgent) -> Option<NavMesh> {
+        let geometry = Geometry::collect(entities, game_data_path);
+        let (min, max) = geometry.bounds()?;
+        let width = ((max.x - min.x) / CELL_SIZE).ceil() as usize + 1;
+        let depth = ((max.z - min.z) / CELL_SIZE).ceil() as usize + 1;
+        if width * depth > MAX_CELLS {
+            warn!("navmesh: map is too big ({}x{} cells)", width, depth);
+            return None;
+        }
+        let mut navmesh = NavMesh {
+            agent,
+            origin: Vec3::new(min.x, 0.0, min.z),
+            width,
+            depth,
+            nodes: Vec::new(),
+            columns: vec![Vec::new(); width * depth],
+        };
+        let cells = navmesh.rasterise(&geometry);
+        let candidates = navmesh.walkable_heights(&cells);
+        navmesh.erode_and_connect(candidates);
+        Some(navmesh)
+    }
+
+    fn cell_of(&self, position: Vec3) -> Option<(usize, usize)> {
+        let x = ((position.x - self.origin.x) / CELL_SIZE).floor();
+        let z = ((position.z - self.origin.z) / CELL_SIZE).floor();
+        if x < 0.0 || z < 0.0 || x as usize >= self.width || z as usize >= self.depth {
+            return None;
+        }
+        Some((x as usize, z as usize))
+    }
+
+    fn cell_centre(&self, x: usize, z: usize) -> (f32, f32) {
+        (self.origin.x + (x as f32 + 0.5) * CELL_SIZE, self.origin.z + (z as f32 + 0.5) * CELL_SIZE)
+    }
+
+    // the node in a cell closest to the given height, if it's within max_dy
+    fn node_at(&self, x: i32, z: i32, y: f32, max_dy: f32) -> Option<usize> {
+        if x < 0 || z < 0 || x as usize >= self.width || z as usize >= self.depth {
+            return None;
+        }
+        self.columns[x as usize + z as usize * self.width].iter()
+            .map(|node| (*node, (self.nodes[*node].position.y - y).abs()))
+            .filter(|(_, dy)| *dy <= max_dy)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(node, _)| node)
+    }
+
+    // works out where in each cell you could stand and what's in the way
+    fn rasterise(&self, geometry: &Geometry) -> Vec<Cell> {
+        let mut cells = vec![Cell::default(); self.width * self.depth];
+        let min_normal_y = MAX_SLOPE.to_radians().cos();
+
+        for (min, max) in &geometry.boxes {
+            let (x0, z0) = self.cell_of(*min).unwrap_or((0, 0));
+            let (x1, z1) = self.cell_of(*max).unwrap_or((self.width - 1, self.depth - 1));
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    let (cx, cz) = self.cell_centre(x, z);
+                    if cx < min.x || cx > max.x || cz < min.z || cz > max.z {
+                        continue;
+                    }
+                    let cell = &mut cells[x + z * self.width];
+                    cell.surfaces.push(max.y);
+                    cell.solids.push((min.y, max.y));
+                }
+            }
+        }
+
+        for triangle in &geometry.triangles {
+            let [a, b, c] = *triangle;
+            let normal = (b - a).cross(c - a);
+            let length = normal.magnitude();
+            if length < 1e-6 {
+                continue;
+            }
+            let walkable = (normal.y / length).abs() >= min_normal_y;
+
+            // the height of the triangle over a point, if the point is inside it
+            let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+            let height_at = |px: f32, pz: f32| -> Option<f32> {
+                if area.abs() < 1e-6 {
+                    return None;
+                }
+                let u = ((b.x - px) * (c.z - pz) - (c.x - px) * (b.z - pz)) / area;
+                let v = ((c.x - px) * (a.z - pz) - (a.x - px) * (c.z - pz)) / area;
+                let w = 1.0 - u - v;
+                if u < -1e-4 || v < -1e-4 || w < -1e-4 {
+                    return None;
+                }
+                Some(a.y * u + b.y * v + c.y * w)
+            };
+
+            let lower = Vec3::new(a.x.min(b.x).min(c.x), 0.0, a.z.min(b.z).min(c.z));
+            let upper = Vec3::new(a.x.max(b.x).max(c.x), 0.0, a.z.max(b.z).max(c.z));
+            let (x0, z0) = self.cell_of(lower).unwrap_or((0, 0));
+            let (x1, z1) = self.cell_of(upper).unwrap_or((self.width - 1, self.depth - 1));
+
+            // the height range this triangle covers in each cell
+            let mut spans: HashMap<usize, (f32, f32)> = HashMap::new();
+            let mut add_span = |index: usize, y: f32| {
+                let span = spans.entry(index).or_insert((y, y));
+                span.0 = span.0.min(y);
+                span.1 = span.1.max(y);
+            };
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    let (cx, cz) = self.cell_centre(x, z);
+                    if let Some(y) = height_at(cx, cz) {
+                        if walkable {
+                            cells[x + z * self.width].surfaces.push(y);
+                        }
+                        add_span(x + z * self.width, y);
+                    }
+                }
+            }
+            if !walkable {
+                // walls are thin from above, so walk their edges too or they'd slip between cell centres
+                for (from, to) in [(a, b), (b, c), (c, a)] {
+                    let steps = (helpers::flat_distance(from, to) / (CELL_SIZE * 0.25)).ceil().max(1.0) as usize;
+                    for i in 0..=steps {
+                        let point = from + (to - from) * (i as f32 / steps as f32);
+                        if let Some((x, z)) = self.cell_of(point) {
+                            add_span(x + z * self.width, point.y);
+                        }
+                    }
+                }
+            }
+            for (index, span) in spans {
+                cells[index].solids.push(span);
+            }
+        }
+        cells
+    }
+
+    // the heights in each cell an agent can stand at, with enough room above it
+    fn walkable_heights(&self, cells: &[Cell]) -> Vec<Vec<f32>> {
+        let total_height = self.agent.total_height();
+        cells.iter().map(|cell| {
+            let mut surfaces = cell.surfaces.clone();
+            surfaces.sort_by(|a, b| b.total_cmp(a));
+            let mut floors: Vec<f32> = Vec::new();
+            let mut heights = Vec::new();
+            for height in surfaces {
+                // anything just below a floor we already have is the same floor, you'd stand on the higher one
+                if floors.last().map_or(false, |last| *last - height <= self.agent.step_height) {
+                    continue;
+                }
+                floors.push(height);
+                let blocked = cell.solids.iter().any(|(lower, upper)| {
+                    *upper > height + self.agent.step_height && *lower < height + total_height
+                });
+                if !blocked {
+                    heights.push(height);
+                }
+            }
+            heights
+        }).collect()
+    }
+
+    // drops anything too close to an edge or wall for the agent to fit, then links up what's left
+    fn erode_and_connect(&mut self, candidates: Vec<Vec<f32>>) {
+        let radius_cells = (self.agent.radius / CELL_SIZE).ceil() as i32;
+        let max_slope = MAX_SLOPE.to_radians().tan();
+        let candidate_at = |x: i32, z: i32, y: f32, max_dy: f32| -> bool {
+            if x < 0 || z < 0 || x as usize >= self.width || z as usize >= self.depth {
+                return false;
+            }
+            candidates[x as usize + z as usize * self.width].iter().any(|height| (height - y).abs() <= max_dy)
+        };
+
+        let mut nodes = Vec::new();
+        let mut columns = vec![Vec::new(); self.width * self.depth];
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                for height in &candidates[x + z * self.width] {
+                    let mut fits = true;
+                    'around: for dz in -radius_cells..=radius_cells {
+                        for dx in -radius_cells..=radius_cells {
+                            let distance = ((dx * dx + dz * dz) as f32).sqrt() * CELL_SIZE;
+                            if distance > self.agent.radius {
+                                continue;
+                            }
+                            if !candidate_at(x as i32 + dx, z as i32 + dz, *height, self.agent.step_height + distance * max_slope) {
+                                fits = false;
+                                break 'around;
+                            }
+                        }
+                    }
+                    if fits {
+                        let (cx, cz) = self.cell_centre(x, z);
+                        columns[x + z * self.width].push(nodes.len());
+                        nodes.push(NavNode {
+                            position: Vec3::new(cx, *height, cz),
+                            neighbours: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        self.nodes = nodes;
+        self.columns = columns;
+
+        for z in 0..self.depth as i32 {
+            for x in 0..self.width as i32 {
+                for node in self.columns[x as usize + z as usize * self.width].clone() {
+                    let y = self.nodes[node].position.y;
+                    let mut neighbours = Vec::new();
+                    for (dx, dz) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                        let neighbour = match self.node_at(x + dx, z + dz, y, self.agent.step_height) {
+                            Some(neighbour) => neighbour,
+                            None => continue,
+                        };
+                        // no cutting corners
+                        if dx != 0 && dz != 0 && (self.node_at(x + dx, z, y, self.agent.step_height).is_none() || self.node_at(x, z + dz, y, self.agent.step_height).is_none()) {
+                            continue;
+                        }
+                        neighbours.push(neighbour);
+                    }
+                    self.nodes[node].neighbours = neighbours;
+                }
+            }
+        }
+    }
+
+    /// the node closest to a position, as long as it's near the navmesh
+    pub fn nearest_node(&self, position: Vec3) -> Option<usize> {
+        let x = ((position.x - self.origin.x) / CELL_SIZE).floor() as i32;
+        let z = ((position.z - self.origin.z) / CELL_SIZE).floor() as i32;
+        let mut best: Option<(usize, f32)> = None;
+        for ring in 0..=MAX_SNAP_CELLS {
+            for dz in -ring..=ring {
+                for dx in -ring..=ring {
+                    if dx.abs() != ring && dz.abs() != ring {
+                        continue;
+                    }
+                    let (cx, cz) = (x + dx, z + dz);
+                    if cx < 0 || cz < 0 || cx as usize >= self.width || cz as usize >= self.depth {
+                        continue;
+                    }
+                    for node in &self.columns[cx as usize + cz as usize * self.width] {
+                        let distance = helpers::distance(self.nodes[*node].position, position);
+                        if best.map_or(true, |(_, best)| distance < best) {
+                            best = Some((*node, distance));
+                        }
+                    }
+                }
+            }
+            // anything in the next ring is at least this far away
+            if let Some((node, distance)) = best {
+                if distance <= ring as f32 * CELL_SIZE {
+                    return Some(node);
+                }
+            }
+        }
+        best.map(|(node, _)| node)
+    }
+
+    /// a* from one node to another, the nodes along the way including both ends
+    pub fn find_node_path(&self, start: usize, goal: usize) -> Option<Vec<usize>> {
+        let goal_position = self.nodes.get(goal)?.position;
+        self.nodes.get(start)?;
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut cost: HashMap<usize, f32> = HashMap::new();
+        cost.insert(start, 0.0);
+        open.push(OpenNode { cost: helpers::distance(self.nodes[start].position, goal_position), node: start });
+        while let Some(OpenNode { node, .. }) = open.pop() {
+            if node == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while let Some(previous) = came_from.get(&current) {
+                    path.push(*previous);
+                    current = *previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let node_cost = cost[&node];
+            for neighbour in &self.nodes[node].neighbours {
+                let neighbour_cost = node_cost + helpers::distance(self.nodes[node].position, self.nodes[*neighbour].position);
+                if cost.get(neighbour).map_or(true, |old| neighbour_cost < *old) {
+                    cost.insert(*neighbour, neighbour_cost);
+                    came_from.insert(*neighbour, node);
+                    open.push(OpenNode { cost: neighbour_cost + helpers::distance(self.nodes[*neighbour].position, goal_position), node: *neighbour });
+                }
+            }
+        }
+        None
+    }
+
+    // whether you could walk straight from one point on the navmesh to another
+    fn can_walk_straight(&self, from: Vec3, to: Vec3) -> bool {
+        let steps = (helpers::flat_distance(from, to) / (CELL_SIZE * 0.5)).ceil().max(1.0) as usize;
+        let mut y = from.y;
+        for i in 1..=steps {
+            let point = from + (to - from) * (i as f32 / steps as f32);
+            let (x, z) = match self.cell_of(point) {
+                Some(cell) => cell,
+                None => return false,
+            };
+            match self.node_at(x as i32, z as i32, y, self.agent.step_height) {
+                Some(node) => y = self.nodes[node].position.y,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// waypoints to walk through to get from one place to another, not including where you start
+    /// the last one is the node closest to `to`, None if either end is off the navmesh or there's no way there
+    pub fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<Vec3>> {
+        let start = self.nearest_node(from)?;
+        let goal = self.nearest_node(to)?;
+        let path = self.find_node_path(start, goal)?;
+        let points = path.iter().map(|node| self.nodes[*node].position).collect::<Vec<Vec3>>();
+
+        // skip every node we can see past, so the path doesn't zigzag along the grid
+        let mut waypoints = Vec::new();
+        let mut anchor = 0;
+        while anchor < points.len() - 1 {
+            let mut next = anchor + 1;
+            while next + 1 < points.len() && self.can_walk_straight(points[anchor], points[next + 1]) {
+                next += 1;
+            }
+            waypoints.push(points[next]);
+            anchor = next;
+        }
+        if waypoints.is_empty() {
+            waypoints.push(points[0]);
+        }
+        Some(waypoints)
+    }
+}
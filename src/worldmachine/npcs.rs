@@ -0,0 +1,251 @@
+use std::ops::Deref;
+use gfx_maths::{Quaternion, Vec3};
+use crate::helpers;
+use crate::physics::{ActorOwner, Materials, PhysicsCharacterController, PhysicsSystem};
+use crate::server::server_player::{DEFAULT_HEIGHT, DEFAULT_RADIUS, DEFAULT_STEPHEIGHT};
+use crate::worldmachine::components::{COMPONENT_TYPE_NPC, COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_TRANSFORM};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::navmesh::NavMesh;
+use crate::worldmachine::{EntityId, WorldMachine};
+
+const IDLE_TIME: f32 = 2.0; // how long an npc stands around before heading to its next waypoint
+const ARRIVE_DISTANCE: f32 = 1.0;
+const WAYPOINT_DISTANCE: f32 = 0.5; // close enough to a navmesh waypoint to head for the next one
+const PATROL_TIMEOUT: f32 = 30.0; // give up on a waypoint that's taking this long to reach
+const FLEE_SAFE_MULTIPLIER: f32 = 1.5; // stops fleeing once everyone is this many flee_radiuses away
+const FLEE_SPEED_MULTIPLIER: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NpcState {
+    Idle,
+    Patrol,
+    Flee,
+}
+
+impl NpcState {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "idle" => Some(NpcState::Idle),
+            "patrol" => Some(NpcState::Patrol),
+            "flee" => Some(NpcState::Flee),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NpcState::Idle => "idle",
+            NpcState::Patrol => "patrol",
+            NpcState::Flee => "flee",
+        }
+    }
+}
+
+/// the serverside half of an npc, clients only see its Transform and Npc component
+pub struct NpcController {
+    controller: PhysicsCharacterController,
+    state: NpcState,
+    state_time: f32, // how long it's been in the current state
+    waypoints: Vec<Vec3>,
+    next_waypoint: usize,
+    path: Vec<Vec3>, // navmesh waypoints to the next patrol waypoint
+    rotation: Quaternion,
+}
+
+// what an npc did this tick, to be written back into its components
+struct NpcStep {
+    position: Vec3,
+    rotation: Quaternion,
+    speed: f64,
+    state: NpcState,
+}
+
+// "x y z; x y z; ...", platforms use these too
+pub(crate) fn parse_waypoints(entity_id: EntityId, waypoints: &str) -> Vec<Vec3> {
+    let mut parsed = Vec::new();
+    for waypoint in waypoints.split(';').map(|w| w.trim()).filter(|w| !w.is_empty()) {
+        let numbers = waypoint.split_whitespace().map(|n| n.parse::<f32>()).collect::<Result<Vec<f32>, _>>();
+        match numbers.as_deref() {
+            Ok([x, y, z]) => parsed.push(Vec3::new(*x, *y, *z)),
+            _ => warn!("entity {} has a bad waypoint: {:?}", entity_id, waypoint),
+        }
+    }
+    parsed
+}
+
+fn float_parameter(component: &Component, name: &str) -> f32 {
+    match component.get_parameter(name).value {
+        ParameterValue::Float(v) => v as f32,
+        _ => 0.0,
+    }
+}
+
+impl NpcController {
+    pub fn new(entity_id: EntityId, npc: &Component, position: Vec3, physics: &PhysicsSystem) -> Option<Self> {
+        let controller = physics.create_character_controller(DEFAULT_RADIUS, DEFAULT_HEIGHT, DEFAULT_STEPHEIGHT, Materials::Player)?;
+        controller.set_foot_position(position);
+        controller.set_owner(ActorOwner::Npc(entity_id));
+        let state = match npc.get_parameter("state").value {
+            ParameterValue::String(ref s) => NpcState::from_name(s).unwrap_or_else(|| {
+                warn!("npc {} has an unknown state {}, it'll idle", entity_id, s);
+                NpcState::Idle
+            }),
+            _ => NpcState::Idle,
+        };
+        let waypoints = match npc.get_parameter("waypoints").value {
+            ParameterValue::String(ref s) => parse_waypoints(entity_id, s),
+            _ => Vec::new(),
+        };
+        Some(Self {
+            controller,
+            state,
+            state_time: 0.0,
+            waypoints,
+            next_waypoint: 0,
+            path: Vec::new(),
+            rotation: Quaternion::identity(),
+        })
+    }
+
+    fn set_state(&mut self, state: NpcState) {
+        if state != self.state {
+            self.state = state;
+            self.state_time = 0.0;
+            self.path.clear();
+        }
+    }
+
+    fn think(&mut self, npc: &Component, players: &[Vec3], navmesh: Option<&NavMesh>, delta: f32) -> NpcStep {
+        let position = self.controller.get_foot_position();
+        let move_speed = float_parameter(npc, "move_speed");
+        let flee_radius = float_parameter(npc, "flee_radius");
+        self.state_time += delta;
+
+        let nearest = players.iter()
+            .map(|player| (*player, helpers::flat_distance(position, *player)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match nearest {
+            Some((_, distance)) if flee_radius > 0.0 && distance < flee_radius => self.set_state(NpcState::Flee),
+            _ if self.state == NpcState::Flee && nearest.map_or(true, |(_, distance)| distance > flee_radius * FLEE_SAFE_MULTIPLIER) => self.set_state(NpcState::Idle),
+            _ => {}
+        }
+        if self.state == NpcState::Idle && self.state_time > IDLE_TIME && !self.waypoints.is_empty() {
+            self.set_state(NpcState::Patrol);
+        }
+
+        let target = match self.state {
+            NpcState::Idle => None,
+            NpcState::Patrol => {
+                let waypoint = self.waypoints[self.next_waypoint % self.waypoints.len()];
+                if helpers::flat_distance(position, waypoint) < ARRIVE_DISTANCE || self.state_time > PATROL_TIMEOUT {
+                    // wait here for a bit, then on to the next one
+                    self.next_waypoint = (self.next_waypoint + 1) % self.waypoints.len();
+                    self.set_state(NpcState::Idle);
+                    None
+                } else {
+                    Some(self.steer(position, waypoint, navmesh))
+                }
+            }
+            NpcState::Flee => nearest.map(|(player, _)| {
+                let mut away = position - player;
+                away.y = 0.0;
+                if away == Vec3::zero() {
+                    away = Vec3::new(1.0, 0.0, 0.0);
+                }
+                position + *away.normalize().deref()
+            }),
+        };
+
+        let mut velocity = Vec3::zero();
+        if let Some(target) = target {
+            let mut flat = target - position;
+            flat.y = 0.0;
+            if flat != Vec3::zero() {
+                let speed = if self.state == NpcState::Flee { move_speed * FLEE_SPEED_MULTIPLIER } else { move_speed };
+                velocity = *flat.normalize().deref() * speed;
+                // same yaw convention as the camera, see bots
+                let yaw = -flat.x.atan2(flat.z).to_degrees();
+                self.rotation = Quaternion::from_euler_angles_zyx(&Vec3::new(0.0, yaw, 0.0));
+            }
+        }
+        self.controller.move_by(velocity * delta, false, None, false, delta, delta);
+
+        NpcStep {
+            position: self.controller.get_foot_position(),
+            rotation: self.rotation,
+            speed: if velocity == Vec3::zero() { 0.0 } else { 1.0 },
+            state: self.state,
+        }
+    }
+
+    // the next navmesh waypoint on the way to target, or target itself if there's no navmesh (or no way there)
+    fn steer(&mut self, position: Vec3, target: Vec3, navmesh: Option<&NavMesh>) -> Vec3 {
+        let navmesh = match navmesh {
+            Some(navmesh) => navmesh,
+            None => return target,
+        };
+        if self.path.is_empty() {
+            self.path = navmesh.find_path(position, target).unwrap_or_else(|| vec![target]);
+        }
+        while self.path.len() > 1 && helpers::flat_distance(position, self.path[0]) < WAYPOINT_DISTANCE {
+            self.path.remove(0);
+        }
+        self.path[0]
+    }
+}
+
+impl WorldMachine {
+    /// moves every npc along, serverside only
+    pub fn tick_npcs(&mut self, delta: f32) {
+        if !self.is_server || self.npcs.is_empty() || delta <= 0.0 {
+            return;
+        }
+        let players = self.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone())
+            .filter_map(|(_, entity)| match entity.get_component(COMPONENT_TYPE_PLAYER.clone())?.get_parameter("position").value {
+                ParameterValue::Vec3(position) => Some(position),
+                _ => None,
+            })
+            .collect::<Vec<Vec3>>();
+        let navmesh = self.navmesh.clone();
+
+        let mut steps = Vec::new();
+        for (entity_id, npc) in self.npcs.iter_mut() {
+            let component = match self.world.entities.get_by_id(*entity_id).and_then(|entity| entity.get_component(COMPONENT_TYPE_NPC.clone())) {
+                Some(component) => component,
+                None => continue,
+            };
+            steps.push((*entity_id, npc.think(component, &players, navmesh.as_deref(), delta)));
+        }
+
+        for (entity_id, step) in steps {
+            let entity = match self.world.entities.get_by_id(entity_id) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            let transform = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone());
+            let old_position = transform.map(|t| t.get_parameter("position").value.clone());
+            let old_rotation = transform.map(|t| t.get_parameter("rotation").value.clone());
+            let npc = entity.get_component(COMPONENT_TYPE_NPC.clone()).unwrap();
+            let old_speed = npc.get_parameter("speed").value.clone();
+            let old_state = npc.get_parameter("state").value.clone();
+
+            // only send what actually changed, standing npcs shouldn't cost anything
+            let position = ParameterValue::Vec3(step.position);
+            if old_position.as_ref() != Some(&position) {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "position", position);
+            }
+            let rotation = ParameterValue::Quaternion(step.rotation);
+            if old_rotation.as_ref() != Some(&rotation) {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "rotation", rotation);
+            }
+            let speed = ParameterValue::Float(step.speed);
+            if old_speed != speed {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_NPC.clone(), "speed", speed);
+            }
+            let state = ParameterValue::String(step.state.name().to_string());
+            if old_state != state {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_NPC.clone(), "state", state);
+            }
+        }
+    }
+}
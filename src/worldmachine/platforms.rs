@@ -0,0 +1,149 @@
+use gfx_maths::Vec3;
+use crate::animation::Animation;
+use crate::helpers;
+use crate::worldmachine::components::{COMPONENT_TYPE_PLATFORM, COMPONENT_TYPE_TRANSFORM};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::npcs::parse_waypoints;
+use crate::worldmachine::{EntityId, WorldMachine};
+
+/// the serverside half of a Platform component, clients only see its Transform and velocity
+/// platforms only move, they never turn, so anyone riding one doesn't need turning with it
+pub struct PlatformPath {
+    waypoints: Vec<Vec3>,
+    next_waypoint: usize,
+    leg: Option<Animation>, // None while it's waiting at a waypoint
+    time: f32, // how far into the current leg or wait it is, in seconds
+    position: Vec3,
+}
+
+fn float_parameter(component: &Component, name: &str) -> f32 {
+    match component.get_parameter(name).value {
+        ParameterValue::Float(v) => v as f32,
+        _ => 0.0,
+    }
+}
+
+impl PlatformPath {
+    pub fn new(entity_id: EntityId, platform: &Component, position: Vec3) -> Option<Self> {
+        let waypoints = match platform.get_parameter("waypoints").value {
+            ParameterValue::String(ref s) => parse_waypoints(entity_id, s),
+            _ => Vec::new(),
+        };
+        if waypoints.is_empty() {
+            warn!("platform {} has no waypoints, it'll stay where it is", entity_id);
+            return None;
+        }
+        Some(Self {
+            waypoints,
+            next_waypoint: 0,
+            leg: None,
+            time: 0.0,
+            position,
+        })
+    }
+
+    // moves along for another delta seconds, returning where it is and how fast it's going
+    fn advance(&mut self, speed: f32, wait: f32, delta: f32) -> (Vec3, Vec3) {
+        self.time += delta;
+        if self.leg.is_none() && self.time >= wait && speed > 0.0 {
+            let target = self.waypoints[self.next_waypoint];
+            // animations count in milliseconds
+            let duration = helpers::distance(self.position, target) / speed * 1000.0;
+            self.leg = Some(Animation::new(self.position, target, duration));
+            self.time = 0.0;
+        }
+        let leg = match self.leg.as_ref() {
+            Some(leg) => leg,
+            None => return (self.position, Vec3::zero()),
+        };
+        let duration = leg.time_to_animate / 1000.0;
+        if self.time >= duration {
+            // arrived, wait here for a bit, then on to the next one
+            self.position = leg.loc2;
+            self.leg = None;
+            self.time = 0.0;
+            self.next_waypoint = (self.next_waypoint + 1) % self.waypoints.len();
+            return (self.position, Vec3::zero());
+        }
+        self.position = leg.get_point_at_time(self.time as f64 * 1000.0);
+        (self.position, (leg.loc2 - leg.loc1) * (1.0 / duration))
+    }
+}
+
+impl WorldMachine {
+    /// serverside, moves every platform along its path
+    /// on both sides, tells physics how fast each one is going so it can carry whoever's standing on it
+    pub fn tick_platforms(&mut self, delta: f32) {
+        if self.is_server && delta > 0.0 {
+            let mut steps = Vec::new();
+            for (entity_id, path) in self.platforms.iter_mut() {
+                let platform = match self.world.entities.get_by_id(*entity_id).and_then(|entity| entity.get_component(COMPONENT_TYPE_PLATFORM.clone())) {
+                    Some(platform) => platform,
+                    None => continue,
+                };
+                let (position, velocity) = path.advance(float_parameter(platform, "speed"), float_parameter(platform, "wait"), delta);
+                steps.push((*entity_id, position, velocity));
+            }
+
+            for (entity_id, position, velocity) in steps {
+                let entity = match self.world.entities.get_by_id(entity_id) {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let old_position = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()).map(|t| t.get_parameter("position").value.clone());
+                let old_velocity = entity.get_component(COMPONENT_TYPE_PLATFORM.clone()).map(|p| p.get_parameter("velocity").value.clone());
+                // the velocity only changes at each waypoint, so that's all clients hear about it
+                let position = ParameterValue::Vec3(position);
+                if old_position.as_ref() != Some(&position) {
+                    self.set_component_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "position", position);
+                }
+                let velocity = ParameterValue::Vec3(velocity);
+                if old_velocity.as_ref() != Some(&velocity) {
+                    self.set_component_parameter(entity_id, COMPONENT_TYPE_PLATFORM.clone(), "velocity", velocity);
+                }
+            }
+        }
+
+        for (entity_id, rigid_body) in self.rigid_bodies.iter() {
+            let velocity = match self.world.entities.get_by_id(*entity_id).and_then(|entity| entity.get_component(COMPONENT_TYPE_PLATFORM.clone())) {
+                Some(platform) => match platform.get_parameter("velocity").value {
+                    ParameterValue::Vec3(velocity) => velocity,
+                    _ => Vec3::zero(),
+                },
+                None => continue,
+            };
+            rigid_body.set_carry_velocity(velocity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldmachine::components::Platform;
+
+    #[test]
+    fn platforms_follow_their_path_then_stop() {
+        let mut path = PlatformPath::new(1, &Platform::new("10 0 0", 5.0, 0.0), Vec3::zero()).unwrap();
+        let (position, velocity) = path.advance(5.0, 0.0, 0.05);
+        assert_eq!(position, Vec3::zero());
+        assert_eq!(velocity, Vec3::new(5.0, 0.0, 0.0));
+        for _ in 0..20 {
+            path.advance(5.0, 0.0, 0.05);
+        }
+        assert!((path.position.x - 5.0).abs() < 0.1, "platform only got to {:?}", path.position);
+
+        // with only one waypoint it stays there once it's arrived
+        for _ in 0..30 {
+            path.advance(5.0, 0.0, 0.05);
+        }
+        for _ in 0..10 {
+            assert_eq!(path.advance(5.0, 0.0, 0.05), (Vec3::new(10.0, 0.0, 0.0), Vec3::zero()));
+        }
+    }
+
+    #[test]
+    fn platforms_need_somewhere_to_go() {
+        assert!(PlatformPath::new(1, &Platform::new("", 5.0, 0.0), Vec3::zero()).is_none());
+    }
+}
@@ -7,15 +7,16 @@ use crate::camera::EYE_HEIGHT;
 use crate::helpers::lerp;
 use crate::keyboard::HTKey;
 use crate::mouse::MouseButtonState;
-use crate::physics::{ClimbingMode, Materials, PhysicsCharacterController, PhysicsSystem};
+use crate::physics::{ActorOwner, ClimbingMode, Materials, MovementMode, PhysicsCharacterController, PhysicsSystem};
 use crate::server::ConnectionUUID;
-use crate::server::server_player::{DEFAULT_HEIGHT, DEFAULT_MOVESPEED, DEFAULT_RADIUS, DEFAULT_SPRINTSPEED, DEFAULT_STEPHEIGHT};
 use crate::worldmachine::components::COMPONENT_TYPE_PLAYER;
 use crate::worldmachine::ecs::*;
+use crate::worldmachine::movement::MovementSettings;
 use crate::worldmachine::{ClientUpdate, EntityId, WorldMachine};
 
 pub const DEFAULT_FOV: f32 = 120.0;
 pub const SPRINT_FOV: f32 = 140.0;
+const FOOTSTEP_STRIDE: f32 = 2.0; // how far we walk between footsteps
 
 pub struct PlayerComponent {}
 
@@ -30,6 +31,7 @@ impl PlayerComponent {
         parameters.insert("rotation".to_string(), Parameter::new("rotation", ParameterValue::Quaternion(rotation)));
         parameters.insert("scale".to_string(), Parameter::new("scale", ParameterValue::Vec3(scale)));
         parameters.insert("sprinting".to_string(), Parameter::new("sprinting", ParameterValue::Bool(false)));
+        parameters.insert("crouching".to_string(), Parameter::new("crouching", ParameterValue::Bool(false)));
         parameters.insert("speed".to_string(), Parameter::new("speed", ParameterValue::Float(0.0)));
         parameters.insert("strafe".to_string(), Parameter::new("strafe", ParameterValue::Float(0.0)));
 
@@ -63,9 +65,11 @@ pub struct Player {
     pub speed: f64,
     pub strafe: f64,
     sprinting: bool,
+    crouching: bool, // wants to, it might not have room to stand back up
     last_mouse_pos: Option<Vec2>,
     physics_controller: Option<PhysicsCharacterController>,
-    movement_speed: f32,
+    movement_settings: MovementSettings, // whatever the server last sent
+    velocity: Vec3, // horizontal, see MovementSettings::next_velocity
     last_move_call: std::time::Instant,
     wasd: [bool; 4],
     jump: bool,
@@ -75,6 +79,7 @@ pub struct Player {
     was_moving: bool,
     bob_t: f32,
     bob_on: bool,
+    stride: f32, // how far we've walked since the last footstep
     pub has_camera_control: bool,
 }
 
@@ -92,9 +97,11 @@ impl Default for Player {
             speed: 0.0,
             strafe: 0.0,
             sprinting: false,
+            crouching: false,
             last_mouse_pos: None,
             physics_controller: None,
-            movement_speed: DEFAULT_MOVESPEED,
+            movement_settings: MovementSettings::default(),
+            velocity: Vec3::new(0.0, 0.0, 0.0),
             last_move_call: std::time::Instant::now(),
             wasd: [false; 4],
             jump: false,
@@ -104,6 +111,7 @@ impl Default for Player {
             was_moving: false,
             bob_t: 0.0,
             bob_on: true,
+            stride: 0.0,
             has_camera_control: true,
         }
     }
@@ -115,13 +123,19 @@ pub struct MovementInfo {
     pub sprinting: bool,
     pub speed: f32,
     pub strafe: f32,
+    pub crouching: bool,
+    pub wish_direction: Vec3, // where the keys say to go, the server works out the velocity from this the same way we do
 }
 
 impl Player {
     pub fn init(&mut self, physics_system: PhysicsSystem, uuid: String, name: String, position: Vec3, rotation: Quaternion, scale: Vec3) {
-        self.physics_controller = physics_system.create_character_controller(DEFAULT_RADIUS, DEFAULT_HEIGHT, DEFAULT_STEPHEIGHT, Materials::Player);
+        let settings = self.movement_settings;
+        self.physics_controller = physics_system.create_character_controller(settings.radius, settings.height, settings.step_height, Materials::Player);
         self.calculate_pitch_and_yaw_from_rotation(rotation);
-        if self.physics_controller.is_none() {
+        if let Some(controller) = &self.physics_controller {
+            settings.apply(controller);
+            controller.set_owner(ActorOwner::LocalPlayer);
+        } else {
             warn!("failed to create physics controller for player");
         }
         self.uuid = uuid;
@@ -201,15 +215,14 @@ impl Player {
         }
     }
 
-    fn handle_keyboard_movement(&mut self, renderer: &mut ht_renderer, jump: bool, frame_delta: f32) -> Option<(Vec3, MovementInfo)> {
+    fn handle_keyboard_movement(&mut self, renderer: &mut ht_renderer, jump: bool, mode: MovementMode, frame_delta: f32) -> Option<(Vec3, MovementInfo)> {
         let mut movement = Vec3::new(0.0, 0.0, 0.0);
         let camera = &mut renderer.camera;
         let camera_rotation = camera.get_rotation();
         let camera_forward = camera.get_forward_no_pitch();
         let camera_right = camera.get_right();
         let camera_up = camera.get_up();
-        let mut speed = self.movement_speed;
-        //let speed = 10.0; // uncomment to cheat!
+        let settings = self.movement_settings;
 
         let mut info = MovementInfo::default();
 
@@ -243,8 +256,27 @@ impl Player {
         if keyboard::check_key_released(HTKey::LeftShift) {
             self.sprinting = false;
         }
+        if keyboard::check_key_down(HTKey::LeftControl) {
+            self.crouching = true;
+        }
+        if keyboard::check_key_released(HTKey::LeftControl) {
+            self.crouching = false;
+        }
+
+        // ladders and water change how we move, and there's no crouching in either
+        let controller = self.physics_controller.as_ref().unwrap();
+        controller.set_movement_mode(mode);
+        if self.crouching && mode == MovementMode::Walk {
+            controller.crouch(settings.crouch_height);
+        } else if controller.is_crouching() {
+            // stays down until there's room to stand up
+            controller.stand();
+        }
+        info.crouching = controller.is_crouching();
+
         self.speed = 0.0;
         self.strafe = 0.0;
+        let mut climb = 0.0;
         if self.has_camera_control && self.locked_mouse {
             if self.wasd[0] {
                 self.speed = lerp(0.0, 1.0, 1.0) as f64;
@@ -262,11 +294,13 @@ impl Player {
                 self.strafe = lerp(0.0, 1.0, 1.0) as f64;
                 movement -= camera_right;
             }
-            if self.sprinting {
-                info.sprinting = false;
-                speed = DEFAULT_SPRINTSPEED;
-            } else {
-                info.sprinting = false;
+            // the server needs to know too, or it'll hold us to walking speed
+            info.sprinting = self.sprinting;
+            let speed = settings.speed(mode, info.crouching, info.sprinting);
+            //let speed = 10.0; // uncomment to cheat!
+            if mode == MovementMode::Climb {
+                // forwards and backwards go up and down the ladder
+                climb = self.speed as f32 * speed;
             }
             info.speed = self.speed as f32;
             info.strafe = self.strafe as f32;
@@ -277,16 +311,19 @@ impl Player {
             } else {
                 camera.set_fov(lerp(camera.get_fov(), DEFAULT_FOV, 0.1));
             }
-
-            movement *= speed;
         }
 
-        movement.y = 0.0;
+        // speeding up, slowing down and sliding about on ice all carry on between frames
+        info.wish_direction = movement;
+        self.velocity = settings.next_velocity(self.velocity, movement, mode, info.crouching, info.sprinting, controller, frame_delta);
+        let mut movement = self.velocity;
+        movement.y = climb;
         let now = std::time::Instant::now();
         let delta_time = now.duration_since(self.last_move_call).as_secs_f32();
         self.last_move_call = now;
         let dt_movement = movement * frame_delta;
         let final_movement = self.physics_controller.as_mut().unwrap().move_by(dt_movement, jump, None, false, delta_time, frame_delta);
+        self.footsteps(mode, final_movement);
         // uncomment next three lines for FLIGHT
         //let mut position = self.physics_controller.as_ref().unwrap().get_position();
         //position.y += 5.0;
@@ -295,7 +332,9 @@ impl Player {
         *crate::ui::DEBUG_LOCATION.lock().unwrap() = self.physics_controller.as_ref().unwrap().get_position();
 
         //camera.set_position_from_player_position(self.physics_controller.as_ref().unwrap().get_position());
-        if final_movement != Vec3::new(0.0, 0.0, 0.0) {
+        // riding a platform moves us without us moving, the server still needs to hear where we are
+        let carried = self.physics_controller.as_ref().unwrap().ground_velocity() != Vec3::zero();
+        if final_movement != Vec3::new(0.0, 0.0, 0.0) || carried {
             self.was_moving = true;
             Some((final_movement, info))
         } else if self.was_moving {
@@ -306,6 +345,25 @@ impl Player {
         }
     }
 
+    // every FOOTSTEP_STRIDE of walking on the ground, plays whatever the surface under us sounds like
+    fn footsteps(&mut self, mode: MovementMode, moved: Vec3) {
+        let controller = self.physics_controller.as_ref().unwrap();
+        if mode != MovementMode::Walk || !controller.is_on_ground() {
+            self.stride = 0.0;
+            return;
+        }
+        self.stride += Vec3::new(moved.x, 0.0, moved.z).magnitude();
+        if self.stride < FOOTSTEP_STRIDE {
+            return;
+        }
+        self.stride = 0.0;
+        if let Some(surface) = controller.ground_material() {
+            if !surface.footstep.is_empty() {
+                crate::audio::ONESHOTS.lock().unwrap().push((surface.footstep, controller.get_foot_position()));
+            }
+        }
+    }
+
     fn handle_jump(&mut self, renderer: &mut ht_renderer, delta_time: f32) -> bool {
         if keyboard::check_key_down(HTKey::Space) {
             return true;
@@ -313,7 +371,8 @@ impl Player {
         false
     }
 
-    pub fn handle_input(&mut self, renderer: &mut ht_renderer, delta_time: f32) -> Option<Vec<ClientUpdate>> {
+    /// mode is how the volume we're standing in (if any) wants us to move, see WorldMachine::movement_mode_at
+    pub fn handle_input(&mut self, renderer: &mut ht_renderer, mode: MovementMode, delta_time: f32) -> Option<Vec<ClientUpdate>> {
         if self.first_run {
             self.first_run = false;
             self.locked_mouse = true;
@@ -322,7 +381,7 @@ impl Player {
 
         let jump = self.handle_jump(renderer, delta_time);
         let look = self.handle_mouse_movement(renderer, delta_time);
-        let movement = self.handle_keyboard_movement(renderer, jump, delta_time);
+        let movement = self.handle_keyboard_movement(renderer, jump, mode, delta_time);
 
         // FOR DEBUGGING, REMOVE LATER
         if keyboard::check_key_released(HTKey::Comma) {
@@ -354,6 +413,10 @@ impl Player {
             if mouse::get_mouse_button_state(0) == MouseButtonState::Pressed {
                 updates.push(ClientUpdate::IThrewSnowball);
             }
+
+            if keyboard::check_key_pressed(HTKey::E) {
+                updates.push(ClientUpdate::IUsed);
+            }
         }
 
         // how much do we bob?
@@ -377,7 +440,7 @@ impl Player {
 
         // head bob
         if self.bob_on {
-            let initial_head = self.get_position() + Vec3::new(0.0, EYE_HEIGHT, 0.0);
+            let initial_head = self.get_position() + Vec3::new(0.0, self.eye_height(), 0.0);
             let bob = if bob_mag != 0.0 { initial_head + Vec3::new(0.0, 0.1 * ((self.bob_t  * 12.0).sin() * bob_mag), 0.0) } else if delta_time > 0.0 {
                 self.bob_t = 0.0;
                 initial_head
@@ -394,6 +457,27 @@ impl Player {
         }
     }
 
+    /// see MovementSettings, the server sends these when we join and whenever they change
+    pub fn set_movement_settings(&mut self, settings: MovementSettings) {
+        self.movement_settings = settings;
+        if let Some(controller) = self.physics_controller.as_ref() {
+            settings.apply(controller);
+        }
+    }
+
+    pub fn is_crouching(&self) -> bool {
+        self.physics_controller.as_ref().map_or(false, |controller| controller.is_crouching())
+    }
+
+    // the camera goes down with the capsule when crouching
+    fn eye_height(&self) -> f32 {
+        if self.is_crouching() {
+            EYE_HEIGHT - (self.movement_settings.height - self.movement_settings.crouch_height)
+        } else {
+            EYE_HEIGHT
+        }
+    }
+
     pub fn get_position(&mut self) -> Vec3 {
         let position = self.physics_controller.as_mut().unwrap().get_foot_position();
         self.position = position;
@@ -0,0 +1,158 @@
+use std::ops::Deref;
+use gfx_maths::Vec3;
+use crate::helpers;
+use crate::physics::{ColliderShape, PhysicsRigidBody, PhysicsSystem, RigidBodyDesc};
+use crate::server::server_player::{DEFAULT_HEIGHT, DEFAULT_RADIUS};
+use crate::worldmachine::components::{COMPONENT_TYPE_PLAYER, COMPONENT_TYPE_TRANSFORM};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::hierarchy::WorldTransform;
+use crate::worldmachine::{EntityId, WorldMachine};
+
+const PUSH_ACCELERATION: f32 = 40.0; // how hard players shove things they walk into
+const PUSH_REACH: f32 = 0.2; // how far past the edge of a player's capsule they can push from
+
+/// the physics half of a RigidBody component
+/// serverside it's simulated, clientside it's kinematic and follows the Transform the server sends
+pub struct RigidBody {
+    body: PhysicsRigidBody,
+    reach: f32, // roughly how far it sticks out sideways from its centre
+}
+
+impl RigidBody {
+    pub fn new(entity_id: EntityId, component: &Component, transform: &WorldTransform, kinematic: bool, game_data_path: &str, physics: &PhysicsSystem) -> Option<Self> {
+        let float = |name: &str| match component.get_parameter(name).value {
+            ParameterValue::Float(v) => v as f32,
+            _ => 0.0,
+        };
+        let scale = transform.scale;
+        let max_scale = scale.x.abs().max(scale.y.abs()).max(scale.z.abs());
+        let shape = match component.get_parameter("shape").value {
+            ParameterValue::String(ref shape) => shape.clone(),
+            _ => "box".to_string(),
+        };
+        let (shape, reach) = match shape.as_str() {
+            "box" => {
+                let size = match component.get_parameter("size").value {
+                    ParameterValue::Vec3(size) => size * scale,
+                    _ => scale,
+                };
+                (ColliderShape::Box(size), size.x.abs().max(size.z.abs()) / 2.0)
+            }
+            "sphere" => {
+                let radius = float("radius") * max_scale;
+                (ColliderShape::Sphere(radius), radius)
+            }
+            "capsule" => {
+                let radius = float("radius") * scale.x.abs().max(scale.z.abs());
+                (ColliderShape::Capsule(radius, float("height") * scale.y.abs()), radius)
+            }
+            "convex" => {
+                let mesh = match component.get_parameter("mesh").value {
+                    ParameterValue::String(ref mesh) => mesh.clone(),
+                    _ => String::new(),
+                };
+                let (vertices, _) = helpers::load_gltf_mesh(game_data_path, &mesh)?;
+                let reach = vertices.iter()
+                    .map(|vertex| helpers::flat_distance(Vec3::zero(), *vertex * scale))
+                    .fold(0.0, f32::max);
+                (ColliderShape::Convex(mesh, scale), reach)
+            }
+            shape => {
+                warn!("entity {} has a rigid body with an unknown shape {}", entity_id, shape);
+                return None;
+            }
+        };
+        let kinematic = kinematic || matches!(component.get_parameter("kinematic").value, ParameterValue::Bool(true));
+        let desc = RigidBodyDesc {
+            shape,
+            mass: float("mass"),
+            friction: float("friction"),
+            restitution: float("restitution"),
+            kinematic,
+        };
+        let body = physics.create_rigid_body(game_data_path, &desc, transform.position, transform.rotation)?;
+        body.set_owner(entity_id);
+        Some(Self {
+            body,
+            reach,
+        })
+    }
+
+    /// see PhysicsRigidBody::set_carry_velocity
+    pub fn set_carry_velocity(&self, velocity: Vec3) {
+        self.body.set_carry_velocity(velocity);
+    }
+}
+
+impl WorldMachine {
+    /// serverside, writes where every moving body ended up into its Transform and lets players shove them about
+    /// clientside, moves the kinematic copies to wherever the server last said they were
+    pub fn tick_rigid_bodies(&mut self, delta: f32) {
+        if self.rigid_bodies.is_empty() {
+            return;
+        }
+        if !self.is_server {
+            for (entity_id, rigid_body) in self.rigid_bodies.iter() {
+                if let Some(transform) = self.world.entities.world_transform(*entity_id) {
+                    rigid_body.body.set_kinematic_target(transform.position, transform.rotation);
+                }
+            }
+            return;
+        }
+        let players = self.world.entities.with_component(COMPONENT_TYPE_PLAYER.clone())
+            .filter_map(|(_, entity)| match entity.get_component(COMPONENT_TYPE_PLAYER.clone())?.get_parameter("position").value {
+                ParameterValue::Vec3(position) => Some(position),
+                _ => None,
+            })
+            .collect::<Vec<Vec3>>();
+
+        let mut moved = Vec::new();
+        for (entity_id, rigid_body) in self.rigid_bodies.iter() {
+            let body = &rigid_body.body;
+            if body.is_kinematic() {
+                // scripts move these by changing their Transform
+                if let Some(transform) = self.world.entities.world_transform(*entity_id) {
+                    body.set_kinematic_target(transform.position, transform.rotation);
+                }
+                continue;
+            }
+            let (position, rotation) = body.get_pose();
+            // character controllers don't push anything by themselves
+            for player in players.iter() {
+                let height = position.y - player.y;
+                if height < -rigid_body.reach || height > DEFAULT_HEIGHT + DEFAULT_RADIUS * 2.0 {
+                    continue;
+                }
+                if helpers::flat_distance(position, *player) > DEFAULT_RADIUS + rigid_body.reach + PUSH_REACH {
+                    continue;
+                }
+                let mut away = position - *player;
+                away.y = 0.0;
+                if away != Vec3::zero() {
+                    body.add_velocity(*away.normalize().deref() * PUSH_ACCELERATION * delta);
+                }
+            }
+            if !body.is_sleeping() {
+                moved.push((*entity_id, position, rotation));
+            }
+        }
+
+        for (entity_id, position, rotation) in moved {
+            let transform = match self.world.entities.get_by_id(entity_id).and_then(|entity| entity.get_component(COMPONENT_TYPE_TRANSFORM.clone())) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            let old_position = transform.get_parameter("position").value.clone();
+            let old_rotation = transform.get_parameter("rotation").value.clone();
+            // only send what actually changed, same as npcs
+            let position = ParameterValue::Vec3(position);
+            if old_position != position {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "position", position);
+            }
+            let rotation = ParameterValue::Quaternion(rotation);
+            if old_rotation != rotation {
+                self.set_component_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "rotation", rotation);
+            }
+        }
+    }
+}
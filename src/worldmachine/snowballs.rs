@@ -1,8 +1,18 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use gfx_maths::Vec3;
-use crate::physics::{Materials, PhysicsSphereColliderDynamic, PhysicsSystem};
+use crate::physics::{ActorOwner, ContactPhase, Materials, PhysicsSphereColliderDynamic, PhysicsSystem};
+use crate::worldmachine::WorldMachine;
+
+const SPLAT_SOUND: &str = "donk.wav";
+const LIFETIME: f32 = 20.0; // in case it never hits anything
+const THROW_GRACE: f32 = 0.2; // it can't splat on a player this soon after being thrown, so it doesn't hit whoever threw it
+
+// only used to tell snowballs apart in contact events, so it doesn't need to match between client and server
+static NEXT_SNOWBALL_ID: AtomicU64 = AtomicU64::new(0);
 
 pub struct Snowball {
     pub uuid: String,
+    pub id: u64,
     pub position: Vec3,
     pub initial_velocity: Vec3,
     pub time_to_live: f32,
@@ -12,29 +22,27 @@ pub struct Snowball {
 impl Snowball {
     pub fn new(position: Vec3, initial_velocity: Vec3, physics: &PhysicsSystem) -> Self {
         info!("creating snowball at {:?}", position);
-        let phys = physics.create_sphere_actor(position, 0.05, Materials::Player).unwrap();
-        phys.add_self_to_scene(physics.clone());
-        phys.set_velocity(initial_velocity);
-        Self {
-            uuid: uuid::Uuid::new_v4().to_string(),
-            position,
-            initial_velocity,
-            time_to_live: 20.0,
-            physics_object: phys,
-        }
+        Self::create(uuid::Uuid::new_v4().to_string(), position, initial_velocity, physics)
     }
     pub fn new_with_uuid(uuid: String, position: Vec3, initial_velocity: Vec3, physics: &PhysicsSystem) -> Self {
         info!("creating snowball (clientside) at {:?}", position);
         let mut oneshots = crate::audio::ONESHOTS.lock().unwrap();
         oneshots.push(("donk.wav".to_string(), position));
-        let phys = physics.create_sphere_actor(position, 0.05, Materials::Player).unwrap();
-        phys.add_self_to_scene(physics.clone());
+        Self::create(uuid, position, initial_velocity, physics)
+    }
+
+    fn create(uuid: String, position: Vec3, initial_velocity: Vec3, physics: &PhysicsSystem) -> Self {
+        let id = NEXT_SNOWBALL_ID.fetch_add(1, Ordering::Relaxed);
+        let phys = physics.create_sphere_actor(position, 0.05, Materials::surface("snow")).unwrap();
+        phys.set_owner(ActorOwner::Snowball(id));
+        phys.report_contacts();
         phys.set_velocity(initial_velocity);
         Self {
             uuid,
+            id,
             position,
             initial_velocity,
-            time_to_live: 20.0,
+            time_to_live: LIFETIME,
             physics_object: phys,
         }
     }
@@ -44,4 +52,46 @@ impl Snowball {
         self.position = position;
         position
     }
-}
\ No newline at end of file
+}
+
+impl WorldMachine {
+    /// splats snowballs that hit something in the last physics tick, and gets rid of any that have been flying for too long
+    /// serverside the map script hears about who got hit through on_snowball_hit(who), clientside the splat can be heard
+    pub fn tick_snowballs(&mut self, delta: f32) {
+        let events = match self.physics.lock().unwrap().as_ref() {
+            Some(physics) => physics.drain_contact_events(),
+            None => return,
+        };
+        let mut splats = Vec::new();
+        for event in events.iter().filter(|event| event.phase == ContactPhase::Begin) {
+            for snowball in self.snowballs.iter() {
+                if splats.iter().any(|(id, _, _)| *id == snowball.id) {
+                    continue;
+                }
+                let other = match event.other(ActorOwner::Snowball(snowball.id)) {
+                    Some(other) => other,
+                    None => continue,
+                };
+                let thrower = matches!(other, Some(ActorOwner::Player(_)) | Some(ActorOwner::LocalPlayer));
+                if thrower && LIFETIME - snowball.time_to_live < THROW_GRACE {
+                    continue;
+                }
+                splats.push((snowball.id, event.position, other));
+            }
+        }
+        for (id, position, other) in splats {
+            self.snowballs.retain(|snowball| snowball.id != id);
+            if self.is_server {
+                let who = other.and_then(|owner| owner.entity_id()).map(|id| id as i64).unwrap_or(-1);
+                self.run_script_hook("on_snowball_hit", (who,));
+            } else {
+                crate::audio::ONESHOTS.lock().unwrap().push((SPLAT_SOUND.to_string(), position));
+            }
+        }
+
+        for snowball in self.snowballs.iter_mut() {
+            snowball.time_to_live -= delta;
+        }
+        self.snowballs.retain(|snowball| snowball.time_to_live > 0.0);
+    }
+}
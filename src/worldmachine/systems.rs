@@ -0,0 +1,151 @@
+use gfx_maths::*;
+use crate::worldmachine::components::{COMPONENT_TYPE_SPINNER, COMPONENT_TYPE_TRANSFORM};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::EntityId;
+
+lazy_static! {
+    pub static ref SYSTEMS_INITIALISED: bool = {
+        register_systems();
+        true
+    };
+}
+
+pub fn register_systems() {
+    SystemDefinition::register(SystemDefinition {
+        name: "spin".to_string(),
+        reads: vec![COMPONENT_TYPE_SPINNER.clone()],
+        writes: vec![COMPONENT_TYPE_TRANSFORM.clone()],
+        // the server spins things and clients follow its Transform, so everyone sees the same angle
+        side: SystemSide::Server,
+        run: spin,
+    });
+}
+
+#[derive(Clone)]
+pub struct ScheduledSystem {
+    pub definition: SystemDefinition,
+    pub affected_entities: Vec<EntityId>,
+}
+
+/// resolves the systems a map references and puts them in the order they should run in
+/// a system that writes a component another system reads runs before it, otherwise the map's order is kept
+pub fn build_schedule(systems: &[System]) -> Vec<ScheduledSystem> {
+    let mut resolved = Vec::new();
+    for system in systems {
+        if let Some(definition) = SystemDefinition::get(&system.name) {
+            resolved.push(ScheduledSystem {
+                definition,
+                affected_entities: system.affected_entities.clone(),
+            });
+        } else {
+            warn!("map references unknown system: {}", system.name);
+        }
+    }
+
+    let depends_on = |a: &ScheduledSystem, b: &ScheduledSystem| -> bool {
+        // does a need b to run first?
+        a.definition.name != b.definition.name && b.definition.writes.iter().any(|w| a.definition.reads.contains(w))
+    };
+
+    let mut remaining: Vec<usize> = (0..resolved.len()).collect();
+    let mut order = Vec::new();
+    while !remaining.is_empty() {
+        let next = remaining.iter().position(|&i| {
+            !remaining.iter().any(|&j| j != i && depends_on(&resolved[i], &resolved[j]))
+        });
+        let next = match next {
+            Some(next) => next,
+            None => {
+                warn!("systems have a read/write cycle, running the rest in map order");
+                0
+            }
+        };
+        order.push(remaining.remove(next));
+    }
+
+    order.into_iter().map(|i| resolved[i].clone()).collect()
+}
+
+fn spin(ctx: &mut SystemContext) {
+    for handle in ctx.matching_entities() {
+        let entity = ctx.entities.get(handle).unwrap();
+        let spinner = entity.get_component(COMPONENT_TYPE_SPINNER.clone()).unwrap();
+        let speed = match spinner.get_parameter("speed").value {
+            ParameterValue::Float(v) => v as f32,
+            _ => {
+                error!("spin: speed is not a float");
+                continue;
+            }
+        };
+        let axis = match spinner.get_parameter("axis").value {
+            ParameterValue::Vec3(v) => v,
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        };
+        let transform = entity.get_component(COMPONENT_TYPE_TRANSFORM.clone()).unwrap();
+        let rotation = match transform.get_parameter("rotation").value {
+            ParameterValue::Quaternion(v) => v,
+            _ => {
+                error!("spin: transform rotation is not a quaternion");
+                continue;
+            }
+        };
+        let rotation = rotation * Quaternion::from_euler_angles_zyx(&(axis * (speed * ctx.delta)));
+        let entity_id = entity.uid;
+        ctx.set_parameter(entity_id, COMPONENT_TYPE_TRANSFORM.clone(), "rotation", ParameterValue::Quaternion(rotation));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldmachine::components::COMPONENT_TYPE_LIGHT;
+
+    fn nothing(_: &mut SystemContext) {}
+
+    fn define(name: &str, reads: Vec<ComponentType>, writes: Vec<ComponentType>) -> System {
+        SystemDefinition::register(SystemDefinition {
+            name: name.to_string(),
+            reads,
+            writes,
+            side: SystemSide::Both,
+            run: nothing,
+        });
+        System {
+            name: name.to_string(),
+            uid: 0,
+            affected_entities: Vec::new(),
+        }
+    }
+
+    fn names(schedule: &[ScheduledSystem]) -> Vec<&str> {
+        schedule.iter().map(|scheduled| scheduled.definition.name.as_str()).collect()
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let reader = define("test reads transform", vec![COMPONENT_TYPE_TRANSFORM.clone()], vec![]);
+        let unrelated = define("test reads light", vec![COMPONENT_TYPE_LIGHT.clone()], vec![]);
+        let writer = define("test writes transform", vec![COMPONENT_TYPE_SPINNER.clone()], vec![COMPONENT_TYPE_TRANSFORM.clone()]);
+        let missing = System {
+            name: "test not registered".to_string(),
+            uid: 0,
+            affected_entities: Vec::new(),
+        };
+
+        let schedule = build_schedule(&[reader, unrelated, missing, writer]);
+        assert_eq!(names(&schedule), vec!["test reads light", "test writes transform", "test reads transform"]);
+    }
+
+    #[test]
+    fn cycles_fall_back_to_map_order() {
+        let first = define("test cycle first", vec![COMPONENT_TYPE_TRANSFORM.clone()], vec![COMPONENT_TYPE_SPINNER.clone()]);
+        let second = define("test cycle second", vec![COMPONENT_TYPE_SPINNER.clone()], vec![COMPONENT_TYPE_TRANSFORM.clone()]);
+        let after = define("test after cycle", vec![COMPONENT_TYPE_SPINNER.clone()], vec![]);
+
+        let schedule = build_schedule(&[first.clone(), second.clone(), after]);
+        assert_eq!(names(&schedule), vec!["test cycle first", "test cycle second", "test after cycle"]);
+
+        let schedule = build_schedule(&[second, first]);
+        assert_eq!(names(&schedule), vec!["test cycle second", "test cycle first"]);
+    }
+}
@@ -0,0 +1,120 @@
+use gfx_maths::Vec3;
+use crate::physics::MovementMode;
+use crate::worldmachine::components::{COMPONENT_TYPE_LADDER, COMPONENT_TYPE_WATER};
+use crate::worldmachine::ecs::*;
+use crate::worldmachine::hierarchy::WorldTransform;
+use crate::worldmachine::WorldMachine;
+
+const SWIM_DEPTH: f32 = 1.5; // how deep the water has to be over a player's feet before they swim, anything shallower is wading
+
+/// a box from a Ladder or Water component that changes how players move inside it
+/// these are checked by position rather than with physx triggers, so the client and server always agree on them
+#[derive(Clone, Copy, Debug)]
+pub struct MovementVolume {
+    min: Vec3,
+    max: Vec3,
+    mode: MovementMode,
+}
+
+impl MovementVolume {
+    /// the volumes for an entity's Ladder and Water components
+    pub fn from_entity(entity: &Entity, world_transform: &WorldTransform) -> Vec<Self> {
+        let mut volumes = Vec::new();
+        if let Some(ladder) = entity.get_component(COMPONENT_TYPE_LADDER.clone()) {
+            volumes.push(Self::new(ladder, world_transform, MovementMode::Climb));
+        }
+        if let Some(water) = entity.get_component(COMPONENT_TYPE_WATER.clone()) {
+            let buoyancy = match water.get_parameter("buoyancy").value {
+                ParameterValue::Float(v) => v as f32,
+                _ => 1.0,
+            };
+            volumes.push(Self::new(water, world_transform, MovementMode::Swim(buoyancy)));
+        }
+        volumes
+    }
+
+    fn new(component: &Component, world_transform: &WorldTransform, mode: MovementMode) -> Self {
+        let position = match component.get_parameter("position").value {
+            ParameterValue::Vec3(position) => world_transform.transform_point(position),
+            _ => world_transform.position,
+        };
+        // the corner follows the hierarchy, but the box itself stays lined up with the world
+        let size = match component.get_parameter("size").value {
+            ParameterValue::Vec3(size) => size * world_transform.scale,
+            _ => world_transform.scale,
+        };
+        // same corner as triggers, the box goes up in x and y but back in z
+        let corner = position + Vec3::new(size.x, size.y, -size.z);
+        Self {
+            min: Vec3::new(position.x.min(corner.x), position.y.min(corner.y), position.z.min(corner.z)),
+            max: Vec3::new(position.x.max(corner.x), position.y.max(corner.y), position.z.max(corner.z)),
+            mode,
+        }
+    }
+
+    // margin widens the box sideways
+    fn contains(&self, point: Vec3, margin: f32) -> bool {
+        point.x >= self.min.x - margin && point.x <= self.max.x + margin
+            && point.y >= self.min.y && point.y <= self.max.y
+            && point.z >= self.min.z - margin && point.z <= self.max.z + margin
+    }
+
+    /// is a player with its feet at `position` and this radius affected by this?
+    /// ladders only need to be touched, water has to be deep enough to swim in
+    fn affects(&self, position: Vec3, radius: f32) -> bool {
+        match self.mode {
+            MovementMode::Climb => self.contains(position, radius),
+            MovementMode::Swim(_) => self.contains(position + Vec3::new(0.0, SWIM_DEPTH, 0.0), 0.0),
+            MovementMode::Walk => false,
+        }
+    }
+}
+
+impl WorldMachine {
+    /// how a player with its feet at `position` should be moving
+    /// ladders win over water, so you can climb out of it
+    pub fn movement_mode_at(&self, position: Vec3) -> MovementMode {
+        let mut mode = MovementMode::Walk;
+        let radius = self.movement_settings.radius;
+        for volume in self.movement_volumes.values().flatten().filter(|volume| volume.affects(position, radius)) {
+            match volume.mode {
+                MovementMode::Climb => return MovementMode::Climb,
+                swim => mode = swim,
+            }
+        }
+        mode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldmachine::EntityId;
+    use crate::worldmachine::components::{Ladder, Water};
+
+    fn add_volume(worldmachine: &mut WorldMachine, entity_id: EntityId, position: Vec3, component: Component) {
+        let mut entity = Entity::new("volume");
+        entity.add_component(component);
+        let world_transform = WorldTransform { position, ..Default::default() };
+        worldmachine.movement_volumes.insert(entity_id, MovementVolume::from_entity(&entity, &world_transform));
+    }
+
+    #[test]
+    fn volumes_switch_how_players_move() {
+        let mut worldmachine = WorldMachine::default();
+        let ladder = Vec3::new(50.0, 0.0, 0.0);
+        let pool = Vec3::new(-50.0, 0.0, 0.0);
+        add_volume(&mut worldmachine, 1, ladder, Ladder::new(Vec3::zero(), Vec3::new(1.0, 6.0, 1.0)));
+        add_volume(&mut worldmachine, 2, pool, Water::new(Vec3::zero(), Vec3::new(10.0, 3.0, 10.0), 1.2));
+        // close enough to reach it counts as touching
+        assert_eq!(worldmachine.movement_mode_at(ladder + Vec3::new(-1.0, 2.0, -0.5)), MovementMode::Climb);
+        assert_eq!(worldmachine.movement_mode_at(ladder + Vec3::new(-3.0, 2.0, -0.5)), MovementMode::Walk);
+        assert_eq!(worldmachine.movement_mode_at(pool + Vec3::new(5.0, 0.5, -5.0)), MovementMode::Swim(1.2));
+        // wading, the water only comes up to its knees
+        assert_eq!(worldmachine.movement_mode_at(pool + Vec3::new(5.0, 2.0, -5.0)), MovementMode::Walk);
+
+        // a ladder in the water is how you get out of it
+        add_volume(&mut worldmachine, 3, pool + Vec3::new(8.0, 0.0, -8.0), Ladder::new(Vec3::zero(), Vec3::new(1.0, 6.0, 1.0)));
+        assert_eq!(worldmachine.movement_mode_at(pool + Vec3::new(8.5, 0.5, -8.5)), MovementMode::Climb);
+    }
+}